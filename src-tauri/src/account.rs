@@ -1,47 +1,56 @@
 use isideload::{
-    anisette::remote_v3::RemoteV3AnisetteProvider,
     auth::apple_account::AppleAccount,
     dev::{
         app_ids::{AppIdsApi, ListAppIdsResponse},
         certificates::{CertificatesApi, DevelopmentCertificate},
         developer_session::DeveloperSession,
+        teams::{DeveloperTeam, TeamsApi},
     },
-    sideload::{SideloaderBuilder, builder::MaxCertsBehavior, sideloader::Sideloader},
-    util::{fs_storage::FsStorage, keyring_storage::KeyringStorage, storage::SideloadingStorage},
+    sideload::{
+        SideloaderBuilder, TeamSelection, builder::MaxCertsBehavior, sideloader::Sideloader,
+    },
+    util::storage::SideloadingStorage,
 };
 use keyring::Entry;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use std::{path::PathBuf, sync::OnceLock, time::Duration};
-use tauri::{AppHandle, Emitter, Listener, Manager, State, Window};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, OnceLock},
+    time::Duration,
+};
+use tauri::{AppHandle, Emitter, Manager, State, Window};
 use tauri_plugin_store::StoreExt;
-use tracing::{debug, warn};
+use tracing::{debug, info, warn};
 
-use crate::sideload::{SideloaderGuard, SideloaderMutex};
+use crate::{
+    anisette, certificate,
+    encrypted_storage::EncryptedFsStorage,
+    error::IloaderError,
+    max_certs::{self, MaxCertsPolicy},
+    prompt::{self, PromptError, PromptTimeouts},
+    sideload::{SessionInfo, SessionRegistry},
+    storage_migration::IndexedKeyringStorage,
+    two_factor::{AccountLogin, TwoFactorRequest, TwoFactorResponse, login_with_two_factor},
+};
 
 #[tauri::command]
 pub async fn login_new(
     handle: AppHandle,
     window: Window,
-    sideloader_state: State<'_, SideloaderMutex>,
+    sessions: State<'_, SessionRegistry>,
     email: String,
     password: String,
     anisette_server: String,
     save_credentials: bool,
-) -> Result<(), String> {
-    let account = login(&handle, &window, &email, &password, anisette_server).await?;
-    let mut sideloader_guard = sideloader_state.lock().unwrap();
-    *sideloader_guard = Some(account);
+) -> Result<(), IloaderError> {
+    let account = login_window(&handle, &window, &email, &password, anisette_server, false).await?;
+    sessions.insert(account);
 
     if save_credentials {
-        let pass_entry = Entry::new("iloader", &email)
-            .map_err(|e| format!("Failed to create keyring entry for credentials: {:?}.", e))?;
-        pass_entry
-            .set_password(&password)
-            .map_err(|e| format!("Failed to save credentials to keyring: {:?}", e))?;
-        let store = handle
-            .store("data.json")
-            .map_err(|e| format!("Failed to get store: {:?}", e))?;
+        save_password(&email, &password)?;
+        let store = handle.store("data.json").map_err(IloaderError::Store)?;
         let mut existing_ids = store
             .get("ids")
             .unwrap_or_else(|| Value::Array(vec![]))
@@ -63,30 +72,21 @@ pub async fn login_stored(
     window: Window,
     email: String,
     anisette_server: String,
-    sideloader_state: State<'_, SideloaderMutex>,
-) -> Result<(), String> {
-    let pass_entry = Entry::new("iloader", &email)
-        .map_err(|e| format!("Failed to create keyring entry for credentials: {:?}.", e))?;
-    let password = pass_entry
-        .get_password()
-        .map_err(|e| format!("Failed to get credentials: {:?}", e))?;
-    let account = login(&handle, &window, &email, &password, anisette_server).await?;
-    let mut sideloader_guard = sideloader_state.lock().unwrap();
-    *sideloader_guard = Some(account);
+    sessions: State<'_, SessionRegistry>,
+) -> Result<(), IloaderError> {
+    let password = stored_password(&email)?;
+    let account = login_window(&handle, &window, &email, &password, anisette_server, true).await?;
+    sessions.insert(account);
 
     Ok(())
 }
 
 #[tauri::command]
-pub fn delete_account(handle: AppHandle, email: String) -> Result<(), String> {
-    let pass_entry = Entry::new("iloader", &email)
-        .map_err(|e| format!("Failed to create keyring entry for credentials: {:?}.", e))?;
-    pass_entry
-        .delete_credential()
-        .map_err(|e| format!("Failed to delete credentials: {:?}", e))?;
-    let store = handle
-        .store("data.json")
-        .map_err(|e| format!("Failed to get store: {:?}", e))?;
+// Also cleans up accounts that are only half saved, so the password failing to delete doesn't
+// stop the rest from being removed
+pub fn delete_account(handle: AppHandle, email: String) -> Result<(), IloaderError> {
+    let password_deleted = delete_password(&email);
+    let store = handle.store("data.json").map_err(IloaderError::Store)?;
     let mut existing_ids = store
         .get("ids")
         .unwrap_or_else(|| Value::Array(vec![]))
@@ -95,152 +95,731 @@ pub fn delete_account(handle: AppHandle, email: String) -> Result<(), String> {
         .unwrap_or_else(std::vec::Vec::new);
     existing_ids.retain(|v| v.as_str().is_none_or(|s| s != email));
     store.set("ids", Value::Array(existing_ids));
-    Ok(())
+    for key in ["teams", "machineNames"] {
+        if let Some(mut map) = store.get(key).filter(Value::is_object) {
+            map.as_object_mut().unwrap().remove(&email.to_lowercase());
+            store.set(key, map);
+        }
+    }
+    if let Ok(data_dir) = handle.path().app_data_dir() {
+        forget_session_tokens(&data_dir, &email);
+        if let Err(e) = anisette::reset_state(&data_dir, &email) {
+            warn!("Unable to delete anisette state: {}", e.details());
+        }
+    }
+    password_deleted
+}
+
+// The active session, which commands use when they aren't given an email
+#[tauri::command]
+pub fn logged_in_as(sessions: State<'_, SessionRegistry>) -> Option<String> {
+    sessions.active_email()
+}
+
+#[tauri::command]
+pub fn list_sessions(sessions: State<'_, SessionRegistry>) -> Vec<SessionInfo> {
+    sessions.sessions()
 }
 
 #[tauri::command]
-pub fn logged_in_as(sideloader_state: State<'_, SideloaderMutex>) -> Option<String> {
-    let sideloader_guard = sideloader_state.lock().unwrap();
-    if let Some(account) = &*sideloader_guard {
-        return Some(account.get_email().to_string());
+pub fn activate_session(
+    sessions: State<'_, SessionRegistry>,
+    email: String,
+) -> Result<(), IloaderError> {
+    sessions.activate(&email)
+}
+
+// Signs out of the given session, or the active one. Another session becomes active if there is one
+#[tauri::command]
+pub fn invalidate_account(
+    sessions: State<'_, SessionRegistry>,
+    email: Option<String>,
+) -> Result<(), IloaderError> {
+    sessions.remove(email.as_deref());
+    Ok(())
+}
+
+pub fn save_password(email: &str, password: &str) -> Result<(), IloaderError> {
+    Entry::new("iloader", email)
+        .and_then(|entry| entry.set_password(password))
+        .map_err(|source| IloaderError::Keyring {
+            account: email.to_string(),
+            source,
+        })
+}
+
+// Succeeds when there is no password to delete
+pub fn delete_password(email: &str) -> Result<(), IloaderError> {
+    match Entry::new("iloader", email).and_then(|entry| entry.delete_credential()) {
+        Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+        Err(source) => Err(IloaderError::Keyring {
+            account: email.to_string(),
+            source,
+        }),
     }
-    None
+}
+
+pub fn stored_password(email: &str) -> Result<String, IloaderError> {
+    Entry::new("iloader", email)
+        .and_then(|entry| entry.get_password())
+        .map_err(|source| IloaderError::Keyring {
+            account: email.to_string(),
+            source,
+        })
+}
+
+// How login asks the user for input: through the frontend, on a terminal, or scripted in tests
+pub trait LoginPrompter: Send + Sync {
+    fn two_factor(&self, request: &TwoFactorRequest) -> Result<TwoFactorResponse, PromptError>;
+
+    // Serial numbers of the certificates to revoke
+    fn certificates_to_revoke(&self, certs: &[CertificateInfo])
+    -> Result<Vec<String>, PromptError>;
+
+    // Only asked when the account belongs to more than one team and none was remembered
+    fn select_team(&self, teams: &[TeamInfo]) -> Result<String, PromptError>;
+}
+
+struct WindowPrompter {
+    window: Window,
+    timeouts: PromptTimeouts,
+}
+
+impl LoginPrompter for WindowPrompter {
+    fn two_factor(&self, request: &TwoFactorRequest) -> Result<TwoFactorResponse, PromptError> {
+        let response = prompt::ask(
+            &self.window,
+            "2fa-required",
+            request.clone(),
+            "2fa-recieved",
+            self.timeouts.two_factor,
+        )?;
+        TwoFactorResponse::parse(&response).ok_or(PromptError::Cancelled)
+    }
+
+    fn certificates_to_revoke(
+        &self,
+        certs: &[CertificateInfo],
+    ) -> Result<Vec<String>, PromptError> {
+        let response = prompt::ask(
+            &self.window,
+            "max-certs-reached",
+            certs.to_vec(),
+            "max-certs-response",
+            self.timeouts.selection,
+        )?;
+        serde_json::from_str::<Option<Vec<String>>>(&response)
+            .ok()
+            .flatten()
+            .ok_or(PromptError::Cancelled)
+    }
+
+    fn select_team(&self, teams: &[TeamInfo]) -> Result<String, PromptError> {
+        let response = prompt::ask(
+            &self.window,
+            "team-selection-required",
+            teams.to_vec(),
+            "team-selected",
+            self.timeouts.selection,
+        )?;
+        serde_json::from_str::<Option<String>>(&response)
+            .ok()
+            .flatten()
+            .ok_or(PromptError::Cancelled)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum LoginMethod {
+    // The tokens saved by the last login were accepted, so no password or 2FA was needed
+    Resumed,
+    Password,
+}
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct LoginMethodPayload<'a> {
+    email: &'a str,
+    method: LoginMethod,
+    // Why the saved tokens couldn't be used, if there were any
+    resume_error: Option<&'a IloaderError>,
+    anisette_server: &'a str,
+}
+
+// New certificates are registered under this name, so each computer's certificates can be told
+// apart. Used until a name is set for the account
+pub fn default_machine_name() -> String {
+    let hostname = gethostname::gethostname().to_string_lossy().to_string();
+    match hostname.split('.').next().map(str::trim) {
+        Some(host) if !host.is_empty() => format!("iloader-{}", host),
+        _ => "iloader".to_string(),
+    }
+}
+
+// The account's machine name from the `machineNames` object in data.json
+pub fn machine_name(machine_names: Option<&Value>, email: &str) -> String {
+    machine_names
+        .and_then(|names| names.get(email.to_lowercase())?.as_str())
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(str::to_string)
+        .unwrap_or_else(default_machine_name)
+}
+
+pub(crate) fn stored_machine_name(app: &AppHandle, email: &str) -> String {
+    let names = app
+        .store("data.json")
+        .ok()
+        .and_then(|store| store.get("machineNames"));
+    machine_name(names.as_ref(), email)
 }
 
 #[tauri::command]
-pub fn invalidate_account(sideloader_state: State<'_, SideloaderMutex>) {
-    let mut sideloader_guard = sideloader_state.lock().unwrap();
-    *sideloader_guard = None;
+pub fn get_machine_name(handle: AppHandle, email: String) -> String {
+    stored_machine_name(&handle, &email)
 }
 
+// An empty name goes back to the default. Takes effect on the next login
 #[tauri::command]
-pub fn reset_anisette_state() -> Result<(), String> {
-    let state_entry = Entry::new("iloader", "anisette_state")
-        .map_err(|e| format!("Failed to create keyring entry for anisette: {:?}.", e))?;
-    state_entry
-        .delete_credential()
-        .map_err(|e| format!("Failed to delete anisette state: {:?}", e))?;
+pub fn set_machine_name(
+    handle: AppHandle,
+    email: String,
+    machine_name: String,
+) -> Result<String, IloaderError> {
+    save_machine_name(&handle, &email, &machine_name)?;
+    Ok(stored_machine_name(&handle, &email))
+}
 
+pub(crate) fn save_machine_name(
+    app: &AppHandle,
+    email: &str,
+    machine_name: &str,
+) -> Result<(), IloaderError> {
+    let store = app.store("data.json").map_err(IloaderError::Store)?;
+    let mut names = store
+        .get("machineNames")
+        .filter(Value::is_object)
+        .unwrap_or_else(|| Value::Object(Default::default()));
+    let machine_name = machine_name.trim();
+    if machine_name.is_empty() {
+        names.as_object_mut().unwrap().remove(&email.to_lowercase());
+    } else {
+        names.as_object_mut().unwrap().insert(
+            email.to_lowercase(),
+            Value::String(machine_name.to_string()),
+        );
+    }
+    store.set("machineNames", names);
     Ok(())
 }
 
-async fn login(
+// Logs in with prompts shown in the frontend, remembering the chosen team for next time
+async fn login_window(
     app: &AppHandle,
     window: &Window,
     email: &str,
     password: &str,
     anisette_server: String,
-) -> Result<Sideloader, String> {
-    let window_clone = window.clone();
-    let tfa_closure = move || -> Option<String> {
-        window_clone
-            .emit("2fa-required", ())
-            .expect("Failed to emit 2fa-required event");
-
-        let (tx, rx) = std::sync::mpsc::channel::<String>();
-        let handler_id = window_clone.listen("2fa-recieved", move |event| {
-            let code = event.payload();
-            let _ = tx.send(code.to_string());
-        });
-
-        let result = rx.recv_timeout(Duration::from_secs(120));
-        window_clone.unlisten(handler_id);
+    resume: bool,
+) -> Result<Sideloader, IloaderError> {
+    let data_dir = app
+        .path()
+        .app_data_dir()
+        .unwrap_or_else(|_| PathBuf::from("Failed to get app data directory"));
 
-        match result {
-            Ok(code) => {
-                let code = code.trim_matches('"').to_string();
-                Some(code)
-            }
-            Err(_) => None,
-        }
-    };
+    let store = app.store("data.json").map_err(IloaderError::Store)?;
+    let remembered_team = store.get("teams").and_then(|teams| {
+        teams
+            .get(email.to_lowercase())?
+            .as_str()
+            .map(str::to_string)
+    });
 
-    let anisette_url = if !anisette_server.starts_with("http") {
-        format!("https://{}", anisette_server)
-    } else {
-        anisette_server
-    };
+    let result = login(
+        LoginRequest {
+            data_dir: &data_dir,
+            email,
+            password,
+            anisette_server,
+            remembered_team: remembered_team.as_deref(),
+            resume,
+            max_certs_policy: MaxCertsPolicy::from_preferences(app),
+            machine_name: stored_machine_name(app, email),
+        },
+        Arc::new(WindowPrompter {
+            window: window.clone(),
+            timeouts: PromptTimeouts::from_preferences(app),
+        }),
+    )
+    .await?;
 
-    let (anisette_storage, sideloader_storage): (
-        Box<dyn SideloadingStorage>,
-        Box<dyn SideloadingStorage>,
-    ) = if keyring_available() {
-        (
-            Box::new(KeyringStorage::new("iloader".to_string())),
-            Box::new(KeyringStorage::new("iloader".to_string())),
+    window
+        .emit(
+            "login-method",
+            LoginMethodPayload {
+                email,
+                method: result.method,
+                resume_error: result.resume_error.as_ref(),
+                anisette_server: &result.anisette_server,
+            },
         )
-    } else {
-        warn!("Keyring storage is not available, falling back to file storage (less secure)");
-        let data_dir = app
-            .path()
-            .app_data_dir()
-            .unwrap_or_else(|_| PathBuf::from("Failed to get app data directory"));
-        (
-            Box::new(FsStorage::new(data_dir.clone())),
-            Box::new(FsStorage::new(data_dir)),
-        )
-    };
+        .map_err(IloaderError::Emit)?;
+
+    let mut teams = store
+        .get("teams")
+        .filter(Value::is_object)
+        .unwrap_or_else(|| Value::Object(Default::default()));
+    teams
+        .as_object_mut()
+        .unwrap()
+        .insert(email.to_lowercase(), Value::String(result.team.team_id));
+    store.set("teams", teams);
+    store.set(
+        "lastAnisetteServer",
+        Value::String(result.anisette_server.clone()),
+    );
 
-    let mut account = AppleAccount::builder(&email.to_lowercase())
-        .anisette_provider(
-            RemoteV3AnisetteProvider::default()
-                .set_serial_number("0".to_string())
-                .set_storage(anisette_storage)
-                .set_url(&anisette_url),
+    Ok(result.sideloader)
+}
+
+pub struct LoginRequest<'a> {
+    pub data_dir: &'a Path,
+    pub email: &'a str,
+    pub password: &'a str,
+    // The preferred anisette server. The known servers are tried if it is down
+    pub anisette_server: String,
+    // Team picked on a previous login, used without asking if the account still belongs to it
+    pub remembered_team: Option<&'a str>,
+    // Try the tokens saved by the last login before signing in with the password
+    pub resume: bool,
+    // What to revoke when the team has no room for another certificate
+    pub max_certs_policy: MaxCertsPolicy,
+    // Name new certificates are registered under
+    pub machine_name: String,
+}
+
+pub struct LoginResult {
+    pub sideloader: Sideloader,
+    // The team that was used, so the choice can be remembered
+    pub team: TeamInfo,
+    pub method: LoginMethod,
+    pub resume_error: Option<IloaderError>,
+    // The anisette server that worked
+    pub anisette_server: String,
+}
+
+pub async fn login(
+    request: LoginRequest<'_>,
+    prompter: Arc<dyn LoginPrompter>,
+) -> Result<LoginResult, IloaderError> {
+    let email = request.email.to_lowercase();
+    let anisette_servers = anisette::login_order(&request.anisette_server).await;
+    let token_storage = storage(request.data_dir);
+
+    let mut resume_error = None;
+    let mut resumed = None;
+    if request.resume {
+        match resume_session(
+            request.data_dir,
+            &email,
+            &anisette_servers,
+            token_storage.as_ref(),
         )
-        .login(password, tfa_closure)
         .await
-        .map_err(|e| e.to_string())?;
+        {
+            Ok(session) => resumed = session,
+            Err(e) => {
+                if !tokens_rejected(&e).await {
+                    warn!(
+                        "Unable to resume the saved session, keeping it for next time: {}",
+                        e.details()
+                    );
+                    return Err(e);
+                }
+                warn!(
+                    "Saved session was rejected, logging in with password: {}",
+                    e.details()
+                );
+                forget_session_tokens(request.data_dir, &email);
+                resume_error = Some(e);
+            }
+        }
+    }
 
-    debug!("Logged in");
+    let (method, (dev_session, teams), anisette_server) = match resumed {
+        Some((session, anisette_server)) => (LoginMethod::Resumed, session, anisette_server),
+        None => {
+            let (mut account, anisette_server) =
+                build_account(request.data_dir, &email, &anisette_servers).await?;
+            password_login(&mut account, &request, prompter.clone()).await?;
+            save_session_tokens(&account, &email, token_storage.as_ref());
+            (
+                LoginMethod::Password,
+                developer_session(&mut account).await?,
+                anisette_server,
+            )
+        }
+    };
+    info!(
+        "Logged in ({:?}) using anisette server {}",
+        method, anisette_server
+    );
 
-    let dev_session = DeveloperSession::from_account(&mut account)
-        .await
-        .map_err(|e| e.to_string())?;
+    let mut team_infos: Vec<TeamInfo> = teams.iter().map(TeamInfo::from).collect();
+    let index = choose_team(&team_infos, request.remembered_team, prompter.clone()).await?;
+    let team = teams
+        .into_iter()
+        .nth(index)
+        .ok_or(IloaderError::NoTeamSelected)?;
 
-    debug!("Created developer session");
+    debug!("Selected team {}", team_infos[index].team_id);
 
     let max_certs_callback = {
-        let window_clone = window.clone();
+        let prompter = prompter.clone();
+        let policy = request.max_certs_policy.clone();
+        let email = email.clone();
+        let machine_name = request.machine_name.clone();
+        let data_dir = request.data_dir.to_path_buf();
         move |certs: &Vec<DevelopmentCertificate>| -> Option<Vec<String>> {
-            let cert_infos: Vec<CertificateInfo> = certs
-                .iter()
-                .map(|cert| CertificateInfo {
-                    name: cert.name.clone(),
-                    certificate_id: cert.certificate_id.clone(),
-                    serial_number: cert.serial_number.clone(),
-                    machine_name: cert.machine_name.clone(),
-                    machine_id: cert.machine_id.clone(),
-                })
-                .collect();
-            window_clone
-                .emit("max-certs-reached", cert_infos)
-                .expect("Failed to emit max-certs-reached event");
-
-            let (tx, rx) = std::sync::mpsc::channel::<Option<Vec<String>>>();
-            let handler_id = window_clone.listen("max-certs-response", move |event| {
-                let certs = event.payload();
-                let certs = serde_json::from_str::<Option<Vec<String>>>(certs).unwrap_or(None);
-                let _ = tx.send(certs);
-            });
-
-            let result = rx.recv_timeout(Duration::from_secs(300));
-            window_clone.unlisten(handler_id);
-            result.unwrap_or(None)
+            let cert_infos = certificate_infos(
+                certs,
+                certificate::stored_serial(&data_dir, &email, certs).as_deref(),
+                Some(&machine_name),
+                CERTIFICATE_WARN_DAYS,
+            );
+            policy
+                .choose(&cert_infos, prompter.as_ref())
+                .inspect_err(|refusal| max_certs::record_refusal(&email, *refusal))
+                .ok()
         }
     };
 
-    // TODO: Team Selection
-
-    let sideloader = SideloaderBuilder::new(dev_session, email.to_lowercase())
-        .machine_name("iloader".to_string())
-        .storage(sideloader_storage)
+    remember_selected_team(&team_infos, &team.team_id);
+    let sideloader = SideloaderBuilder::new(dev_session, email)
+        .machine_name(request.machine_name.clone())
+        .team_selection(TeamSelection::PromptOnce(selected_team))
+        .storage(storage(request.data_dir))
         .max_certs_behavior(MaxCertsBehavior::Prompt(Box::new(max_certs_callback)))
         .build();
 
     debug!("Built sideloader");
 
-    Ok(sideloader)
+    Ok(LoginResult {
+        sideloader,
+        team: team_infos.swap_remove(index),
+        method,
+        resume_error,
+        anisette_server,
+    })
+}
+
+pub(crate) fn storage(data_dir: &Path) -> Box<dyn SideloadingStorage> {
+    if keyring_available() {
+        Box::new(IndexedKeyringStorage::new(data_dir, "iloader".to_string()))
+    } else {
+        Box::new(EncryptedFsStorage::new(data_dir, data_dir.to_path_buf()))
+    }
+}
+
+// Building the account only fetches the anisette client info and Apple's URL bag. The anisette
+// data is provisioned when it's first asked for, so that's done here too, before any credentials
+// are sent. Trying the next server therefore never retries a wrong password
+async fn build_account(
+    data_dir: &Path,
+    email: &str,
+    anisette_servers: &[String],
+) -> Result<(AppleAccount, String), IloaderError> {
+    let mut errors = vec![];
+    for server in anisette_servers {
+        let result = match AppleAccount::builder(email)
+            .anisette_provider(anisette::provider(data_dir, email, server))
+            .build()
+            .await
+        {
+            Ok(mut account) => {
+                let grandslam = account.grandslam_client.clone();
+                account
+                    .anisette_generator
+                    .get_anisette_data(grandslam)
+                    .await
+                    .map(|_| account)
+            }
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(account) => return Ok((account, server.clone())),
+            // Every server needs Apple too, so there's no point trying the others
+            Err(e) if !apple_reachable().await => {
+                return Err(IloaderError::AppleUnreachable {
+                    report: e.to_string(),
+                });
+            }
+            Err(e) => {
+                warn!(
+                    "Anisette server {} failed, trying the next one: {}",
+                    server, e
+                );
+                errors.push(format!("{}: {}", server, e));
+            }
+        }
+    }
+    Err(IloaderError::AnisetteUnavailable {
+        report: errors.join("\n"),
+    })
+}
+
+async fn password_login(
+    account: &mut AppleAccount,
+    request: &LoginRequest<'_>,
+    prompter: Arc<dyn LoginPrompter>,
+) -> Result<(), IloaderError> {
+    let mut login = AccountLogin {
+        account,
+        email: request.email,
+        password: request.password,
+    };
+    login_with_two_factor(&mut login, request.email, prompter).await
+}
+
+// Listing the teams is the first request that uses the developer services token, so a resumed
+// session whose tokens have expired fails here
+async fn developer_session(
+    account: &mut AppleAccount,
+) -> Result<(DeveloperSession, Vec<DeveloperTeam>), IloaderError> {
+    let mut dev_session = DeveloperSession::from_account(account).await.map_err(|e| {
+        IloaderError::DeveloperServices {
+            action: "create developer session",
+            report: e.to_string(),
+        }
+    })?;
+
+    debug!("Created developer session");
+
+    let teams = dev_session
+        .list_teams()
+        .await
+        .map_err(|e| IloaderError::DeveloperServices {
+            action: "list teams",
+            report: e.to_string(),
+        })?;
+    Ok((dev_session, teams))
+}
+
+pub(crate) fn session_tokens_key(email: &str) -> String {
+    format!("session_tokens_{}", email.to_lowercase())
+}
+
+// The GSA session data (ADSID, IDMS token and per-service tokens) as an XML plist
+fn save_session_tokens(account: &AppleAccount, email: &str, storage: &dyn SideloadingStorage) {
+    let Some(spd) = &account.spd else {
+        return;
+    };
+    let mut xml = Vec::new();
+    if let Err(e) = plist::to_writer_xml(&mut xml, spd) {
+        warn!("Unable to serialize session tokens: {}", e);
+        return;
+    }
+    if let Err(e) = storage.store(&session_tokens_key(email), &String::from_utf8_lossy(&xml)) {
+        warn!("Unable to save session tokens: {}", e);
+    }
+}
+
+pub fn forget_session_tokens(data_dir: &Path, email: &str) {
+    if let Err(e) = storage(data_dir).delete(&session_tokens_key(email)) {
+        warn!("Unable to delete saved session tokens: {}", e);
+    }
+}
+
+// Ok(None) when there are no saved tokens
+async fn resume_session(
+    data_dir: &Path,
+    email: &str,
+    anisette_servers: &[String],
+    storage: &dyn SideloadingStorage,
+) -> Result<Option<((DeveloperSession, Vec<DeveloperTeam>), String)>, IloaderError> {
+    let Ok(Some(xml)) = storage.retrieve(&session_tokens_key(email)) else {
+        return Ok(None);
+    };
+    let spd: plist::Dictionary =
+        plist::from_bytes(xml.as_bytes()).map_err(|e| IloaderError::Auth {
+            email: email.to_string(),
+            report: format!("Saved session tokens are unreadable: {}", e),
+        })?;
+
+    let (mut account, anisette_server) = build_account(data_dir, email, anisette_servers).await?;
+    account.spd = Some(spd);
+
+    let session = developer_session(&mut account).await?;
+    Ok(Some((session, anisette_server)))
+}
+
+// Apple's auth and developer services hosts, checked when signing in or resuming a session fails
+const APPLE_HOSTS: &[&str] = &[
+    "https://gsa.apple.com",
+    "https://developerservices2.apple.com",
+];
+
+// Any response, even an error status, means the host is reachable
+async fn apple_reachable() -> bool {
+    let Ok(client) = reqwest::Client::builder()
+        .timeout(Duration::from_secs(5))
+        .build()
+    else {
+        return false;
+    };
+    futures::future::join_all(APPLE_HOSTS.iter().map(|host| client.head(*host).send()))
+        .await
+        .iter()
+        .all(Result::is_ok)
+}
+
+// isideload reports a rejected token and a dropped connection the same way, so a developer
+// services failure only counts as a rejection if Apple can be reached. Anisette and connection
+// failures say nothing about the tokens
+async fn tokens_rejected(error: &IloaderError) -> bool {
+    match error {
+        IloaderError::Auth { .. } => true,
+        IloaderError::DeveloperServices { .. } => apple_reachable().await,
+        _ => false,
+    }
+}
+
+// The only team, the one remembered from last time, or the one the user picks
+pub async fn choose_team(
+    teams: &[TeamInfo],
+    remembered_team: Option<&str>,
+    prompter: Arc<dyn LoginPrompter>,
+) -> Result<usize, IloaderError> {
+    if teams.len() == 1 {
+        return Ok(0);
+    }
+    if let Some(index) = remembered_team.and_then(|id| teams.iter().position(|t| t.team_id == id)) {
+        return Ok(index);
+    }
+    if teams.is_empty() {
+        return Err(IloaderError::NoTeamSelected);
+    }
+
+    // The prompt blocks until it's answered, so it runs off the async runtime like the 2FA one
+    let options = teams.to_vec();
+    let team_id = tokio::task::spawn_blocking(move || prompter.select_team(&options))
+        .await
+        .unwrap_or(Err(PromptError::Cancelled))
+        .map_err(|e| match e {
+            PromptError::Cancelled => IloaderError::NoTeamSelected,
+            PromptError::TimedOut => IloaderError::PromptTimeout {
+                prompt: "team selection",
+            },
+        })?;
+    teams
+        .iter()
+        .position(|t| t.team_id == team_id)
+        .ok_or(IloaderError::NoTeamSelected)
+}
+
+// The team picked at login, keyed by the account's sorted team IDs. TeamSelection only takes a
+// plain fn pointer, which sees nothing but the teams, so this is how the sideloader finds the
+// choice again
+static SELECTED_TEAMS: Mutex<Option<HashMap<Vec<String>, String>>> = Mutex::new(None);
+
+fn teams_key<'a>(team_ids: impl Iterator<Item = &'a str>) -> Vec<String> {
+    let mut key: Vec<String> = team_ids.map(str::to_string).collect();
+    key.sort();
+    key
+}
+
+fn remember_selected_team(teams: &[TeamInfo], team_id: &str) {
+    SELECTED_TEAMS
+        .lock()
+        .unwrap()
+        .get_or_insert_with(HashMap::new)
+        .insert(
+            teams_key(teams.iter().map(|t| t.team_id.as_str())),
+            team_id.to_string(),
+        );
+}
+
+// Only called when there is more than one team
+#[allow(clippy::ptr_arg)] // The signature TeamSelection::PromptOnce takes
+fn selected_team(teams: &Vec<DeveloperTeam>) -> Option<String> {
+    SELECTED_TEAMS
+        .lock()
+        .unwrap()
+        .as_ref()?
+        .get(&teams_key(teams.iter().map(|t| t.team_id.as_str())))
+        .cloned()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TeamInfo {
+    pub team_id: String,
+    pub name: Option<String>,
+    pub team_type: Option<String>,
+}
+
+impl From<&DeveloperTeam> for TeamInfo {
+    fn from(team: &DeveloperTeam) -> Self {
+        TeamInfo {
+            team_id: team.team_id.clone(),
+            name: team.name.clone(),
+            team_type: team.r#type.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ListTeamsResponse {
+    pub teams: Vec<TeamInfo>,
+    pub selected_team_id: String,
+}
+
+#[tauri::command]
+pub async fn list_teams(
+    sessions: State<'_, SessionRegistry>,
+    email: Option<String>,
+) -> Result<ListTeamsResponse, IloaderError> {
+    let mut sideloader = sessions.lock(email.as_deref()).await?;
+
+    let team =
+        sideloader
+            .get_mut()
+            .get_team()
+            .await
+            .map_err(|e| IloaderError::DeveloperServices {
+                action: "get team",
+                report: e.to_string(),
+            })?;
+    let dev_session = sideloader.get_mut().get_dev_session();
+
+    let teams = dev_session
+        .list_teams()
+        .await
+        .map_err(|e| IloaderError::DeveloperServices {
+            action: "list teams",
+            report: e.to_string(),
+        })?;
+
+    Ok(ListTeamsResponse {
+        teams: teams.iter().map(TeamInfo::from).collect(),
+        selected_team_id: TeamInfo::from(&team).team_id,
+    })
+}
+
+// How many days before expiry a certificate counts as expiring soon, unless the caller says
+pub const CERTIFICATE_WARN_DAYS: i64 = 30;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CertificateStatus {
+    Valid,
+    ExpiringSoon,
+    Expired,
+    // The certificate content wasn't included or couldn't be parsed
+    #[default]
+    Unknown,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -251,77 +830,267 @@ pub struct CertificateInfo {
     pub serial_number: Option<String>,
     pub machine_name: Option<String>,
     pub machine_id: Option<String>,
+    // RFC 3339 validity dates from the certificate itself
+    #[serde(default)]
+    pub created: Option<String>,
+    #[serde(default)]
+    pub expires: Option<String>,
+    #[serde(default)]
+    pub status: CertificateStatus,
+    // Whether it was issued for this install's signing key. isideload signs with it as long as it's
+    // also registered under this install's machine name
+    #[serde(default)]
+    pub in_local_storage: bool,
+    // Whether it was registered under this install's machine name
+    #[serde(default)]
+    pub this_machine: bool,
+}
+
+impl CertificateInfo {
+    pub fn new(cert: &DevelopmentCertificate, warn_days: i64) -> Self {
+        let validity = cert
+            .cert_content
+            .as_ref()
+            .and_then(|content| certificate::validity(content.as_ref()));
+        let status = match validity {
+            None => CertificateStatus::Unknown,
+            Some((_, expires)) => {
+                let now = chrono::Utc::now();
+                if expires <= now {
+                    CertificateStatus::Expired
+                } else if expires - now <= chrono::Duration::days(warn_days) {
+                    CertificateStatus::ExpiringSoon
+                } else {
+                    CertificateStatus::Valid
+                }
+            }
+        };
+        CertificateInfo {
+            name: cert.name.clone(),
+            certificate_id: cert.certificate_id.clone(),
+            serial_number: cert.serial_number.clone(),
+            machine_name: cert.machine_name.clone(),
+            machine_id: cert.machine_id.clone(),
+            created: validity.map(|(created, _)| created.to_rfc3339()),
+            expires: validity.map(|(_, expires)| expires.to_rfc3339()),
+            status,
+            in_local_storage: false,
+            this_machine: false,
+        }
+    }
+}
+
+impl From<&DevelopmentCertificate> for CertificateInfo {
+    fn from(cert: &DevelopmentCertificate) -> Self {
+        CertificateInfo::new(cert, CERTIFICATE_WARN_DAYS)
+    }
+}
+
+// The team's certificates with the one issued for this install's signing key, as found by
+// certificate::stored_serial, and the ones registered under its machine name marked
+pub fn certificate_infos(
+    certificates: &[DevelopmentCertificate],
+    local_serial: Option<&str>,
+    machine_name: Option<&str>,
+    warn_days: i64,
+) -> Vec<CertificateInfo> {
+    certificates
+        .iter()
+        .map(|cert| {
+            let mut info = CertificateInfo::new(cert, warn_days);
+            info.in_local_storage = match (&info.serial_number, local_serial) {
+                (Some(serial), Some(local)) => certificate::same_serial(serial, local),
+                _ => false,
+            };
+            info.this_machine = match (&info.machine_name, machine_name) {
+                (Some(name), Some(ours)) => name.eq_ignore_ascii_case(ours),
+                _ => false,
+            };
+            info
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum CertificateWarning {
+    #[serde(rename_all = "camelCase")]
+    ExpiringSoon {
+        serial_number: String,
+        expires: String,
+    },
+    #[serde(rename_all = "camelCase")]
+    Expired {
+        serial_number: String,
+    },
+    // This install has a signing key, but none of the team's certificates was issued for it, so
+    // its certificate was revoked or never created
+    Revoked,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CertificatesResponse {
+    pub certificates: Vec<CertificateInfo>,
+    // Serial number of the certificate this install signs with for the team, if it has one
+    pub local_serial_number: Option<String>,
+    // Name this install registers certificates under for the account
+    pub machine_name: String,
+    pub warnings: Vec<CertificateWarning>,
+}
+
+// Warnings only cover the certificate this install signs with, the one issued for its signing key;
+// the others belong to other machines
+fn certificate_warnings(
+    certificates: &[CertificateInfo],
+    has_signing_key: bool,
+) -> Vec<CertificateWarning> {
+    let Some(local) = certificates.iter().find(|c| c.in_local_storage) else {
+        return if has_signing_key {
+            vec![CertificateWarning::Revoked]
+        } else {
+            vec![]
+        };
+    };
+    let serial_number = local.serial_number.clone().unwrap_or_default();
+    match local.status {
+        CertificateStatus::ExpiringSoon => vec![CertificateWarning::ExpiringSoon {
+            serial_number,
+            expires: local.expires.clone().unwrap_or_default(),
+        }],
+        CertificateStatus::Expired => vec![CertificateWarning::Expired { serial_number }],
+        _ => vec![],
+    }
 }
 
 #[tauri::command]
 pub async fn get_certificates(
-    sideloader_state: State<'_, SideloaderMutex>,
-) -> Result<Vec<CertificateInfo>, String> {
-    let mut sideloader = SideloaderGuard::take(&sideloader_state)?;
+    handle: AppHandle,
+    sessions: State<'_, SessionRegistry>,
+    email: Option<String>,
+    warn_days: Option<i64>,
+) -> Result<CertificatesResponse, IloaderError> {
+    let mut sideloader = sessions.lock(email.as_deref()).await?;
+    let machine_name = stored_machine_name(&handle, sideloader.get_mut().get_email());
 
-    let team = sideloader
-        .get_mut()
-        .get_team()
-        .await
-        .map_err(|e| e.to_string())?;
+    let team =
+        sideloader
+            .get_mut()
+            .get_team()
+            .await
+            .map_err(|e| IloaderError::DeveloperServices {
+                action: "get team",
+                report: e.to_string(),
+            })?;
     let dev_session = sideloader.get_mut().get_dev_session();
 
     let certificates = dev_session
         .list_all_development_certs(&team, None)
         .await
-        .map_err(|e| format!("Failed to get development certificates: {:?}.", e))?;
+        .map_err(|e| IloaderError::DeveloperServices {
+            action: "list development certificates",
+            report: e.to_string(),
+        })?;
 
-    Ok(certificates
-        .into_iter()
-        .map(|cert| CertificateInfo {
-            name: cert.name,
-            certificate_id: cert.certificate_id,
-            serial_number: cert.serial_number,
-            machine_name: cert.machine_name,
-            machine_id: cert.machine_id,
-        })
-        .collect())
+    let email = sideloader.get_mut().get_email().to_string();
+    let data_dir = handle.path().app_data_dir().ok();
+    let local_serial = data_dir
+        .as_ref()
+        .and_then(|data_dir| certificate::stored_serial(data_dir, &email, &certificates));
+    let has_signing_key = data_dir.is_some_and(|data_dir| {
+        matches!(
+            certificate::stored_signing_key(storage(&data_dir).as_ref(), &email),
+            Ok(Some(_))
+        )
+    });
+    let certificates = certificate_infos(
+        &certificates,
+        local_serial.as_deref(),
+        Some(&machine_name),
+        warn_days.unwrap_or(CERTIFICATE_WARN_DAYS),
+    );
+
+    let warnings = certificate_warnings(&certificates, has_signing_key);
+    for warning in &warnings {
+        match warning {
+            CertificateWarning::ExpiringSoon {
+                serial_number,
+                expires,
+            } => warn!("Signing certificate {} expires {}", serial_number, expires),
+            CertificateWarning::Expired { serial_number } => {
+                warn!("Signing certificate {} has expired", serial_number)
+            }
+            CertificateWarning::Revoked => {
+                warn!(
+                    "No team certificate was issued for the signing key of {}",
+                    email
+                )
+            }
+        }
+    }
+
+    Ok(CertificatesResponse {
+        certificates,
+        local_serial_number: local_serial,
+        machine_name,
+        warnings,
+    })
 }
 
 #[tauri::command]
 pub async fn revoke_certificate(
     serial_number: String,
-    sideloader_state: State<'_, SideloaderMutex>,
-) -> Result<(), String> {
-    let mut sideloader = SideloaderGuard::take(&sideloader_state)?;
+    sessions: State<'_, SessionRegistry>,
+    email: Option<String>,
+) -> Result<(), IloaderError> {
+    let mut sideloader = sessions.lock(email.as_deref()).await?;
 
-    let team = sideloader
-        .get_mut()
-        .get_team()
-        .await
-        .map_err(|e| e.to_string())?;
+    let team =
+        sideloader
+            .get_mut()
+            .get_team()
+            .await
+            .map_err(|e| IloaderError::DeveloperServices {
+                action: "get team",
+                report: e.to_string(),
+            })?;
     let dev_session = sideloader.get_mut().get_dev_session();
 
     dev_session
         .revoke_development_cert(&team, &serial_number, None)
         .await
-        .map_err(|e| format!("Failed to revoke development certificates: {:?}.", e))?;
+        .map_err(|e| IloaderError::DeveloperServices {
+            action: "revoke development certificate",
+            report: e.to_string(),
+        })?;
 
     Ok(())
 }
 
 #[tauri::command]
 pub async fn list_app_ids(
-    sideloader_state: State<'_, SideloaderMutex>,
-) -> Result<ListAppIdsResponse, String> {
-    let mut sideloader = SideloaderGuard::take(&sideloader_state)?;
+    sessions: State<'_, SessionRegistry>,
+    email: Option<String>,
+) -> Result<ListAppIdsResponse, IloaderError> {
+    let mut sideloader = sessions.lock(email.as_deref()).await?;
 
-    let team = sideloader
-        .get_mut()
-        .get_team()
-        .await
-        .map_err(|e| e.to_string())?;
+    let team =
+        sideloader
+            .get_mut()
+            .get_team()
+            .await
+            .map_err(|e| IloaderError::DeveloperServices {
+                action: "get team",
+                report: e.to_string(),
+            })?;
     let dev_session = sideloader.get_mut().get_dev_session();
 
-    let response = dev_session
-        .list_app_ids(&team, None)
-        .await
-        .map_err(|e| e.to_string())?;
+    let response = dev_session.list_app_ids(&team, None).await.map_err(|e| {
+        IloaderError::DeveloperServices {
+            action: "list App IDs",
+            report: e.to_string(),
+        }
+    })?;
 
     Ok(response.clone())
 }
@@ -329,29 +1098,51 @@ pub async fn list_app_ids(
 #[tauri::command]
 pub async fn delete_app_id(
     app_id_id: String,
-    sideloader_state: State<'_, SideloaderMutex>,
-) -> Result<(), String> {
-    let mut sideloader = SideloaderGuard::take(&sideloader_state)?;
+    sessions: State<'_, SessionRegistry>,
+    email: Option<String>,
+) -> Result<(), IloaderError> {
+    let mut sideloader = sessions.lock(email.as_deref()).await?;
 
-    let team = sideloader
-        .get_mut()
-        .get_team()
-        .await
-        .map_err(|e| e.to_string())?;
+    let team =
+        sideloader
+            .get_mut()
+            .get_team()
+            .await
+            .map_err(|e| IloaderError::DeveloperServices {
+                action: "get team",
+                report: e.to_string(),
+            })?;
     let dev_session = sideloader.get_mut().get_dev_session();
 
     dev_session
         .delete_app_id(&team, &app_id_id, None)
         .await
-        .map_err(|e| format!("Failed to delete App ID: {:?}.", e))?;
+        .map_err(|e| IloaderError::DeveloperServices {
+            action: "delete App ID",
+            report: e.to_string(),
+        })?;
 
     Ok(())
 }
 
 static KEYRING_AVAILABLE: OnceLock<bool> = OnceLock::new();
 
-fn keyring_available() -> bool {
-    *KEYRING_AVAILABLE.get_or_init(check_keyring_available)
+pub(crate) fn keyring_available() -> bool {
+    *KEYRING_AVAILABLE.get_or_init(|| {
+        let available = check_keyring_available();
+        if !available {
+            warn!("Keyring storage is not available, falling back to encrypted file storage");
+        }
+        available
+    })
+}
+
+// Makes storage use encrypted files, so tests never touch the real keyring
+#[cfg(feature = "test-support")]
+pub fn disable_keyring() {
+    if KEYRING_AVAILABLE.set(false).is_err() {
+        assert!(!keyring_available(), "the keyring is already in use");
+    }
 }
 
 fn check_keyring_available() -> bool {
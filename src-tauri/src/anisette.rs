@@ -0,0 +1,296 @@
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use futures::{StreamExt, future, stream::FuturesUnordered};
+use isideload::{anisette::remote_v3::RemoteV3AnisetteProvider, util::storage::SideloadingStorage};
+use serde::Serialize;
+use tauri::{AppHandle, Manager, State};
+use tracing::{debug, info, warn};
+
+use crate::{
+    account::{keyring_available, storage},
+    encrypted_storage::EncryptedFsStorage,
+    error::IloaderError,
+    sideload::SessionRegistry,
+    storage_migration::IndexedKeyringStorage,
+};
+
+#[cfg(feature = "test-support")]
+pub mod emulator;
+
+// Known public anisette servers, in the order they are tried after the preferred one
+pub const ANISETTE_SERVERS: &[(&str, &str)] = &[
+    ("ani.sidestore.io", "SideStore (.io)"),
+    ("ani.stikstore.app", "StikStore"),
+    ("ani.sidestore.app", "SideStore (.app)"),
+    ("ani.sidestore.zip", "SideStore (.zip)"),
+    ("ani.846969.xyz", "SideStore (.xyz)"),
+    ("ani.neoarz.xyz", "neoarz"),
+    ("ani.xu30.top", "SteX"),
+    ("anisette.wedotstud.io", "WE. Studio"),
+];
+
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AnisetteServer {
+    pub server: String,
+    pub name: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AnisetteServerStatus {
+    pub server: String,
+    pub name: Option<String>,
+    pub reachable: bool,
+    pub latency_ms: Option<u64>,
+    pub error: Option<String>,
+}
+
+pub fn url(server: &str) -> String {
+    if !server.starts_with("http") {
+        format!("https://{}", server)
+    } else {
+        server.to_string()
+    }
+}
+
+fn name(server: &str) -> Option<String> {
+    ANISETTE_SERVERS
+        .iter()
+        .find(|(s, _)| *s == server)
+        .map(|(_, name)| name.to_string())
+}
+
+// The preferred server first, then the known ones
+pub fn candidates(preferred: &str) -> Vec<String> {
+    let mut servers = vec![preferred.to_string()];
+    servers.extend(
+        ANISETTE_SERVERS
+            .iter()
+            .map(|(s, _)| s.to_string())
+            .filter(|s| s != preferred),
+    );
+    servers
+}
+
+// v3 servers answer client_info without any state, which makes it a cheap health check
+pub async fn probe(client: &reqwest::Client, server: &str) -> AnisetteServerStatus {
+    let start = Instant::now();
+    let result = client
+        .get(format!("{}/v3/client_info", url(server)))
+        .send()
+        .await
+        .and_then(|response| response.error_for_status());
+    let latency_ms = start.elapsed().as_millis() as u64;
+
+    let status = AnisetteServerStatus {
+        server: server.to_string(),
+        name: name(server),
+        reachable: result.is_ok(),
+        latency_ms: result.is_ok().then_some(latency_ms),
+        error: result.err().map(|e| e.to_string()),
+    };
+    debug!(
+        "Anisette server {}: {}",
+        server,
+        match &status.error {
+            Some(e) => e.clone(),
+            None => format!("{} ms", latency_ms),
+        }
+    );
+    status
+}
+
+fn probe_client() -> reqwest::Client {
+    match reqwest::Client::builder().timeout(PROBE_TIMEOUT).build() {
+        Ok(client) => client,
+        Err(e) => {
+            warn!("Unable to create HTTP client for anisette checks: {}", e);
+            reqwest::Client::new()
+        }
+    }
+}
+
+pub async fn probe_all(servers: &[String]) -> Vec<AnisetteServerStatus> {
+    let client = probe_client();
+    future::join_all(servers.iter().map(|server| probe(&client, server))).await
+}
+
+// Order to try the servers in during login
+pub async fn login_order(preferred: &str) -> Vec<String> {
+    fallback_order(candidates(preferred)).await
+}
+
+// Only the first server is checked when it's up. Otherwise the others are checked together and
+// the first one to answer goes first, without waiting for the slow ones; the rest keep their order
+// and the first server goes last
+pub async fn fallback_order(mut servers: Vec<String>) -> Vec<String> {
+    if servers.is_empty() {
+        return servers;
+    }
+    let client = probe_client();
+    let preferred = servers.remove(0);
+    if probe(&client, &preferred).await.reachable {
+        servers.insert(0, preferred);
+        return servers;
+    }
+
+    let first = servers
+        .iter()
+        .map(|server| probe(&client, server))
+        .collect::<FuturesUnordered<_>>()
+        .filter(|status| future::ready(status.reachable))
+        .next()
+        .await;
+    if let Some(first) = first {
+        info!(
+            "Anisette server {} is unavailable, trying {} first",
+            preferred, first.server
+        );
+        servers.retain(|server| *server != first.server);
+        servers.insert(0, first.server);
+    }
+    servers.push(preferred);
+    servers
+}
+
+// The key isideload keeps the provisioned anisette identity under
+pub(crate) const STATE_KEY: &str = "anisette_state";
+pub(crate) const SERIAL_KEY: &str = "anisette_serial";
+
+pub(crate) fn keyring_service(email: &str) -> String {
+    format!("iloader.anisette.{}", email.to_lowercase())
+}
+
+pub(crate) fn storage_dir(data_dir: &Path, email: &str) -> PathBuf {
+    data_dir.join("anisette").join(email.to_lowercase())
+}
+
+// Each Apple ID gets its own provisioned identity, in its own keyring service or directory
+pub(crate) fn account_storage(data_dir: &Path, email: &str) -> Box<dyn SideloadingStorage> {
+    if keyring_available() {
+        Box::new(IndexedKeyringStorage::new(data_dir, keyring_service(email)))
+    } else {
+        Box::new(EncryptedFsStorage::new(
+            data_dir,
+            storage_dir(data_dir, email),
+        ))
+    }
+}
+
+// Before anisette state was per account, every account shared one entry and serial number "0".
+// The first account to log in takes it over, so it doesn't have to provision (and 2FA) again
+fn migrate_global_state(data_dir: &Path, email: &str, account: &dyn SideloadingStorage) {
+    let global = storage(data_dir);
+    let Ok(Some(state)) = global.retrieve(STATE_KEY) else {
+        return;
+    };
+    if !matches!(account.retrieve(STATE_KEY), Ok(None)) {
+        return;
+    }
+
+    let migrated = account
+        .store(STATE_KEY, &state)
+        .and_then(|_| account.store(SERIAL_KEY, "0"));
+    match migrated {
+        Ok(()) => {
+            info!("Moved the shared anisette state to {}", email);
+            if let Err(e) = global.delete(STATE_KEY) {
+                warn!("Unable to delete the shared anisette state: {}", e);
+            }
+        }
+        Err(e) => warn!(
+            "Unable to move the shared anisette state to {}: {}",
+            email, e
+        ),
+    }
+}
+
+// A stable serial number per account, so each one looks like its own machine
+fn serial_number(email: &str, account: &dyn SideloadingStorage) -> String {
+    if let Ok(Some(serial)) = account.retrieve(SERIAL_KEY) {
+        return serial;
+    }
+    // FNV-1a, which unlike the std hasher is the same across Rust versions
+    let hash = email
+        .to_lowercase()
+        .bytes()
+        .fold(0xcbf29ce484222325u64, |hash, b| {
+            (hash ^ b as u64).wrapping_mul(0x100000001b3)
+        });
+    let serial = format!("{:012X}", hash & 0xFFFF_FFFF_FFFF);
+    if let Err(e) = account.store(SERIAL_KEY, &serial) {
+        warn!("Unable to save the anisette serial number: {}", e);
+    }
+    serial
+}
+
+pub fn provider(data_dir: &Path, email: &str, server: &str) -> RemoteV3AnisetteProvider {
+    let account = account_storage(data_dir, email);
+    migrate_global_state(data_dir, email, account.as_ref());
+    RemoteV3AnisetteProvider::default()
+        .set_serial_number(serial_number(email, account.as_ref()))
+        .set_storage(account)
+        .set_url(&url(server))
+}
+
+pub fn reset_state(data_dir: &Path, email: &str) -> Result<(), IloaderError> {
+    account_storage(data_dir, email)
+        .delete(STATE_KEY)
+        .map_err(|e| IloaderError::AnisetteReset {
+            account: email.to_string(),
+            report: e.to_string(),
+        })
+}
+
+// Resets the given account, or the active one. Other accounts keep their anisette state
+#[tauri::command]
+pub fn reset_anisette_state(
+    handle: AppHandle,
+    sessions: State<'_, SessionRegistry>,
+    email: Option<String>,
+) -> Result<(), IloaderError> {
+    let email = email
+        .or_else(|| sessions.active_email())
+        .ok_or(IloaderError::NotLoggedIn)?;
+    let data_dir = handle
+        .path()
+        .app_data_dir()
+        .map_err(|e| IloaderError::AnisetteReset {
+            account: email.clone(),
+            report: e.to_string(),
+        })?;
+    reset_state(&data_dir, &email)
+}
+
+#[tauri::command]
+pub fn anisette_servers() -> Vec<AnisetteServer> {
+    ANISETTE_SERVERS
+        .iter()
+        .map(|(server, name)| AnisetteServer {
+            server: server.to_string(),
+            name: Some(name.to_string()),
+        })
+        .collect()
+}
+
+// Checks the given servers, or the preferred one followed by the known ones
+#[tauri::command]
+pub async fn check_anisette_servers(
+    preferred: Option<String>,
+    servers: Option<Vec<String>>,
+) -> Vec<AnisetteServerStatus> {
+    let servers = servers.unwrap_or_else(|| match preferred {
+        Some(preferred) => candidates(&preferred),
+        None => ANISETTE_SERVERS
+            .iter()
+            .map(|(s, _)| s.to_string())
+            .collect(),
+    });
+    probe_all(&servers).await
+}
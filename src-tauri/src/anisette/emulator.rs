@@ -0,0 +1,160 @@
+use std::{
+    io,
+    net::SocketAddr,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+
+use serde_json::{Value, json};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+    task::JoinHandle,
+};
+
+const CLIENT_INFO: &str =
+    "<MacBookPro13,2> <macOS;13.1;22C65> <com.apple.AuthKit/1 (com.apple.dt.Xcode/3594.4.19)>";
+const USER_AGENT: &str = "akd/1.0 CFNetwork/808.1.4";
+const ROUTING_INFO: &str = "17106176";
+
+// Speaks the stateless part of the anisette v3 protocol on a local port: client_info, which is
+// what server checks and login_order use, and get_headers for an identity that is already
+// provisioned. Provisioning goes through Apple's servers, so it isn't served. Use `url()` as the
+// anisette server
+pub struct AnisetteEmulator {
+    addr: SocketAddr,
+    state: Arc<EmulatorState>,
+    task: JoinHandle<()>,
+}
+
+#[derive(Default)]
+struct EmulatorState {
+    // "<method> <path>" of every request, in order
+    requests: Mutex<Vec<String>>,
+    // Answers everything with 503, like a server that is up but broken
+    failing: AtomicBool,
+}
+
+impl AnisetteEmulator {
+    pub async fn start() -> io::Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let state = Arc::new(EmulatorState::default());
+
+        let task = tokio::spawn({
+            let state = state.clone();
+            async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    let state = state.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = handle_client(&state, stream).await {
+                            tracing::debug!("anisette emulator connection closed: {e}");
+                        }
+                    });
+                }
+            }
+        });
+
+        Ok(AnisetteEmulator { addr, state, task })
+    }
+
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    pub fn requests(&self) -> Vec<String> {
+        self.state.requests.lock().unwrap().clone()
+    }
+
+    pub fn set_failing(&self, failing: bool) {
+        self.state.failing.store(failing, Ordering::SeqCst);
+    }
+}
+
+impl Drop for AnisetteEmulator {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+// One request per connection, answered with `Connection: close`
+async fn handle_client(state: &EmulatorState, stream: TcpStream) -> io::Result<()> {
+    let mut stream = BufReader::new(stream);
+    let mut request_line = String::new();
+    stream.read_line(&mut request_line).await?;
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or_default().to_string();
+    let path = parts.next().unwrap_or_default().to_string();
+
+    let mut content_length = 0;
+    loop {
+        let mut header = String::new();
+        if stream.read_line(&mut header).await? == 0 || header.trim().is_empty() {
+            break;
+        }
+        match header.split_once(':') {
+            Some((name, value)) if name.trim().eq_ignore_ascii_case("content-length") => {
+                content_length = value.trim().parse().unwrap_or(0);
+            }
+            _ => {}
+        }
+    }
+    let mut body = vec![0u8; content_length];
+    stream.read_exact(&mut body).await?;
+
+    state
+        .requests
+        .lock()
+        .unwrap()
+        .push(format!("{} {}", method, path));
+    let (status, response) = if state.failing.load(Ordering::SeqCst) {
+        ("503 Service Unavailable", json!({ "error": "unavailable" }))
+    } else {
+        route(&method, &path, &body)
+    };
+
+    let body = response.to_string();
+    let head = format!(
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        body.len()
+    );
+    let stream = stream.get_mut();
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(body.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+fn route(method: &str, path: &str, body: &[u8]) -> (&'static str, Value) {
+    match (method, path) {
+        ("GET", "/v3/client_info") => (
+            "200 OK",
+            json!({ "client_info": CLIENT_INFO, "user_agent": USER_AGENT }),
+        ),
+        ("POST", "/v3/get_headers") => {
+            let request: Value = serde_json::from_slice(body).unwrap_or_default();
+            let field = |name: &str| request.get(name).and_then(Value::as_str);
+            match (field("identifier"), field("adi_pb")) {
+                (Some(identifier), Some(_)) => (
+                    "200 OK",
+                    json!({
+                        "result": "Headers",
+                        "X-Apple-I-MD": format!("md-{}", identifier),
+                        "X-Apple-I-MD-M": format!("md-m-{}", identifier),
+                        "X-Apple-I-MD-RINFO": ROUTING_INFO,
+                    }),
+                ),
+                _ => (
+                    "200 OK",
+                    json!({
+                        "result": "GetHeadersError",
+                        "message": "The identity is not provisioned",
+                    }),
+                ),
+            }
+        }
+        _ => ("404 Not Found", json!({ "error": "not found" })),
+    }
+}
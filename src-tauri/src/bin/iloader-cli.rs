@@ -0,0 +1,1040 @@
+use std::{
+    io::{BufRead, Write},
+    path::PathBuf,
+    sync::{Arc, Mutex, mpsc},
+    time::Duration,
+};
+
+use clap::{Parser, Subcommand, ValueEnum};
+use iloader_lib::{
+    account::{
+        CertificateInfo, LoginMethod, LoginPrompter, LoginRequest, TeamInfo, delete_password,
+        forget_session_tokens, login, machine_name, save_password, stored_password,
+    },
+    anisette,
+    certificate::{self, RevokeFilter},
+    credentials,
+    device::{DeviceInfo, connected_devices},
+    device_backend::UsbmuxdBackend,
+    error::IloaderError,
+    history::{HistoryFilter, HistoryJournal},
+    max_certs::MaxCertsPolicy,
+    operation::{JsonLinesProgress, Operation, ProgressSink, TerminalProgress},
+    pairing::{export_pairing, pairing_apps, place_pairing},
+    profile,
+    prompt::{PromptError, PromptTimeouts},
+    sideload::{SessionRegistry, run_install_sidestore, run_sideload},
+    storage_migration,
+    two_factor::{TwoFactorRequest, TwoFactorResponse},
+};
+use isideload::sideload::sideloader::Sideloader;
+use serde_json::{Map, Value};
+use tokio_util::sync::CancellationToken;
+
+// Matches the identifier in tauri.conf.json so the CLI shares saved accounts with the GUI
+const APP_IDENTIFIER: &str = "me.nabdev.iloader";
+
+#[derive(Parser)]
+#[command(name = "iloader-cli", version, about = "Headless iloader")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+    /// Report install progress and history as JSON lines on stdout
+    #[arg(long, global = true)]
+    json: bool,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Sign in to an Apple ID, optionally saving the credentials for later use
+    Login {
+        #[command(flatten)]
+        account: AccountArgs,
+        /// Save the credentials to the keyring
+        #[arg(long)]
+        save: bool,
+    },
+    /// Delete the provisioned anisette identity of an Apple ID, so the next login provisions a new one
+    ResetAnisette {
+        /// Apple ID email
+        #[arg(short, long, env = "ILOADER_EMAIL")]
+        email: String,
+    },
+    /// Move saved data left in the other storage backend into the active one
+    MigrateStorage,
+    /// Check that every saved account has a password and every saved password an account
+    Credentials {
+        /// Add accounts with a saved password back to the saved accounts
+        #[arg(long)]
+        restore: bool,
+        /// Forget saved accounts without a password and passwords without a saved account
+        #[arg(long, conflicts_with = "restore")]
+        prune: bool,
+    },
+    /// List connected devices
+    Devices,
+    /// Install an IPA on a device
+    Install {
+        #[command(flatten)]
+        account: AccountArgs,
+        #[command(flatten)]
+        device: DeviceArgs,
+        /// Path to the IPA to install
+        ipa: PathBuf,
+    },
+    /// Download and install SideStore, then place its pairing file
+    InstallSidestore {
+        #[command(flatten)]
+        account: AccountArgs,
+        #[command(flatten)]
+        device: DeviceArgs,
+        #[arg(long)]
+        nightly: bool,
+        /// Install LiveContainer + SideStore instead of SideStore
+        #[arg(long)]
+        live_container: bool,
+    },
+    /// Manage pairing files
+    Pairing {
+        #[command(subcommand)]
+        command: PairingCommand,
+    },
+    /// Import or export the development certificate apps are signed with
+    Certificate {
+        #[command(subcommand)]
+        command: CertificateCommand,
+    },
+    /// Move saved accounts, teams, anisette identities and the signing identity between machines
+    Profile {
+        #[command(subcommand)]
+        command: ProfileCommand,
+    },
+    /// Show past install operations, newest first
+    History {
+        #[command(flatten)]
+        filter: HistoryFilterArgs,
+        /// Delete the history instead of showing it
+        #[arg(long)]
+        clear: bool,
+    },
+}
+
+#[derive(clap::Args)]
+struct HistoryFilterArgs {
+    /// Only show this operation, e.g. "sideload" or "install_sidestore"
+    #[arg(long)]
+    operation: Option<String>,
+    /// Only show operations on this device UDID
+    #[arg(long)]
+    device: Option<String>,
+    /// Only show operations by this Apple ID
+    #[arg(long)]
+    email: Option<String>,
+    /// Only show operations that "succeeded", "failed" or were "cancelled"
+    #[arg(long)]
+    outcome: Option<String>,
+}
+
+#[derive(Subcommand)]
+enum PairingCommand {
+    /// List installed apps that accept a pairing file
+    Apps {
+        #[command(flatten)]
+        device: DeviceArgs,
+    },
+    /// Place the pairing file in an installed app
+    Place {
+        #[command(flatten)]
+        device: DeviceArgs,
+        /// Bundle ID of the app, as shown by `pairing apps`
+        bundle_id: String,
+        /// Path of the pairing file inside the app's Documents directory
+        path: String,
+    },
+    /// Write the pairing file to disk
+    Export {
+        #[command(flatten)]
+        device: DeviceArgs,
+        #[arg(short, long, default_value = "pairingFile.plist")]
+        output: PathBuf,
+    },
+}
+
+#[derive(Subcommand)]
+enum CertificateCommand {
+    /// Sign with an existing development certificate of the team instead of creating a new one
+    Import {
+        #[command(flatten)]
+        account: AccountArgs,
+        /// PKCS#12 file with the certificate and its private key
+        input: PathBuf,
+    },
+    /// Write the certificate and private key apps are signed with to a PKCS#12 file
+    Export {
+        #[command(flatten)]
+        account: AccountArgs,
+        #[arg(short, long, default_value = "iloader.p12")]
+        output: PathBuf,
+    },
+    /// Revoke every development certificate matching a filter
+    Revoke {
+        #[command(flatten)]
+        account: AccountArgs,
+        #[command(flatten)]
+        filter: RevokeFilterArgs,
+        /// Only show what would be revoked
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+#[derive(clap::Args)]
+#[group(required = true, multiple = false)]
+struct RevokeFilterArgs {
+    /// Certificates created by this machine
+    #[arg(long)]
+    machine: Option<String>,
+    /// Certificates whose name matches this pattern, with * and ? wildcards
+    #[arg(long)]
+    name: Option<String>,
+    /// Certificates created before this date (YYYY-MM-DD or RFC 3339)
+    #[arg(long)]
+    created_before: Option<String>,
+    /// Every certificate except the one this machine signs with
+    #[arg(long)]
+    all_except_local: bool,
+}
+
+impl RevokeFilterArgs {
+    fn filter(self) -> RevokeFilter {
+        if let Some(machine_name) = self.machine {
+            RevokeFilter::MachineName { machine_name }
+        } else if let Some(pattern) = self.name {
+            RevokeFilter::NamePattern { pattern }
+        } else if let Some(date) = self.created_before {
+            RevokeFilter::CreatedBefore { date }
+        } else {
+            RevokeFilter::AllExceptLocal
+        }
+    }
+}
+
+#[derive(Subcommand)]
+enum ProfileCommand {
+    /// Write a password-encrypted profile
+    Export {
+        #[arg(short, long, default_value = "profile.iloaderprofile")]
+        output: PathBuf,
+    },
+    /// Restore a profile written by `profile export` or the GUI
+    Import {
+        input: PathBuf,
+        /// Replace data this machine already has instead of keeping it
+        #[arg(long)]
+        overwrite: bool,
+    },
+}
+
+#[derive(clap::Args)]
+struct AccountArgs {
+    /// Apple ID email
+    #[arg(short, long, env = "ILOADER_EMAIL")]
+    email: String,
+    /// Apple ID password. Falls back to saved credentials, then to a prompt
+    #[arg(long, env = "ILOADER_PASSWORD", hide_env_values = true)]
+    password: Option<String>,
+    #[arg(
+        long,
+        env = "ILOADER_ANISETTE_SERVER",
+        default_value = "ani.sidestore.io"
+    )]
+    anisette_server: String,
+    /// Developer team ID to use. Defaults to the team picked last time for this account
+    #[arg(long, env = "ILOADER_TEAM")]
+    team: Option<String>,
+    /// What to do when the team has the maximum number of development certificates
+    #[arg(long, value_enum, env = "ILOADER_MAX_CERTS", default_value = "prompt")]
+    max_certs: MaxCertsMode,
+    /// Machine name whose certificates are revoked with --max-certs revoke-machines. Repeatable
+    #[arg(long = "revoke-machine")]
+    revoke_machines: Vec<String>,
+    /// Name new certificates are registered under, remembered for this account. Defaults to
+    /// iloader-<hostname>
+    #[arg(long, env = "ILOADER_MACHINE_NAME")]
+    machine_name: Option<String>,
+    /// Seconds to wait for a 2FA code. Defaults to 120
+    #[arg(long, env = "ILOADER_TWO_FACTOR_TIMEOUT")]
+    two_factor_timeout: Option<u64>,
+    /// Seconds to wait for a team or certificate choice. Defaults to 300
+    #[arg(long, env = "ILOADER_PROMPT_TIMEOUT")]
+    prompt_timeout: Option<u64>,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum MaxCertsMode {
+    /// Ask which certificates to revoke
+    Prompt,
+    /// Revoke the oldest certificate created by iloader
+    RevokeOldest,
+    /// Revoke the certificates of the --revoke-machine machines
+    RevokeMachines,
+    /// Revoke nothing and fail
+    Fail,
+}
+
+impl AccountArgs {
+    // The --machine-name given now, which is saved for next time, or the saved one
+    fn machine_name(&self) -> Result<String, IloaderError> {
+        let email = self.email.to_lowercase();
+        if let Some(name) = &self.machine_name {
+            save_machine_name(&email, name)?;
+        }
+        Ok(machine_name(read_data().get("machineNames"), &email))
+    }
+
+    fn max_certs_policy(&self) -> MaxCertsPolicy {
+        match self.max_certs {
+            MaxCertsMode::Prompt => MaxCertsPolicy::Prompt,
+            MaxCertsMode::RevokeOldest => MaxCertsPolicy::RevokeOldestIloader,
+            MaxCertsMode::RevokeMachines => MaxCertsPolicy::RevokeMachines {
+                machines: self.revoke_machines.clone(),
+            },
+            MaxCertsMode::Fail => MaxCertsPolicy::Fail,
+        }
+    }
+
+    fn prompt_timeouts(&self) -> PromptTimeouts {
+        let mut timeouts = PromptTimeouts::default();
+        if let Some(seconds) = self.two_factor_timeout.filter(|s| *s > 0) {
+            timeouts.two_factor = Duration::from_secs(seconds);
+        }
+        if let Some(seconds) = self.prompt_timeout.filter(|s| *s > 0) {
+            timeouts.selection = Duration::from_secs(seconds);
+        }
+        timeouts
+    }
+}
+
+#[derive(clap::Args)]
+struct DeviceArgs {
+    /// UDID of the device to use. Required when more than one device is connected
+    #[arg(short, long, env = "ILOADER_DEVICE")]
+    device: Option<String>,
+}
+
+fn main() {
+    rustls::crypto::aws_lc_rs::default_provider()
+        .install_default()
+        .expect("Failed to install rustls crypto provider");
+    isideload::init().expect("Failed to initialize error reporting");
+
+    tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .with_max_level(tracing::Level::INFO)
+        .init();
+
+    let cli = Cli::parse();
+    let sink: Box<dyn ProgressSink> = if cli.json {
+        Box::new(JsonLinesProgress::new(std::io::stdout()))
+    } else {
+        Box::new(TerminalProgress)
+    };
+    if let Err(e) = tauri::async_runtime::block_on(run(cli.command, sink.as_ref(), cli.json)) {
+        eprintln!("Error: {}", e.details());
+        std::process::exit(1);
+    }
+}
+
+async fn run(command: Command, sink: &dyn ProgressSink, json: bool) -> Result<(), IloaderError> {
+    match command {
+        Command::Login { account, save } => {
+            let password = password(&account)?;
+            let sideloader = login_account(&account, &password, false).await?;
+            println!("Logged in as {}", sideloader.get_email());
+            if save {
+                save_password(&account.email, &password)?;
+                add_stored_id(&account.email)?;
+                println!("Saved credentials for {}", account.email);
+            }
+        }
+        Command::ResetAnisette { email } => {
+            anisette::reset_state(&data_dir()?, &email)?;
+            println!("Reset anisette state for {}", email);
+        }
+        Command::Credentials { restore, prune } => {
+            let check = credentials::check(&data_dir()?, &saved_ids(), &known_emails());
+            if !check.keyring_available {
+                println!("The keyring is unavailable, so saved passwords can't be checked");
+                return Ok(());
+            }
+            if check.is_consistent() {
+                println!("Saved credentials are consistent");
+            }
+            for failure in &check.unreadable {
+                eprintln!(
+                    "Unable to read the password of {}: {}",
+                    failure.email, failure.error
+                );
+            }
+            for email in &check.missing_passwords {
+                if prune {
+                    forget_account(email)?;
+                    println!("Removed {}, which had no saved password", email);
+                } else {
+                    println!("{} is saved without a password", email);
+                }
+            }
+            for email in &check.orphaned_passwords {
+                if restore {
+                    add_stored_id(email)?;
+                    println!("Restored {}", email);
+                } else if prune {
+                    forget_account(email)?;
+                    println!("Deleted the saved password of {}", email);
+                } else {
+                    println!("{} has a saved password but isn't a saved account", email);
+                }
+            }
+        }
+        Command::MigrateStorage => {
+            let migration = storage_migration::migrate(&data_dir()?, &known_emails());
+            for key in &migration.moved {
+                println!("Moved {}", key);
+            }
+            for key in &migration.conflicts {
+                println!("Kept the {} copy of {}", migration.to, key);
+            }
+            for failure in &migration.failed {
+                eprintln!("Failed to move {}: {}", failure.key, failure.error);
+            }
+            println!(
+                "Moved {} entries from {} to {} storage",
+                migration.moved.len(),
+                migration.from,
+                migration.to
+            );
+        }
+        Command::Devices => {
+            for device in connected_devices(&UsbmuxdBackend).await? {
+                println!(
+                    "{}\t{}\t{}",
+                    device.uuid, device.name, device.connection_type
+                );
+            }
+        }
+        Command::Install {
+            account,
+            device,
+            ipa,
+        } => {
+            let device = Mutex::new(Some(select_device(&device).await?));
+            let sessions = SessionRegistry::default();
+            sessions.insert(login_account(&account, &password(&account)?, true).await?);
+            let journal = HistoryJournal::new(&data_dir()?);
+            let op = Operation::new("sideload".to_string(), sink, CancellationToken::new())
+                .with_journal(Some(&journal));
+            run_sideload(
+                &op,
+                &UsbmuxdBackend,
+                &device,
+                &sessions.select(None),
+                ipa.to_string_lossy().to_string(),
+            )
+            .await?;
+        }
+        Command::InstallSidestore {
+            account,
+            device,
+            nightly,
+            live_container,
+        } => {
+            let device = Mutex::new(Some(select_device(&device).await?));
+            let sessions = SessionRegistry::default();
+            sessions.insert(login_account(&account, &password(&account)?, true).await?);
+            let journal = HistoryJournal::new(&data_dir()?);
+            let op = Operation::new(
+                "install_sidestore".to_string(),
+                sink,
+                CancellationToken::new(),
+            )
+            .with_journal(Some(&journal));
+            run_install_sidestore(
+                &op,
+                &UsbmuxdBackend,
+                &device,
+                &sessions.select(None),
+                nightly,
+                live_container,
+            )
+            .await?;
+        }
+        Command::History { filter, clear } => {
+            let journal = HistoryJournal::new(&data_dir()?);
+            if clear {
+                journal.clear()?;
+                println!("Cleared operation history");
+                return Ok(());
+            }
+
+            let filter = HistoryFilter {
+                operation: filter.operation,
+                device_udid: filter.device,
+                apple_id: filter.email,
+                outcome: filter.outcome,
+            };
+            for entry in journal.entries(&filter)? {
+                if json {
+                    println!("{}", serde_json::to_string(&entry).unwrap_or_default());
+                    continue;
+                }
+                println!(
+                    "{}\t{}\t{}\t{}\t{}\t{}",
+                    entry.started_at,
+                    entry.operation,
+                    entry.outcome.as_deref().unwrap_or("unfinished"),
+                    entry.device_udid.as_deref().unwrap_or("-"),
+                    entry.apple_id.as_deref().unwrap_or("-"),
+                    entry
+                        .bundle_id
+                        .as_deref()
+                        .or(entry.source.as_deref())
+                        .unwrap_or("-"),
+                );
+                for step in entry.steps.iter().filter(|s| s.error.is_some()) {
+                    if let Some(error) = &step.error {
+                        println!("\t{} failed: {}", step.id, error.message);
+                    }
+                }
+            }
+        }
+        Command::Pairing { command } => match command {
+            PairingCommand::Apps { device } => {
+                let device = select_device(&device).await?;
+                for app in pairing_apps(&UsbmuxdBackend, &device).await? {
+                    println!("{}\t{}\t{}", app.bundle_id, app.name, app.path);
+                }
+            }
+            PairingCommand::Place {
+                device,
+                bundle_id,
+                path,
+            } => {
+                let device = select_device(&device).await?;
+                place_pairing(&UsbmuxdBackend, device, bundle_id.clone(), path).await?;
+                println!("Placed pairing file for {}", bundle_id);
+            }
+            PairingCommand::Export { device, output } => {
+                let device = select_device(&device).await?;
+                let pairing_file = export_pairing(&UsbmuxdBackend, &device).await?;
+                tokio::fs::write(&output, pairing_file)
+                    .await
+                    .map_err(|source| IloaderError::Io {
+                        path: output.to_string_lossy().to_string(),
+                        source,
+                    })?;
+                println!("Exported pairing file to {}", output.display());
+            }
+        },
+        Command::Certificate { command } => match command {
+            CertificateCommand::Import { account, input } => {
+                let p12 =
+                    std::fs::read(&input).map_err(|e| io_error(input.to_string_lossy(), e))?;
+                let p12_password = p12_password()?;
+                let mut sideloader = login_account(&account, &password(&account)?, true).await?;
+                let cert = certificate::import_identity(
+                    &mut sideloader,
+                    &data_dir()?,
+                    &p12,
+                    &p12_password,
+                )
+                .await?;
+                println!(
+                    "Imported certificate {}",
+                    cert.serial_number.unwrap_or_default()
+                );
+                // isideload only signs with it under the machine name it was registered with
+                if let Some(name) = &cert.machine_name {
+                    save_machine_name(&account.email, name)?;
+                    println!("Using machine name {} for {}", name, account.email);
+                }
+            }
+            CertificateCommand::Export { account, output } => {
+                let mut sideloader = login_account(&account, &password(&account)?, true).await?;
+                let p12_password = p12_password()?;
+                let p12 =
+                    certificate::export_identity(&mut sideloader, &data_dir()?, &p12_password)
+                        .await?;
+                std::fs::write(&output, p12).map_err(|e| io_error(output.to_string_lossy(), e))?;
+                println!("Exported certificate to {}", output.display());
+            }
+            CertificateCommand::Revoke {
+                account,
+                filter,
+                dry_run,
+            } => {
+                let mut sideloader = login_account(&account, &password(&account)?, true).await?;
+                let response = certificate::revoke_matching(
+                    &mut sideloader,
+                    &data_dir()?,
+                    &account.machine_name()?,
+                    &filter.filter(),
+                    dry_run,
+                )
+                .await?;
+                if json {
+                    println!("{}", serde_json::to_string(&response).unwrap_or_default());
+                    return Ok(());
+                }
+                if response.results.is_empty() {
+                    println!("No certificates match");
+                }
+                for result in &response.results {
+                    let cert = &result.certificate;
+                    let status = match (&result.error, dry_run) {
+                        (Some(e), _) => format!("failed: {}", e),
+                        (None, true) => "would be revoked".to_string(),
+                        (None, false) => "revoked".to_string(),
+                    };
+                    println!(
+                        "{} - {} ({}): {}",
+                        cert.name.as_deref().unwrap_or("Unknown"),
+                        cert.machine_name.as_deref().unwrap_or("Unknown"),
+                        cert.serial_number.as_deref().unwrap_or("Unknown"),
+                        status
+                    );
+                }
+            }
+        },
+        Command::Profile { command } => match command {
+            ProfileCommand::Export { output } => {
+                let password = profile_password()?;
+                let data = read_data();
+                let teams = match data.get("teams") {
+                    Some(Value::Object(teams)) => teams.clone(),
+                    _ => Map::new(),
+                };
+                let (bundle, summary) = profile::export(
+                    &data_dir()?,
+                    &saved_ids(),
+                    &teams,
+                    data.get("machineNames"),
+                    &password,
+                )?;
+                std::fs::write(&output, bundle)
+                    .map_err(|e| io_error(output.to_string_lossy(), e))?;
+                println!(
+                    "Exported {} accounts and {} storage entries to {}",
+                    summary.accounts.len(),
+                    summary.entries,
+                    output.display()
+                );
+                for email in &summary.missing_identities {
+                    eprintln!(
+                        "No signing key saved for {}, it needs a new certificate after import",
+                        email
+                    );
+                }
+            }
+            ProfileCommand::Import { input, overwrite } => {
+                let bundle =
+                    std::fs::read(&input).map_err(|e| io_error(input.to_string_lossy(), e))?;
+                let password = profile_password()?;
+                let imported = profile::import(&data_dir()?, &bundle, &password, overwrite)?;
+                for id in &imported.ids {
+                    add_stored_id(id)?;
+                }
+                update_data(|data| {
+                    let teams = data
+                        .entry("teams")
+                        .or_insert_with(|| Value::Object(Map::new()));
+                    if !teams.is_object() {
+                        *teams = Value::Object(Map::new());
+                    }
+                    let teams = teams.as_object_mut().unwrap();
+                    for (email, team_id) in &imported.teams {
+                        if overwrite || !teams.contains_key(email) {
+                            teams.insert(email.clone(), Value::String(team_id.clone()));
+                        }
+                    }
+                    let names = data
+                        .entry("machineNames")
+                        .or_insert_with(|| Value::Object(Map::new()));
+                    if !names.is_object() {
+                        *names = Value::Object(Map::new());
+                    }
+                    let names = names.as_object_mut().unwrap();
+                    for (email, machine_name) in &imported.machine_names {
+                        if overwrite || !names.contains_key(email) {
+                            names.insert(email.clone(), Value::String(machine_name.clone()));
+                        }
+                    }
+                })?;
+                println!(
+                    "Imported {} accounts and {} storage entries ({} already present)",
+                    imported.summary.accounts.len(),
+                    imported.summary.entries,
+                    imported.summary.skipped
+                );
+            }
+        },
+    }
+    Ok(())
+}
+
+fn data_dir() -> Result<PathBuf, IloaderError> {
+    dirs::data_dir()
+        .map(|dir| dir.join(APP_IDENTIFIER))
+        .ok_or_else(|| io_error("app data directory", std::io::Error::other("not found")))
+}
+
+fn io_error(path: impl Into<String>, source: std::io::Error) -> IloaderError {
+    IloaderError::Io {
+        path: path.into(),
+        source,
+    }
+}
+
+fn password(account: &AccountArgs) -> Result<String, IloaderError> {
+    if let Some(password) = &account.password {
+        return Ok(password.clone());
+    }
+    if let Ok(password) = stored_password(&account.email) {
+        return Ok(password);
+    }
+    rpassword::prompt_password(format!("Password for {}: ", account.email))
+        .map_err(|e| io_error("stdin", e))
+}
+
+fn p12_password() -> Result<String, IloaderError> {
+    if let Ok(password) = std::env::var("ILOADER_P12_PASSWORD") {
+        return Ok(password);
+    }
+    rpassword::prompt_password(".p12 password: ").map_err(|e| io_error("stdin", e))
+}
+
+fn profile_password() -> Result<String, IloaderError> {
+    if let Ok(password) = std::env::var("ILOADER_PROFILE_PASSWORD") {
+        return Ok(password);
+    }
+    rpassword::prompt_password("Profile password: ").map_err(|e| io_error("stdin", e))
+}
+
+// Logs in and remembers the team that was used, like the GUI does. With `resume`, the tokens saved
+// by the last login are tried first so the password and 2FA code are only needed if they expired
+async fn login_account(
+    account: &AccountArgs,
+    password: &str,
+    resume: bool,
+) -> Result<Sideloader, IloaderError> {
+    let email = account.email.to_lowercase();
+    let remembered_team = account.team.clone().or_else(|| {
+        read_data()
+            .get("teams")?
+            .get(&email)?
+            .as_str()
+            .map(str::to_string)
+    });
+
+    let result = login(
+        LoginRequest {
+            data_dir: &data_dir()?,
+            email: &account.email,
+            password,
+            anisette_server: account.anisette_server.clone(),
+            remembered_team: remembered_team.as_deref(),
+            resume,
+            max_certs_policy: account.max_certs_policy(),
+            machine_name: account.machine_name()?,
+        },
+        Arc::new(TerminalPrompter {
+            timeouts: account.prompt_timeouts(),
+        }),
+    )
+    .await?;
+    match result.method {
+        LoginMethod::Resumed => eprintln!("Resumed saved session for {}", email),
+        LoginMethod::Password if result.resume_error.is_some() => {
+            eprintln!(
+                "Saved session for {} expired, signed in with password",
+                email
+            )
+        }
+        LoginMethod::Password => {}
+    }
+    if result.anisette_server != account.anisette_server {
+        eprintln!(
+            "Anisette server {} is unavailable, used {} instead",
+            account.anisette_server, result.anisette_server
+        );
+    }
+
+    update_data(|data| {
+        let teams = data
+            .entry("teams")
+            .or_insert_with(|| Value::Object(Default::default()));
+        if !teams.is_object() {
+            *teams = Value::Object(Default::default());
+        }
+        teams
+            .as_object_mut()
+            .unwrap()
+            .insert(email, Value::String(result.team.team_id));
+    })?;
+    Ok(result.sideloader)
+}
+
+struct TerminalPrompter {
+    timeouts: PromptTimeouts,
+}
+
+impl LoginPrompter for TerminalPrompter {
+    fn two_factor(&self, request: &TwoFactorRequest) -> Result<TwoFactorResponse, PromptError> {
+        if request.resent {
+            eprintln!("A new 2FA code was sent.");
+        }
+        let answer = prompt(
+            "Enter the 2FA code, \"resend\" for a new one, or nothing to cancel: ",
+            self.timeouts.two_factor,
+        )?;
+
+        if answer.is_empty() {
+            Err(PromptError::Cancelled)
+        } else if answer.eq_ignore_ascii_case("resend") {
+            Ok(TwoFactorResponse::Resend)
+        } else {
+            Ok(TwoFactorResponse::Code(answer))
+        }
+    }
+
+    fn certificates_to_revoke(
+        &self,
+        certs: &[CertificateInfo],
+    ) -> Result<Vec<String>, PromptError> {
+        prompt_max_certs(certs, self.timeouts.selection)
+    }
+
+    fn select_team(&self, teams: &[TeamInfo]) -> Result<String, PromptError> {
+        eprintln!("This account belongs to multiple developer teams:");
+        for (i, team) in teams.iter().enumerate() {
+            eprintln!(
+                "  [{}] {} - {} ({})",
+                i + 1,
+                team.name.as_deref().unwrap_or("Unknown"),
+                team.team_type.as_deref().unwrap_or("Unknown"),
+                team.team_id,
+            );
+        }
+        let answer = prompt(
+            "Team to use (number, or empty to cancel): ",
+            self.timeouts.selection,
+        )?;
+        answer
+            .parse::<usize>()
+            .ok()
+            .and_then(|i| i.checked_sub(1))
+            .and_then(|i| teams.get(i))
+            .map(|team| team.team_id.clone())
+            .ok_or(PromptError::Cancelled)
+    }
+}
+
+fn prompt_max_certs(
+    certs: &[CertificateInfo],
+    timeout: Duration,
+) -> Result<Vec<String>, PromptError> {
+    eprintln!("The maximum number of development certificates has been reached:");
+    for (i, cert) in certs.iter().enumerate() {
+        eprintln!(
+            "  [{}] {} - {}{} ({}, expires {})",
+            i + 1,
+            cert.name.as_deref().unwrap_or("Unknown"),
+            cert.machine_name.as_deref().unwrap_or("Unknown"),
+            if cert.this_machine {
+                " (this machine)"
+            } else {
+                ""
+            },
+            cert.serial_number.as_deref().unwrap_or("Unknown"),
+            cert.expires.as_deref().unwrap_or("unknown"),
+        );
+    }
+    let answer = prompt(
+        "Certificates to revoke (comma separated numbers, \"all\", or empty to cancel): ",
+        timeout,
+    )?;
+    if answer.is_empty() {
+        return Err(PromptError::Cancelled);
+    }
+
+    let selected: Vec<&CertificateInfo> = if answer.eq_ignore_ascii_case("all") {
+        certs.iter().collect()
+    } else {
+        answer
+            .split(',')
+            .filter_map(|i| i.trim().parse::<usize>().ok())
+            .filter_map(|i| certs.get(i.checked_sub(1)?))
+            .collect()
+    };
+    let serials: Vec<String> = selected
+        .into_iter()
+        .filter_map(|cert| cert.serial_number.clone())
+        .collect();
+
+    if serials.is_empty() {
+        Err(PromptError::Cancelled)
+    } else {
+        Ok(serials)
+    }
+}
+
+fn prompt(message: &str, timeout: Duration) -> Result<String, PromptError> {
+    eprint!("{}", message);
+    std::io::stderr()
+        .flush()
+        .map_err(|_| PromptError::Cancelled)?;
+    // stdin can't be read with a timeout, so the read gets its own thread, which is left blocked
+    // if nobody answers. The command fails at that point, so nothing else reads stdin after it
+    let (tx, rx) = mpsc::channel();
+    std::thread::spawn(move || {
+        let mut line = String::new();
+        let read = std::io::stdin().lock().read_line(&mut line);
+        let _ = tx.send(read.map(|_| line));
+    });
+    match rx.recv_timeout(timeout) {
+        Ok(Ok(line)) => Ok(line.trim().to_string()),
+        Ok(Err(_)) => Err(PromptError::Cancelled),
+        Err(_) => {
+            eprintln!();
+            Err(PromptError::TimedOut)
+        }
+    }
+}
+
+async fn select_device(args: &DeviceArgs) -> Result<DeviceInfo, IloaderError> {
+    let devices = connected_devices(&UsbmuxdBackend).await?;
+    match &args.device {
+        Some(udid) => devices
+            .into_iter()
+            .find(|d| &d.uuid == udid)
+            .ok_or_else(|| IloaderError::DeviceNotFound {
+                udid: udid.clone(),
+                source: None,
+            }),
+        None => match devices.len() {
+            0 => Err(IloaderError::NoDeviceSelected),
+            1 => Ok(devices.into_iter().next().unwrap()),
+            _ => {
+                eprintln!("Multiple devices connected, pick one with --device:");
+                for device in &devices {
+                    eprintln!("  {}\t{}", device.uuid, device.name);
+                }
+                Err(IloaderError::NoDeviceSelected)
+            }
+        },
+    }
+}
+
+fn data_path() -> Result<PathBuf, IloaderError> {
+    Ok(data_dir()?.join("data.json"))
+}
+
+// The store the GUI keeps in data.json, or an empty object if it doesn't exist yet
+// An empty name goes back to the default
+fn save_machine_name(email: &str, name: &str) -> Result<(), IloaderError> {
+    let email = email.to_lowercase();
+    let name = name.trim();
+    update_data(|data| {
+        let names = data
+            .entry("machineNames")
+            .or_insert_with(|| Value::Object(Default::default()));
+        if !names.is_object() {
+            *names = Value::Object(Default::default());
+        }
+        let names = names.as_object_mut().unwrap();
+        if name.is_empty() {
+            names.remove(&email);
+        } else {
+            names.insert(email, Value::String(name.to_string()));
+        }
+    })
+}
+
+fn read_data() -> Map<String, Value> {
+    data_path()
+        .ok()
+        .and_then(|path| std::fs::read_to_string(path).ok())
+        .and_then(|s| serde_json::from_str::<Value>(&s).ok())
+        .and_then(|data| match data {
+            Value::Object(map) => Some(map),
+            _ => None,
+        })
+        .unwrap_or_default()
+}
+
+fn update_data(f: impl FnOnce(&mut Map<String, Value>)) -> Result<(), IloaderError> {
+    let dir = data_dir()?;
+    std::fs::create_dir_all(&dir).map_err(|e| io_error(dir.to_string_lossy(), e))?;
+    let path = data_path()?;
+
+    let mut data = read_data();
+    f(&mut data);
+
+    let data = serde_json::to_string_pretty(&data).map_err(std::io::Error::from);
+    data.and_then(|data| std::fs::write(&path, data))
+        .map_err(|e| io_error(path.to_string_lossy(), e))
+}
+
+fn saved_ids() -> Vec<String> {
+    read_data()
+        .get("ids")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(|id| id.as_str().map(str::to_string))
+        .collect()
+}
+
+// Saved accounts and accounts with a remembered team
+fn known_emails() -> Vec<String> {
+    let mut emails = saved_ids();
+    if let Some(Value::Object(teams)) = read_data().get("teams") {
+        emails.extend(teams.keys().cloned());
+    }
+    emails
+}
+
+// The CLI side of delete_account
+fn forget_account(email: &str) -> Result<(), IloaderError> {
+    let password_deleted = delete_password(email);
+    update_data(|data| {
+        if let Some(Value::Array(ids)) = data.get_mut("ids") {
+            ids.retain(|v| v.as_str().is_none_or(|s| s != email));
+        }
+        for key in ["teams", "machineNames"] {
+            if let Some(Value::Object(map)) = data.get_mut(key) {
+                map.remove(&email.to_lowercase());
+            }
+        }
+    })?;
+    let data_dir = data_dir()?;
+    forget_session_tokens(&data_dir, email);
+    if let Err(e) = anisette::reset_state(&data_dir, email) {
+        eprintln!("Unable to delete anisette state: {}", e.details());
+    }
+    password_deleted
+}
+
+// Mirrors the `ids` list the GUI keeps in data.json so saved accounts show up in both
+fn add_stored_id(email: &str) -> Result<(), IloaderError> {
+    update_data(|data| {
+        let ids = data.entry("ids").or_insert_with(|| Value::Array(vec![]));
+        if !ids.is_array() {
+            *ids = Value::Array(vec![]);
+        }
+        let ids = ids.as_array_mut().unwrap();
+        let value = Value::String(email.to_string());
+        if !ids.contains(&value) {
+            ids.push(value);
+        }
+    })
+}
@@ -0,0 +1,493 @@
+use std::path::Path;
+
+use chrono::{DateTime, NaiveDate, Utc};
+use isideload::{
+    dev::{
+        certificates::{CertificatesApi, DevelopmentCertificate},
+        teams::DeveloperTeam,
+    },
+    sideload::sideloader::Sideloader,
+    util::storage::SideloadingStorage,
+};
+use p12_keystore::{Certificate, KeyStore, KeyStoreEntry, PrivateKeyChain};
+use rsa::{RsaPrivateKey, pkcs1::EncodeRsaPublicKey, pkcs8::DecodePrivateKey};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tauri::{AppHandle, Manager, State};
+use tauri_plugin_dialog::DialogExt;
+use tracing::{info, warn};
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+use crate::{
+    account::{
+        CERTIFICATE_WARN_DAYS, CertificateInfo, certificate_infos, save_machine_name, storage,
+        stored_machine_name,
+    },
+    error::IloaderError,
+    sideload::SessionRegistry,
+};
+
+// Where isideload keeps the private key an Apple ID requests its certificates with, as PKCS#8 DER
+// through store_data. It's named after the SHA-256 of the email the Sideloader was built with,
+// which login lowercases
+pub(crate) fn signing_key_key(email: &str) -> String {
+    let hash: String = Sha256::digest(email.to_lowercase().as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    format!("{}/key", hash)
+}
+
+fn cert_err<E: std::fmt::Display>(action: &'static str) -> impl Fn(E) -> IloaderError {
+    move |e| IloaderError::Certificate {
+        action,
+        report: e.to_string(),
+    }
+}
+
+// Serial numbers the way the developer services list them: uppercase hex without separators
+pub fn serial_number(cert_der: &[u8]) -> Result<String, IloaderError> {
+    let (_, cert) = X509Certificate::from_der(cert_der).map_err(cert_err("read certificate"))?;
+    Ok(cert.tbs_certificate.serial.to_str_radix(16).to_uppercase())
+}
+
+pub fn same_serial(a: &str, b: &str) -> bool {
+    a.trim_start_matches('0')
+        .eq_ignore_ascii_case(b.trim_start_matches('0'))
+}
+
+// When the certificate became valid and when it expires
+pub fn validity(cert_der: &[u8]) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+    let (_, cert) = X509Certificate::from_der(cert_der).ok()?;
+    let validity = cert.validity();
+    Some((
+        DateTime::from_timestamp(validity.not_before.timestamp(), 0)?,
+        DateTime::from_timestamp(validity.not_after.timestamp(), 0)?,
+    ))
+}
+
+// The account's signing key, if isideload has created one
+pub fn stored_signing_key(
+    storage: &dyn SideloadingStorage,
+    email: &str,
+) -> Result<Option<Vec<u8>>, IloaderError> {
+    storage
+        .retrieve_data(&signing_key_key(email))
+        .map_err(cert_err("read signing key"))
+}
+
+// The PKCS#1 public key of a PKCS#8 RSA private key, which is what a certificate issued for it
+// holds as its subject public key
+pub fn public_key(key_der: &[u8]) -> Result<Vec<u8>, IloaderError> {
+    let key = RsaPrivateKey::from_pkcs8_der(key_der).map_err(cert_err("read private key"))?;
+    let public_key = key
+        .to_public_key()
+        .to_pkcs1_der()
+        .map_err(cert_err("read private key"))?;
+    Ok(public_key.as_bytes().to_vec())
+}
+
+fn certificate_public_key(cert_der: &[u8]) -> Option<Vec<u8>> {
+    let (_, cert) = X509Certificate::from_der(cert_der).ok()?;
+    Some(cert.public_key().subject_public_key.data.to_vec())
+}
+
+// The team certificate issued for the signing key. isideload signs with it as long as it's
+// registered under the Sideloader's machine name, and requests a new one otherwise
+pub fn signing_certificate<'a>(
+    certificates: &'a [DevelopmentCertificate],
+    public_key: &[u8],
+) -> Option<&'a DevelopmentCertificate> {
+    certificates.iter().find(|cert| {
+        cert.cert_content
+            .as_ref()
+            .and_then(|content| certificate_public_key(content.as_ref()))
+            .is_some_and(|key| key == public_key)
+    })
+}
+
+// The serial number of the team certificate this install holds the key of, if there is one
+pub fn stored_serial(
+    data_dir: &Path,
+    email: &str,
+    certificates: &[DevelopmentCertificate],
+) -> Option<String> {
+    let key = stored_signing_key(storage(data_dir).as_ref(), email).ok()??;
+    let public_key = public_key(&key).ok()?;
+    signing_certificate(certificates, &public_key)?
+        .serial_number
+        .clone()
+}
+
+async fn team_certificates(
+    sideloader: &mut Sideloader,
+) -> Result<(DeveloperTeam, Vec<DevelopmentCertificate>), IloaderError> {
+    let team = sideloader
+        .get_team()
+        .await
+        .map_err(|e| IloaderError::DeveloperServices {
+            action: "get team",
+            report: e.to_string(),
+        })?;
+    let certificates = sideloader
+        .get_dev_session()
+        .list_all_development_certs(&team, None)
+        .await
+        .map_err(|e| IloaderError::DeveloperServices {
+            action: "list development certificates",
+            report: e.to_string(),
+        })?;
+    Ok((team, certificates))
+}
+
+// Makes the identity in a PKCS#12 file the one the sideloader's account signs with, replacing the
+// key isideload created. The certificate has to be one of the team's development certificates
+// that hasn't been revoked. isideload only picks it under the machine name it was registered
+// with, which the caller saves as the account's machine name for the next login
+pub async fn import_identity(
+    sideloader: &mut Sideloader,
+    data_dir: &Path,
+    p12: &[u8],
+    password: &str,
+) -> Result<CertificateInfo, IloaderError> {
+    let keystore = KeyStore::from_pkcs12(p12, password).map_err(cert_err("read .p12 file"))?;
+    let (_, chain) = keystore
+        .private_key_chain()
+        .ok_or_else(|| IloaderError::Certificate {
+            action: "read .p12 file",
+            report: "The file doesn't contain a private key".to_string(),
+        })?;
+    let cert = chain
+        .chain()
+        .first()
+        .ok_or_else(|| IloaderError::Certificate {
+            action: "read .p12 file",
+            report: "The file doesn't contain a certificate".to_string(),
+        })?;
+    if certificate_public_key(cert.as_der()) != Some(public_key(chain.key())?) {
+        return Err(IloaderError::Certificate {
+            action: "read .p12 file",
+            report: "The private key doesn't belong to the certificate".to_string(),
+        });
+    }
+    let serial = serial_number(cert.as_der())?;
+
+    let (team, certificates) = team_certificates(sideloader).await?;
+    let certificate = certificates
+        .iter()
+        .find(|c| {
+            c.serial_number
+                .as_deref()
+                .is_some_and(|s| same_serial(s, &serial))
+        })
+        .ok_or_else(|| IloaderError::CertificateNotOnTeam {
+            serial: serial.clone(),
+        })?;
+
+    let email = sideloader.get_email().to_string();
+    storage(data_dir)
+        .store_data(&signing_key_key(&email), chain.key())
+        .map_err(cert_err("store signing key"))?;
+
+    info!(
+        "Imported certificate {} of machine {} for {} in team {}",
+        serial,
+        certificate.machine_name.as_deref().unwrap_or("unknown"),
+        email,
+        team.team_id
+    );
+    let mut info = CertificateInfo::from(certificate);
+    info.in_local_storage = true;
+    Ok(info)
+}
+
+// The account's signing key with the team certificate issued for it, as a password protected
+// PKCS#12 file
+pub async fn export_identity(
+    sideloader: &mut Sideloader,
+    data_dir: &Path,
+    password: &str,
+) -> Result<Vec<u8>, IloaderError> {
+    let key = stored_signing_key(storage(data_dir).as_ref(), sideloader.get_email())?
+        .ok_or(IloaderError::NoSigningIdentity)?;
+    let (_, certificates) = team_certificates(sideloader).await?;
+    let cert = signing_certificate(&certificates, &public_key(&key)?)
+        .and_then(|cert| cert.cert_content.as_ref())
+        .ok_or(IloaderError::NoSigningIdentity)?;
+    let serial = serial_number(cert.as_ref())?;
+
+    let cert = Certificate::from_der(cert.as_ref()).map_err(cert_err("read certificate"))?;
+    let chain = PrivateKeyChain::new(key, serial.as_bytes(), vec![cert]);
+    let mut keystore = KeyStore::new();
+    keystore.add_entry("iloader", KeyStoreEntry::PrivateKeyChain(chain));
+    keystore
+        .writer(password)
+        .write()
+        .map_err(cert_err("write .p12 file"))
+}
+
+// Which certificates revoke_certificates applies to
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum RevokeFilter {
+    #[serde(rename_all = "camelCase")]
+    MachineName {
+        machine_name: String,
+    },
+    // `*` matches any text and `?` any one character, ignoring case
+    NamePattern {
+        pattern: String,
+    },
+    // RFC 3339 or YYYY-MM-DD. Certificates whose creation date is unknown never match
+    CreatedBefore {
+        date: String,
+    },
+    // Refused when this install has no certificate, since that would revoke everything
+    AllExceptLocal,
+}
+
+fn parse_date(date: &str) -> Result<DateTime<Utc>, IloaderError> {
+    if let Ok(date) = DateTime::parse_from_rfc3339(date) {
+        return Ok(date.with_timezone(&Utc));
+    }
+    NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .map(|date| date.and_hms_opt(0, 0, 0).unwrap().and_utc())
+        .map_err(cert_err("read date"))
+}
+
+fn glob_match(pattern: &[char], text: &[char]) -> bool {
+    match (pattern.first(), text.first()) {
+        (None, _) => text.is_empty(),
+        (Some('*'), _) => {
+            glob_match(&pattern[1..], text) || (!text.is_empty() && glob_match(pattern, &text[1..]))
+        }
+        (Some('?'), Some(_)) => glob_match(&pattern[1..], &text[1..]),
+        (Some(p), Some(t)) => p == t && glob_match(&pattern[1..], &text[1..]),
+        (Some(_), None) => false,
+    }
+}
+
+type Matcher = Box<dyn Fn(&CertificateInfo) -> bool>;
+
+impl RevokeFilter {
+    fn matcher(&self, local_serial: Option<&str>) -> Result<Matcher, IloaderError> {
+        let matcher: Matcher = match self {
+            RevokeFilter::MachineName { machine_name } => {
+                let machine_name = machine_name.trim().to_string();
+                Box::new(move |cert: &CertificateInfo| {
+                    cert.machine_name
+                        .as_deref()
+                        .is_some_and(|name| name.eq_ignore_ascii_case(&machine_name))
+                })
+            }
+            RevokeFilter::NamePattern { pattern } => {
+                let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+                Box::new(move |cert: &CertificateInfo| {
+                    cert.name.as_deref().is_some_and(|name| {
+                        glob_match(&pattern, &name.to_lowercase().chars().collect::<Vec<_>>())
+                    })
+                })
+            }
+            RevokeFilter::CreatedBefore { date } => {
+                let before = parse_date(date)?;
+                Box::new(move |cert: &CertificateInfo| {
+                    cert.created
+                        .as_deref()
+                        .and_then(|created| DateTime::parse_from_rfc3339(created).ok())
+                        .is_some_and(|created| created < before)
+                })
+            }
+            RevokeFilter::AllExceptLocal => {
+                let Some(local_serial) = local_serial.map(str::to_string) else {
+                    return Err(IloaderError::NoSigningIdentity);
+                };
+                Box::new(move |cert: &CertificateInfo| {
+                    cert.serial_number
+                        .as_deref()
+                        .is_some_and(|serial| !same_serial(serial, &local_serial))
+                })
+            }
+        };
+        Ok(matcher)
+    }
+
+    // The certificates to revoke. `local_serial` is the serial number of the certificate issued for
+    // this install's signing key, as `stored_serial` finds it
+    pub fn select(
+        &self,
+        certs: Vec<CertificateInfo>,
+        local_serial: Option<&str>,
+    ) -> Result<Vec<CertificateInfo>, IloaderError> {
+        let matches = self.matcher(local_serial)?;
+        Ok(certs
+            .into_iter()
+            .filter(|cert| cert.serial_number.is_some() && matches(cert))
+            .collect())
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CertificateRevocation {
+    pub certificate: CertificateInfo,
+    // Always false on a dry run
+    pub revoked: bool,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RevokeCertificatesResponse {
+    pub dry_run: bool,
+    pub results: Vec<CertificateRevocation>,
+}
+
+// Revokes every team certificate the filter matches. A failure is recorded for that certificate
+// and the rest are still revoked. With `dry_run`, only lists what would be revoked
+pub async fn revoke_matching(
+    sideloader: &mut Sideloader,
+    data_dir: &Path,
+    machine_name: &str,
+    filter: &RevokeFilter,
+    dry_run: bool,
+) -> Result<RevokeCertificatesResponse, IloaderError> {
+    let (team, certificates) = team_certificates(sideloader).await?;
+    let local_serial = stored_serial(data_dir, sideloader.get_email(), &certificates);
+    let selected = filter.select(
+        certificate_infos(
+            &certificates,
+            local_serial.as_deref(),
+            Some(machine_name),
+            CERTIFICATE_WARN_DAYS,
+        ),
+        local_serial.as_deref(),
+    )?;
+
+    let dev_session = sideloader.get_dev_session();
+    let mut results = Vec::with_capacity(selected.len());
+    for certificate in selected {
+        let serial = certificate.serial_number.clone().unwrap_or_default();
+        if dry_run {
+            info!("Would revoke certificate {} ({:?})", serial, filter);
+            results.push(CertificateRevocation {
+                certificate,
+                revoked: false,
+                error: None,
+            });
+            continue;
+        }
+        let error = match dev_session
+            .revoke_development_cert(&team, &serial, None)
+            .await
+        {
+            Ok(_) => {
+                info!("Revoked certificate {} ({:?})", serial, filter);
+                None
+            }
+            Err(e) => {
+                warn!("Unable to revoke certificate {}: {}", serial, e);
+                Some(e.to_string())
+            }
+        };
+        results.push(CertificateRevocation {
+            certificate,
+            revoked: error.is_none(),
+            error,
+        });
+    }
+    Ok(RevokeCertificatesResponse { dry_run, results })
+}
+
+#[tauri::command]
+pub async fn revoke_certificates(
+    app: AppHandle,
+    sessions: State<'_, SessionRegistry>,
+    filter: RevokeFilter,
+    dry_run: bool,
+    email: Option<String>,
+) -> Result<RevokeCertificatesResponse, IloaderError> {
+    let data_dir = app
+        .path()
+        .app_data_dir()
+        .map_err(cert_err("revoke certificates"))?;
+    let mut sideloader = sessions.lock(email.as_deref()).await?;
+    let machine_name = stored_machine_name(&app, sideloader.get_mut().get_email());
+    revoke_matching(
+        sideloader.get_mut(),
+        &data_dir,
+        &machine_name,
+        &filter,
+        dry_run,
+    )
+    .await
+}
+
+#[tauri::command]
+pub async fn import_certificate(
+    app: AppHandle,
+    sessions: State<'_, SessionRegistry>,
+    password: String,
+    email: Option<String>,
+) -> Result<CertificateInfo, IloaderError> {
+    let open_path = app
+        .dialog()
+        .file()
+        .add_filter("PKCS#12", &["p12", "pfx"])
+        .set_title("Import Certificate")
+        .blocking_pick_file();
+    let Some(open_path) = open_path.as_ref().and_then(|path| path.as_path()) else {
+        return Err(IloaderError::SaveCancelled);
+    };
+    let p12 = tokio::fs::read(open_path)
+        .await
+        .map_err(|source| IloaderError::Io {
+            path: open_path.to_string_lossy().to_string(),
+            source,
+        })?;
+    let data_dir = app
+        .path()
+        .app_data_dir()
+        .map_err(cert_err("import certificate"))?;
+
+    let mut sideloader = sessions.lock(email.as_deref()).await?;
+    let certificate = import_identity(sideloader.get_mut(), &data_dir, &p12, &password).await?;
+    if let Some(machine_name) = &certificate.machine_name {
+        save_machine_name(&app, sideloader.get_mut().get_email(), machine_name)?;
+        info!("Signing with it under {} from the next login", machine_name);
+    }
+    Ok(certificate)
+}
+
+#[tauri::command]
+pub async fn export_certificate(
+    app: AppHandle,
+    sessions: State<'_, SessionRegistry>,
+    password: String,
+    email: Option<String>,
+) -> Result<(), IloaderError> {
+    let data_dir = app
+        .path()
+        .app_data_dir()
+        .map_err(cert_err("export certificate"))?;
+    let p12 = {
+        let mut sideloader = sessions.lock(email.as_deref()).await?;
+        export_identity(sideloader.get_mut(), &data_dir, &password).await?
+    };
+
+    let save_path = app
+        .dialog()
+        .file()
+        .add_filter("PKCS#12", &["p12"])
+        .set_file_name("iloader.p12")
+        .set_title("Export Certificate")
+        .blocking_save_file();
+    let Some(save_path) = save_path.as_ref().and_then(|path| path.as_path()) else {
+        return Err(IloaderError::SaveCancelled);
+    };
+    tokio::fs::write(save_path, &p12)
+        .await
+        .map_err(|source| IloaderError::Io {
+            path: save_path.to_string_lossy().to_string(),
+            source,
+        })
+}
@@ -0,0 +1,150 @@
+use std::{collections::BTreeSet, fs, path::Path};
+
+use keyring::Entry;
+use serde::Serialize;
+use serde_json::Value;
+use tauri::{AppHandle, Manager};
+use tauri_plugin_store::StoreExt;
+use tracing::warn;
+
+use crate::{account::keyring_available, error::IloaderError, storage_migration::known_emails};
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CredentialError {
+    pub email: String,
+    pub error: String,
+}
+
+// Saved accounts are an entry in the `ids` list of data.json plus a password in the keyring.
+// This is where the two disagree
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CredentialsCheck {
+    pub keyring_available: bool,
+    // Saved accounts without a password, which can't sign in
+    pub missing_passwords: Vec<String>,
+    // Passwords for accounts that aren't in the saved list
+    pub orphaned_passwords: Vec<String>,
+    pub unreadable: Vec<CredentialError>,
+}
+
+impl CredentialsCheck {
+    pub fn is_consistent(&self) -> bool {
+        self.missing_passwords.is_empty()
+            && self.orphaned_passwords.is_empty()
+            && self.unreadable.is_empty()
+    }
+}
+
+// The keyring can't list its entries, so passwords are only found for accounts iloader has seen:
+// those with a remembered team or anisette data
+pub fn check(data_dir: &Path, ids: &[String], known: &[String]) -> CredentialsCheck {
+    let mut check = CredentialsCheck {
+        keyring_available: keyring_available(),
+        ..Default::default()
+    };
+    if !check.keyring_available {
+        return check;
+    }
+
+    let saved: BTreeSet<String> = ids.iter().map(|id| id.to_lowercase()).collect();
+    let mut others: BTreeSet<String> = known
+        .iter()
+        .map(|email| email.to_lowercase())
+        .filter(|email| !saved.contains(email))
+        .collect();
+    if let Ok(entries) = fs::read_dir(data_dir.join("anisette")) {
+        others.extend(
+            entries
+                .flatten()
+                .map(|entry| entry.file_name().to_string_lossy().to_string())
+                .filter(|email| !saved.contains(email)),
+        );
+    }
+
+    for id in ids {
+        match Entry::new("iloader", id).and_then(|entry| entry.get_password()) {
+            Ok(_) => {}
+            Err(keyring::Error::NoEntry) => check.missing_passwords.push(id.clone()),
+            Err(e) => check.unreadable.push(CredentialError {
+                email: id.clone(),
+                error: e.to_string(),
+            }),
+        }
+    }
+    for email in others {
+        if Entry::new("iloader", &email)
+            .and_then(|entry| entry.get_password())
+            .is_ok()
+        {
+            check.orphaned_passwords.push(email);
+        }
+    }
+
+    for id in &check.missing_passwords {
+        warn!("Saved account {} has no password in the keyring", id);
+    }
+    for email in &check.orphaned_passwords {
+        warn!(
+            "Found a saved password for {}, which isn't a saved account",
+            email
+        );
+    }
+    for failure in &check.unreadable {
+        warn!(
+            "Unable to read the password of {}: {}",
+            failure.email, failure.error
+        );
+    }
+    check
+}
+
+fn saved_ids(handle: &AppHandle) -> Result<Vec<String>, IloaderError> {
+    let store = handle.store("data.json").map_err(IloaderError::Store)?;
+    Ok(store
+        .get("ids")
+        .and_then(|ids| ids.as_array().cloned())
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|id| id.as_str().map(str::to_string))
+        .collect())
+}
+
+pub fn check_app(handle: &AppHandle) -> Result<CredentialsCheck, IloaderError> {
+    let data_dir = handle
+        .path()
+        .app_data_dir()
+        .unwrap_or_else(|_| "Failed to get app data directory".into());
+    Ok(check(&data_dir, &saved_ids(handle)?, &known_emails(handle)))
+}
+
+// Missing passwords are repaired by signing in again with "save credentials", or pruned with
+// delete_account. Orphaned passwords are pruned with delete_account, or restored here
+#[tauri::command]
+pub fn check_saved_credentials(handle: AppHandle) -> Result<CredentialsCheck, IloaderError> {
+    check_app(&handle)
+}
+
+// Adds an account with a saved password back to the saved list
+#[tauri::command]
+pub fn restore_saved_account(handle: AppHandle, email: String) -> Result<(), IloaderError> {
+    Entry::new("iloader", &email)
+        .and_then(|entry| entry.get_password())
+        .map_err(|source| IloaderError::Keyring {
+            account: email.clone(),
+            source,
+        })?;
+
+    let store = handle.store("data.json").map_err(IloaderError::Store)?;
+    let mut ids = store
+        .get("ids")
+        .and_then(|ids| ids.as_array().cloned())
+        .unwrap_or_default();
+    let value = Value::String(email);
+    if !ids.contains(&value) {
+        ids.push(value);
+    }
+    store.set("ids", Value::Array(ids));
+    Ok(())
+}
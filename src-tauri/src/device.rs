@@ -1,15 +1,18 @@
 use std::sync::Mutex;
 
 use idevice::{
-    lockdown::LockdownClient,
     provider::UsbmuxdProvider,
-    usbmuxd::{Connection, UsbmuxdAddr, UsbmuxdConnection},
-    IdeviceService,
+    usbmuxd::{UsbmuxdAddr, UsbmuxdConnection},
 };
 use serde::{Deserialize, Serialize};
 use tauri::State;
 
-#[derive(Deserialize, Serialize, Clone)]
+use crate::{
+    device_backend::{DeviceBackend, DeviceBackendState},
+    error::IloaderError,
+};
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
 #[serde(rename_all = "camelCase")]
 pub struct DeviceInfo {
     pub name: String,
@@ -21,57 +24,36 @@ pub struct DeviceInfo {
 pub type DeviceInfoMutex = Mutex<Option<DeviceInfo>>;
 
 #[tauri::command]
-pub async fn list_devices() -> Result<Vec<DeviceInfo>, String> {
-    let usbmuxd = UsbmuxdConnection::default().await;
-    if usbmuxd.is_err() {
-        eprintln!("Failed to connect to usbmuxd: {:?}", usbmuxd.err());
-        return Err("Failed to connect to usbmuxd".to_string());
-    }
-    let mut usbmuxd = usbmuxd.unwrap();
+pub async fn list_devices(
+    backend: State<'_, DeviceBackendState>,
+) -> Result<Vec<DeviceInfo>, IloaderError> {
+    connected_devices(backend.as_ref()).await
+}
 
-    let devs = usbmuxd.get_devices().await.unwrap();
+pub async fn connected_devices(
+    backend: &dyn DeviceBackend,
+) -> Result<Vec<DeviceInfo>, IloaderError> {
+    let devs = backend.devices().await?;
     if devs.is_empty() {
         return Ok(vec![]);
     }
 
     let device_info_futures: Vec<_> = devs
-        .iter()
+        .into_iter()
         .map(|d| async move {
-            let provider = d.to_provider(UsbmuxdAddr::from_env_var().unwrap(), "iloader");
-            let device_uid = d.device_id;
-            let connection_type = match d.connection_type {
-                Connection::Usb => "USB",
-                Connection::Network(_) => "Network",
-                Connection::Unknown(_) => "Unknown",
-            }
-            .to_string();
-
-            let mut lockdown_client = match LockdownClient::connect(&provider).await {
-                Ok(l) => l,
+            let name = match backend.get_value(&d.udid, "DeviceName", None).await {
+                Ok(name) => name.as_string().unwrap_or("Unknown Device").to_string(),
                 Err(e) => {
-                    eprintln!("Unable to connect to lockdown: {e:?}");
-                    return DeviceInfo {
-                        connection_type,
-                        name: String::from("Unknown Device"),
-                        id: device_uid,
-                        uuid: d.udid.clone(),
-                    };
+                    eprintln!("Unable to get device name: {e:?}");
+                    String::from("Unknown Device")
                 }
             };
 
-            let device_name = lockdown_client
-                .get_value(Some("DeviceName"), None)
-                .await
-                .expect("Failed to get device name")
-                .as_string()
-                .expect("Failed to convert device name to string")
-                .to_string();
-
             DeviceInfo {
-                name: device_name,
-                id: device_uid,
-                uuid: d.udid.clone(),
-                connection_type,
+                name,
+                id: d.id,
+                uuid: d.udid,
+                connection_type: d.connection_type,
             }
         })
         .collect();
@@ -83,29 +65,42 @@ pub async fn list_devices() -> Result<Vec<DeviceInfo>, String> {
 pub async fn set_selected_device(
     device_state: State<'_, DeviceInfoMutex>,
     device: Option<DeviceInfo>,
-) -> Result<(), String> {
+) -> Result<(), IloaderError> {
     let mut device_state = device_state.lock().unwrap();
     *device_state = device;
     Ok(())
 }
 
-pub async fn get_provider(device_info: &DeviceInfo) -> Result<UsbmuxdProvider, String> {
-    let mut usbmuxd = UsbmuxdConnection::default()
+// Honors USBMUXD_SOCKET_ADDRESS, so usbmuxd can be swapped out (e.g. for the emulator in tests)
+pub fn usbmuxd_addr() -> Result<UsbmuxdAddr, IloaderError> {
+    UsbmuxdAddr::from_env_var().map_err(|e| IloaderError::InvalidUsbmuxdAddress(e.to_string()))
+}
+
+pub async fn usbmuxd_connection() -> Result<UsbmuxdConnection, IloaderError> {
+    usbmuxd_addr()?
+        .connect(0)
         .await
-        .map_err(|e| format!("Failed to connect to usbmuxd: {}", e))?;
+        .map_err(IloaderError::UsbmuxdUnreachable)
+}
+
+pub async fn get_provider(udid: &str) -> Result<UsbmuxdProvider, IloaderError> {
+    let mut usbmuxd = usbmuxd_connection().await?;
 
-    get_provider_from_connection(device_info, &mut usbmuxd).await
+    get_provider_from_connection(udid, &mut usbmuxd).await
 }
 
 pub async fn get_provider_from_connection(
-    device_info: &DeviceInfo,
+    udid: &str,
     connection: &mut UsbmuxdConnection,
-) -> Result<UsbmuxdProvider, String> {
+) -> Result<UsbmuxdProvider, IloaderError> {
     let device = connection
-        .get_device(&device_info.uuid)
+        .get_device(udid)
         .await
-        .map_err(|e| format!("Failed to get device: {}", e))?;
+        .map_err(|e| IloaderError::DeviceNotFound {
+            udid: udid.to_string(),
+            source: Some(e),
+        })?;
 
-    let provider = device.to_provider(UsbmuxdAddr::from_env_var().unwrap(), "iloader");
+    let provider = device.to_provider(usbmuxd_addr()?, "iloader");
     Ok(provider)
 }
@@ -0,0 +1,240 @@
+use std::{collections::HashMap, sync::Arc};
+
+use async_trait::async_trait;
+use idevice::{
+    IdeviceService,
+    afc::opcode::AfcFopenMode,
+    house_arrest::HouseArrestClient,
+    installation_proxy::InstallationProxyClient,
+    lockdown::LockdownClient,
+    usbmuxd::{Connection, UsbmuxdConnection},
+};
+
+use crate::{
+    device::{get_provider, get_provider_from_connection, usbmuxd_connection},
+    error::IloaderError,
+};
+
+#[cfg(all(unix, feature = "test-support"))]
+pub mod emulator;
+#[cfg(feature = "test-support")]
+pub mod fake;
+
+// A device as reported by usbmuxd, before anything has been read from it over lockdown
+#[derive(Debug, Clone)]
+pub struct ConnectedDevice {
+    pub id: u32,
+    pub udid: String,
+    pub connection_type: String,
+}
+
+// Everything iloader needs from a device, outside of installing apps (which isideload does itself)
+#[async_trait]
+pub trait DeviceBackend: Send + Sync {
+    async fn devices(&self) -> Result<Vec<ConnectedDevice>, IloaderError>;
+
+    async fn get_value(
+        &self,
+        udid: &str,
+        key: &str,
+        domain: Option<&str>,
+    ) -> Result<plist::Value, IloaderError>;
+
+    // Runs inside a lockdown session started with the device's pair record
+    async fn set_value(
+        &self,
+        udid: &str,
+        key: &str,
+        value: plist::Value,
+        domain: Option<&str>,
+    ) -> Result<(), IloaderError>;
+
+    // The serialized pairing file, with the device's UDID filled in
+    async fn pair_record(&self, udid: &str) -> Result<Vec<u8>, IloaderError>;
+
+    // User apps keyed by bundle ID, as returned by installation_proxy
+    async fn installed_apps(
+        &self,
+        udid: &str,
+    ) -> Result<HashMap<String, plist::Value>, IloaderError>;
+
+    // Writes a file relative to the Documents directory of an app, creating its parent directory
+    async fn write_document(
+        &self,
+        udid: &str,
+        bundle_id: &str,
+        path: &str,
+        contents: &[u8],
+    ) -> Result<(), IloaderError>;
+}
+
+pub type DeviceBackendState = Arc<dyn DeviceBackend>;
+
+pub struct UsbmuxdBackend;
+
+impl UsbmuxdBackend {
+    async fn connect(&self) -> Result<UsbmuxdConnection, IloaderError> {
+        usbmuxd_connection().await
+    }
+
+    async fn lockdown(&self, udid: &str) -> Result<LockdownClient, IloaderError> {
+        let provider = get_provider(udid).await?;
+        LockdownClient::connect(&provider)
+            .await
+            .map_err(|source| IloaderError::Lockdown {
+                action: "connect",
+                source,
+            })
+    }
+}
+
+#[async_trait]
+impl DeviceBackend for UsbmuxdBackend {
+    async fn devices(&self) -> Result<Vec<ConnectedDevice>, IloaderError> {
+        let devs = self
+            .connect()
+            .await?
+            .get_devices()
+            .await
+            .map_err(IloaderError::UsbmuxdUnreachable)?;
+
+        Ok(devs
+            .into_iter()
+            .map(|d| ConnectedDevice {
+                id: d.device_id,
+                connection_type: match d.connection_type {
+                    Connection::Usb => "USB",
+                    Connection::Network(_) => "Network",
+                    Connection::Unknown(_) => "Unknown",
+                }
+                .to_string(),
+                udid: d.udid,
+            })
+            .collect())
+    }
+
+    async fn get_value(
+        &self,
+        udid: &str,
+        key: &str,
+        domain: Option<&str>,
+    ) -> Result<plist::Value, IloaderError> {
+        self.lockdown(udid)
+            .await?
+            .get_value(Some(key), domain)
+            .await
+            .map_err(|source| IloaderError::Lockdown {
+                action: "get value",
+                source,
+            })
+    }
+
+    async fn set_value(
+        &self,
+        udid: &str,
+        key: &str,
+        value: plist::Value,
+        domain: Option<&str>,
+    ) -> Result<(), IloaderError> {
+        let mut usbmuxd = self.connect().await?;
+        let pairing_file =
+            usbmuxd
+                .get_pair_record(udid)
+                .await
+                .map_err(|source| IloaderError::PairRecord {
+                    device: udid.to_string(),
+                    source,
+                })?;
+
+        let mut lc = self.lockdown(udid).await?;
+        lc.start_session(&pairing_file)
+            .await
+            .map_err(|source| IloaderError::Lockdown {
+                action: "start session",
+                source,
+            })?;
+
+        lc.set_value(key, value, domain)
+            .await
+            .map_err(|source| IloaderError::Lockdown {
+                action: "set value",
+                source,
+            })
+    }
+
+    async fn pair_record(&self, udid: &str) -> Result<Vec<u8>, IloaderError> {
+        let pair_record_err = |source| IloaderError::PairRecord {
+            device: udid.to_string(),
+            source,
+        };
+
+        let mut pairing_file = self
+            .connect()
+            .await?
+            .get_pair_record(udid)
+            .await
+            .map_err(pair_record_err)?;
+        pairing_file.udid = Some(udid.to_string());
+
+        pairing_file.serialize().map_err(pair_record_err)
+    }
+
+    async fn installed_apps(
+        &self,
+        udid: &str,
+    ) -> Result<HashMap<String, plist::Value>, IloaderError> {
+        let provider = get_provider(udid).await?;
+        let mut installation_proxy = InstallationProxyClient::connect(&provider)
+            .await
+            .map_err(IloaderError::InstallationProxy)?;
+
+        installation_proxy
+            .get_apps(Some("User"), None)
+            .await
+            .map_err(IloaderError::InstallationProxy)
+    }
+
+    async fn write_document(
+        &self,
+        udid: &str,
+        bundle_id: &str,
+        path: &str,
+        contents: &[u8],
+    ) -> Result<(), IloaderError> {
+        let mut usbmuxd = self.connect().await?;
+        let provider = get_provider_from_connection(udid, &mut usbmuxd).await?;
+
+        let house_arrest_err = |source| IloaderError::HouseArrest {
+            bundle_id: bundle_id.to_string(),
+            source,
+        };
+        let house_arrest_client = HouseArrestClient::connect(&provider)
+            .await
+            .map_err(house_arrest_err)?;
+
+        let mut afc_client = house_arrest_client
+            .vend_documents(bundle_id.to_string())
+            .await
+            .map_err(house_arrest_err)?;
+
+        let afc_err = |source| IloaderError::Afc {
+            path: path.to_string(),
+            source,
+        };
+        afc_client
+            .mk_dir(format!(
+                "/Documents/{}",
+                path.rsplit_once('/').map(|x| x.0).unwrap_or("")
+            ))
+            .await
+            .map_err(afc_err)?;
+
+        let mut file = afc_client
+            .open(format!("/Documents/{}", path), AfcFopenMode::Wr)
+            .await
+            .map_err(afc_err)?;
+
+        file.write_entire(contents).await.map_err(afc_err)?;
+        file.close().await.map_err(afc_err)
+    }
+}
@@ -0,0 +1,472 @@
+use std::{
+    collections::HashMap,
+    io::{self, Cursor},
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicU32, Ordering},
+    },
+};
+
+use plist::{Dictionary, Value};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{UnixListener, UnixStream},
+    task::JoinHandle,
+};
+
+use super::{DeviceBackend, fake::FakeDeviceBackend};
+
+const LOCKDOWN_PORT: u16 = 62078;
+const INSTALLATION_PROXY_PORT: u16 = 49152;
+const HOUSE_ARREST_PORT: u16 = 49153;
+
+const USBMUXD_PLIST_VERSION: u32 = 1;
+const USBMUXD_PLIST_MESSAGE: u32 = 8;
+
+const AFC_MAGIC: &[u8; 8] = b"CFA6LPAA";
+const AFC_HEADER_LEN: usize = 40;
+const AFC_OP_STATUS: u64 = 0x01;
+const AFC_OP_MAKE_DIR: u64 = 0x09;
+const AFC_OP_FILE_OPEN: u64 = 0x0D;
+const AFC_OP_FILE_OPEN_RES: u64 = 0x0E;
+const AFC_OP_FILE_WRITE: u64 = 0x10;
+const AFC_OP_FILE_CLOSE: u64 = 0x14;
+const AFC_ERR_OBJECT_NOT_FOUND: u64 = 8;
+
+static NEXT_SOCKET: AtomicU32 = AtomicU32::new(0);
+
+// Speaks the usbmuxd plist protocol on a Unix socket and answers lockdown, installation_proxy
+// and house_arrest/AFC requests from the devices in a FakeDeviceBackend. Point idevice at it by
+// setting USBMUXD_SOCKET_ADDRESS to `socket_path()`.
+pub struct UsbmuxdEmulator {
+    socket_path: PathBuf,
+    backend: Arc<FakeDeviceBackend>,
+    task: JoinHandle<()>,
+}
+
+impl UsbmuxdEmulator {
+    pub async fn start(backend: Arc<FakeDeviceBackend>) -> io::Result<Self> {
+        let socket_path = std::env::temp_dir().join(format!(
+            "iloader-usbmuxd-{}-{}.sock",
+            std::process::id(),
+            NEXT_SOCKET.fetch_add(1, Ordering::SeqCst)
+        ));
+        let _ = std::fs::remove_file(&socket_path);
+        let listener = UnixListener::bind(&socket_path)?;
+
+        let task = tokio::spawn({
+            let backend = backend.clone();
+            async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    let backend = backend.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = handle_client(&backend, stream).await {
+                            tracing::debug!("usbmuxd emulator connection closed: {e}");
+                        }
+                    });
+                }
+            }
+        });
+
+        Ok(UsbmuxdEmulator {
+            socket_path,
+            backend,
+            task,
+        })
+    }
+
+    pub fn socket_path(&self) -> &Path {
+        &self.socket_path
+    }
+
+    pub fn backend(&self) -> &FakeDeviceBackend {
+        &self.backend
+    }
+}
+
+impl Drop for UsbmuxdEmulator {
+    fn drop(&mut self) {
+        self.task.abort();
+        let _ = std::fs::remove_file(&self.socket_path);
+    }
+}
+
+async fn handle_client(backend: &FakeDeviceBackend, mut stream: UnixStream) -> io::Result<()> {
+    loop {
+        let mut header = [0u8; 16];
+        if stream.read_exact(&mut header).await.is_err() {
+            return Ok(());
+        }
+        let len = u32::from_le_bytes(header[0..4].try_into().unwrap()) as usize;
+        let tag = u32::from_le_bytes(header[12..16].try_into().unwrap());
+        let mut body = vec![0u8; len.saturating_sub(16)];
+        stream.read_exact(&mut body).await?;
+        let request = parse_dictionary(&body)?;
+
+        let message_type = request
+            .get("MessageType")
+            .and_then(Value::as_string)
+            .unwrap_or_default();
+        match message_type {
+            "ListDevices" => {
+                let devices = backend.devices().await.unwrap_or_default();
+                let list = devices
+                    .into_iter()
+                    .map(|d| {
+                        let mut properties = Dictionary::new();
+                        properties.insert("ConnectionType".into(), "USB".into());
+                        properties.insert("DeviceID".into(), d.id.into());
+                        properties.insert("SerialNumber".into(), d.udid.into());
+                        properties.insert("LocationID".into(), 0u64.into());
+                        properties.insert("ProductID".into(), 0x12a8u64.into());
+
+                        let mut device = Dictionary::new();
+                        device.insert("DeviceID".into(), d.id.into());
+                        device.insert("MessageType".into(), "Attached".into());
+                        device.insert("Properties".into(), Value::Dictionary(properties));
+                        Value::Dictionary(device)
+                    })
+                    .collect();
+
+                let mut response = Dictionary::new();
+                response.insert("DeviceList".into(), Value::Array(list));
+                write_usbmuxd(&mut stream, tag, response).await?;
+            }
+            "ReadPairRecord" => {
+                let udid = request
+                    .get("PairRecordID")
+                    .and_then(Value::as_string)
+                    .unwrap_or_default();
+                match backend.pair_record(udid).await {
+                    Ok(record) => {
+                        let mut response = Dictionary::new();
+                        response.insert("PairRecordData".into(), Value::Data(record));
+                        write_usbmuxd(&mut stream, tag, response).await?;
+                    }
+                    Err(_) => write_result(&mut stream, tag, 2).await?,
+                }
+            }
+            "ReadBUID" => {
+                let mut response = Dictionary::new();
+                response.insert("BUID".into(), "ILOADER-EMULATOR".into());
+                write_usbmuxd(&mut stream, tag, response).await?;
+            }
+            "Connect" => {
+                let device_id = request
+                    .get("DeviceID")
+                    .and_then(Value::as_unsigned_integer)
+                    .unwrap_or_default() as u32;
+                // usbmuxd expects the port in network byte order
+                let port = (request
+                    .get("PortNumber")
+                    .and_then(Value::as_unsigned_integer)
+                    .unwrap_or_default() as u16)
+                    .swap_bytes();
+
+                let devices = backend.devices().await.unwrap_or_default();
+                let Some(device) = devices.into_iter().find(|d| d.id == device_id) else {
+                    return write_result(&mut stream, tag, 2).await;
+                };
+                if ![LOCKDOWN_PORT, INSTALLATION_PROXY_PORT, HOUSE_ARREST_PORT].contains(&port) {
+                    return write_result(&mut stream, tag, 3).await;
+                }
+
+                write_result(&mut stream, tag, 0).await?;
+                return match port {
+                    LOCKDOWN_PORT => serve_lockdown(backend, &device.udid, stream).await,
+                    INSTALLATION_PROXY_PORT => {
+                        serve_installation_proxy(backend, &device.udid, stream).await
+                    }
+                    _ => serve_house_arrest(backend, &device.udid, stream).await,
+                };
+            }
+            _ => write_result(&mut stream, tag, 1).await?,
+        }
+    }
+}
+
+async fn serve_lockdown(
+    backend: &FakeDeviceBackend,
+    udid: &str,
+    mut stream: UnixStream,
+) -> io::Result<()> {
+    while let Some(request) = read_service(&mut stream).await? {
+        let request_type = request
+            .get("Request")
+            .and_then(Value::as_string)
+            .unwrap_or_default()
+            .to_string();
+        let key = request.get("Key").and_then(Value::as_string);
+        let domain = request.get("Domain").and_then(Value::as_string);
+
+        let mut response = Dictionary::new();
+        response.insert("Request".into(), request_type.clone().into());
+        match request_type.as_str() {
+            "QueryType" => {
+                response.insert("Type".into(), "com.apple.mobile.lockdown".into());
+            }
+            "GetValue" => match backend
+                .get_value(udid, key.unwrap_or_default(), domain)
+                .await
+            {
+                Ok(value) => {
+                    response.insert("Value".into(), value);
+                }
+                Err(_) => {
+                    response.insert("Error".into(), "MissingValue".into());
+                }
+            },
+            "SetValue" => {
+                let value = request
+                    .get("Value")
+                    .cloned()
+                    .unwrap_or(Value::Boolean(false));
+                if backend
+                    .set_value(udid, key.unwrap_or_default(), value, domain)
+                    .await
+                    .is_err()
+                {
+                    response.insert("Error".into(), "InvalidHostID".into());
+                }
+            }
+            "StartSession" => {
+                response.insert("SessionID".into(), "ILOADER-EMULATOR-SESSION".into());
+                response.insert("EnableSessionSSL".into(), false.into());
+            }
+            "StopSession" => {}
+            "StartService" => {
+                let port = match request.get("Service").and_then(Value::as_string) {
+                    Some("com.apple.mobile.installation_proxy") => Some(INSTALLATION_PROXY_PORT),
+                    Some("com.apple.mobile.house_arrest") => Some(HOUSE_ARREST_PORT),
+                    _ => None,
+                };
+                match port {
+                    Some(port) => {
+                        response.insert("Port".into(), port.into());
+                        response.insert("EnableServiceSSL".into(), false.into());
+                    }
+                    None => {
+                        response.insert("Error".into(), "InvalidService".into());
+                    }
+                }
+            }
+            _ => {
+                response.insert("Error".into(), "UnknownRequest".into());
+            }
+        }
+        write_service(&mut stream, response).await?;
+    }
+    Ok(())
+}
+
+async fn serve_installation_proxy(
+    backend: &FakeDeviceBackend,
+    udid: &str,
+    mut stream: UnixStream,
+) -> io::Result<()> {
+    while let Some(request) = read_service(&mut stream).await? {
+        let apps = backend.installed_apps(udid).await.unwrap_or_default();
+        let command = request
+            .get("Command")
+            .and_then(Value::as_string)
+            .unwrap_or_default();
+
+        match command {
+            "Lookup" => {
+                let mut response = Dictionary::new();
+                response.insert(
+                    "LookupResult".into(),
+                    Value::Dictionary(apps.into_iter().collect()),
+                );
+                response.insert("Status".into(), "Complete".into());
+                write_service(&mut stream, response).await?;
+            }
+            "Browse" => {
+                let list: Vec<Value> = apps.into_values().collect();
+                let mut response = Dictionary::new();
+                response.insert("CurrentAmount".into(), (list.len() as u64).into());
+                response.insert("CurrentList".into(), Value::Array(list));
+                response.insert("Status".into(), "BrowsingApplications".into());
+                write_service(&mut stream, response).await?;
+
+                let mut response = Dictionary::new();
+                response.insert("Status".into(), "Complete".into());
+                write_service(&mut stream, response).await?;
+            }
+            _ => {
+                let mut response = Dictionary::new();
+                response.insert("Error".into(), "UnknownCommand".into());
+                write_service(&mut stream, response).await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn serve_house_arrest(
+    backend: &FakeDeviceBackend,
+    udid: &str,
+    mut stream: UnixStream,
+) -> io::Result<()> {
+    let Some(request) = read_service(&mut stream).await? else {
+        return Ok(());
+    };
+    let bundle_id = request
+        .get("Identifier")
+        .and_then(Value::as_string)
+        .unwrap_or_default()
+        .to_string();
+
+    let installed = backend
+        .installed_apps(udid)
+        .await
+        .is_ok_and(|apps| apps.contains_key(&bundle_id));
+    let mut response = Dictionary::new();
+    if installed {
+        response.insert("Status".into(), "Complete".into());
+    } else {
+        response.insert("Error".into(), "ApplicationLookupFailed".into());
+    }
+    write_service(&mut stream, response).await?;
+
+    if installed {
+        serve_afc(backend, udid, &bundle_id, stream).await
+    } else {
+        Ok(())
+    }
+}
+
+// Only the subset of AFC needed to write a file: make_dir, open, write and close
+async fn serve_afc(
+    backend: &FakeDeviceBackend,
+    udid: &str,
+    bundle_id: &str,
+    mut stream: UnixStream,
+) -> io::Result<()> {
+    let mut open_files: HashMap<u64, (String, Vec<u8>)> = HashMap::new();
+    let mut next_fd = 1u64;
+    let mut packet_num = 0u64;
+
+    loop {
+        let mut header = [0u8; AFC_HEADER_LEN];
+        if stream.read_exact(&mut header).await.is_err() {
+            return Ok(());
+        }
+        if &header[0..8] != AFC_MAGIC {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "bad AFC magic"));
+        }
+        let entire_len = u64::from_le_bytes(header[8..16].try_into().unwrap()) as usize;
+        let this_len = u64::from_le_bytes(header[16..24].try_into().unwrap()) as usize;
+        let opcode = u64::from_le_bytes(header[32..40].try_into().unwrap());
+
+        let mut header_payload = vec![0u8; this_len.saturating_sub(AFC_HEADER_LEN)];
+        stream.read_exact(&mut header_payload).await?;
+        let mut payload = vec![0u8; entire_len.saturating_sub(this_len)];
+        stream.read_exact(&mut payload).await?;
+
+        let (response_op, response_payload) = match opcode {
+            AFC_OP_MAKE_DIR => (AFC_OP_STATUS, 0u64),
+            AFC_OP_FILE_OPEN => {
+                let path = afc_path(&header_payload[8.min(header_payload.len())..]);
+                let fd = next_fd;
+                next_fd += 1;
+                open_files.insert(fd, (path, Vec::new()));
+                (AFC_OP_FILE_OPEN_RES, fd)
+            }
+            AFC_OP_FILE_WRITE => match open_files.get_mut(&afc_fd(&header_payload)) {
+                Some((_, contents)) => {
+                    contents.extend_from_slice(&payload);
+                    (AFC_OP_STATUS, 0)
+                }
+                None => (AFC_OP_STATUS, AFC_ERR_OBJECT_NOT_FOUND),
+            },
+            AFC_OP_FILE_CLOSE => match open_files.remove(&afc_fd(&header_payload)) {
+                Some((path, contents)) => {
+                    let relative = path.trim_start_matches("/Documents/");
+                    match backend
+                        .write_document(udid, bundle_id, relative, &contents)
+                        .await
+                    {
+                        Ok(()) => (AFC_OP_STATUS, 0),
+                        Err(_) => (AFC_OP_STATUS, AFC_ERR_OBJECT_NOT_FOUND),
+                    }
+                }
+                None => (AFC_OP_STATUS, AFC_ERR_OBJECT_NOT_FOUND),
+            },
+            _ => (AFC_OP_STATUS, 0),
+        };
+
+        let mut packet = Vec::with_capacity(AFC_HEADER_LEN + 8);
+        packet.extend_from_slice(AFC_MAGIC);
+        packet.extend_from_slice(&((AFC_HEADER_LEN + 8) as u64).to_le_bytes());
+        packet.extend_from_slice(&((AFC_HEADER_LEN + 8) as u64).to_le_bytes());
+        packet.extend_from_slice(&packet_num.to_le_bytes());
+        packet.extend_from_slice(&response_op.to_le_bytes());
+        packet.extend_from_slice(&response_payload.to_le_bytes());
+        stream.write_all(&packet).await?;
+        packet_num += 1;
+    }
+}
+
+fn afc_fd(header_payload: &[u8]) -> u64 {
+    header_payload
+        .get(0..8)
+        .map(|b| u64::from_le_bytes(b.try_into().unwrap()))
+        .unwrap_or_default()
+}
+
+fn afc_path(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).to_string()
+}
+
+fn parse_dictionary(bytes: &[u8]) -> io::Result<Dictionary> {
+    Value::from_reader(Cursor::new(bytes))
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?
+        .into_dictionary()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "expected a dictionary"))
+}
+
+fn serialize(dict: Dictionary) -> io::Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    plist::to_writer_xml(&mut buf, &Value::Dictionary(dict))
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    Ok(buf)
+}
+
+async fn write_usbmuxd(stream: &mut UnixStream, tag: u32, dict: Dictionary) -> io::Result<()> {
+    let body = serialize(dict)?;
+    let mut message = Vec::with_capacity(16 + body.len());
+    message.extend_from_slice(&((16 + body.len()) as u32).to_le_bytes());
+    message.extend_from_slice(&USBMUXD_PLIST_VERSION.to_le_bytes());
+    message.extend_from_slice(&USBMUXD_PLIST_MESSAGE.to_le_bytes());
+    message.extend_from_slice(&tag.to_le_bytes());
+    message.extend_from_slice(&body);
+    stream.write_all(&message).await
+}
+
+// usbmuxd result codes: 0 ok, 1 bad command, 2 bad device, 3 connection refused
+async fn write_result(stream: &mut UnixStream, tag: u32, number: u64) -> io::Result<()> {
+    let mut response = Dictionary::new();
+    response.insert("MessageType".into(), "Result".into());
+    response.insert("Number".into(), number.into());
+    write_usbmuxd(stream, tag, response).await
+}
+
+// Lockdown and the services behind it frame plists with a big endian length
+async fn read_service(stream: &mut UnixStream) -> io::Result<Option<Dictionary>> {
+    let mut len = [0u8; 4];
+    if stream.read_exact(&mut len).await.is_err() {
+        return Ok(None);
+    }
+    let mut body = vec![0u8; u32::from_be_bytes(len) as usize];
+    stream.read_exact(&mut body).await?;
+    parse_dictionary(&body).map(Some)
+}
+
+async fn write_service(stream: &mut UnixStream, dict: Dictionary) -> io::Result<()> {
+    let body = serialize(dict)?;
+    stream.write_all(&(body.len() as u32).to_be_bytes()).await?;
+    stream.write_all(&body).await
+}
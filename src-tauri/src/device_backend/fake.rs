@@ -0,0 +1,186 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use async_trait::async_trait;
+use idevice::IdeviceError;
+
+use super::{ConnectedDevice, DeviceBackend};
+use crate::error::IloaderError;
+
+// An in-memory stand-in for usbmuxd and the device services it proxies, for tests
+#[derive(Default)]
+pub struct FakeDeviceBackend {
+    devices: Mutex<Vec<FakeDevice>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct FakeDevice {
+    pub id: u32,
+    pub udid: String,
+    pub connection_type: String,
+    // lockdown values keyed by (domain, key)
+    pub values: HashMap<(Option<String>, String), plist::Value>,
+    pub pair_record: Option<Vec<u8>>,
+    pub apps: HashMap<String, plist::Value>,
+    // files written through house arrest, keyed by (bundle ID, path relative to Documents)
+    pub documents: HashMap<(String, String), Vec<u8>>,
+}
+
+impl FakeDevice {
+    pub fn new(id: u32, udid: &str, name: &str) -> Self {
+        let mut values = HashMap::new();
+        values.insert((None, "DeviceName".to_string()), name.into());
+        FakeDevice {
+            id,
+            udid: udid.to_string(),
+            connection_type: "USB".to_string(),
+            values,
+            pair_record: Some(format!("pair record for {}", udid).into_bytes()),
+            apps: HashMap::new(),
+            documents: HashMap::new(),
+        }
+    }
+
+    pub fn with_app(mut self, bundle_id: &str, display_name: &str) -> Self {
+        let mut app = plist::Dictionary::new();
+        app.insert("CFBundleIdentifier".to_string(), bundle_id.into());
+        app.insert("CFBundleDisplayName".to_string(), display_name.into());
+        self.apps
+            .insert(bundle_id.to_string(), plist::Value::Dictionary(app));
+        self
+    }
+
+    // Needed when the record has to be parsed, e.g. when served by the usbmuxd emulator
+    pub fn with_pair_record(mut self, pair_record: Vec<u8>) -> Self {
+        self.pair_record = Some(pair_record);
+        self
+    }
+
+    pub fn without_pair_record(mut self) -> Self {
+        self.pair_record = None;
+        self
+    }
+}
+
+impl FakeDeviceBackend {
+    pub fn new(devices: Vec<FakeDevice>) -> Self {
+        FakeDeviceBackend {
+            devices: Mutex::new(devices),
+        }
+    }
+
+    pub fn device(&self, udid: &str) -> Option<FakeDevice> {
+        self.devices
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|d| d.udid == udid)
+            .cloned()
+    }
+
+    fn with_device<T>(
+        &self,
+        udid: &str,
+        f: impl FnOnce(&mut FakeDevice) -> Result<T, IloaderError>,
+    ) -> Result<T, IloaderError> {
+        let mut devices = self.devices.lock().unwrap();
+        let device = devices.iter_mut().find(|d| d.udid == udid).ok_or_else(|| {
+            IloaderError::DeviceNotFound {
+                udid: udid.to_string(),
+                source: None,
+            }
+        })?;
+        f(device)
+    }
+}
+
+#[async_trait]
+impl DeviceBackend for FakeDeviceBackend {
+    async fn devices(&self) -> Result<Vec<ConnectedDevice>, IloaderError> {
+        Ok(self
+            .devices
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|d| ConnectedDevice {
+                id: d.id,
+                udid: d.udid.clone(),
+                connection_type: d.connection_type.clone(),
+            })
+            .collect())
+    }
+
+    async fn get_value(
+        &self,
+        udid: &str,
+        key: &str,
+        domain: Option<&str>,
+    ) -> Result<plist::Value, IloaderError> {
+        self.with_device(udid, |d| {
+            d.values
+                .get(&(domain.map(str::to_string), key.to_string()))
+                .cloned()
+                .ok_or(IloaderError::Lockdown {
+                    action: "get value",
+                    source: IdeviceError::UnexpectedResponse,
+                })
+        })
+    }
+
+    async fn set_value(
+        &self,
+        udid: &str,
+        key: &str,
+        value: plist::Value,
+        domain: Option<&str>,
+    ) -> Result<(), IloaderError> {
+        self.with_device(udid, |d| {
+            if d.pair_record.is_none() {
+                return Err(IloaderError::Lockdown {
+                    action: "start session",
+                    source: IdeviceError::UnexpectedResponse,
+                });
+            }
+            d.values
+                .insert((domain.map(str::to_string), key.to_string()), value);
+            Ok(())
+        })
+    }
+
+    async fn pair_record(&self, udid: &str) -> Result<Vec<u8>, IloaderError> {
+        self.with_device(udid, |d| {
+            d.pair_record
+                .clone()
+                .ok_or_else(|| IloaderError::PairRecord {
+                    device: udid.to_string(),
+                    source: IdeviceError::UnexpectedResponse,
+                })
+        })
+    }
+
+    async fn installed_apps(
+        &self,
+        udid: &str,
+    ) -> Result<HashMap<String, plist::Value>, IloaderError> {
+        self.with_device(udid, |d| Ok(d.apps.clone()))
+    }
+
+    async fn write_document(
+        &self,
+        udid: &str,
+        bundle_id: &str,
+        path: &str,
+        contents: &[u8],
+    ) -> Result<(), IloaderError> {
+        self.with_device(udid, |d| {
+            if !d.apps.contains_key(bundle_id) {
+                return Err(IloaderError::HouseArrest {
+                    bundle_id: bundle_id.to_string(),
+                    source: IdeviceError::UnexpectedResponse,
+                });
+            }
+            d.documents
+                .insert((bundle_id.to_string(), path.to_string()), contents.to_vec());
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,435 @@
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+    sync::{Mutex, OnceLock},
+};
+
+use aes_gcm::{
+    Aes256Gcm, Key, KeyInit, Nonce,
+    aead::{Aead, AeadCore, OsRng, rand_core::RngCore},
+};
+use base64::{Engine, engine::general_purpose::STANDARD as BASE64};
+use isideload::util::{fs_storage::FsStorage, storage::SideloadingStorage};
+use rootcause::{Report, report};
+use serde::Serialize;
+use sha2::Sha256;
+use tauri::{AppHandle, Manager};
+use tracing::{info, warn};
+
+use crate::account::keyring_available;
+
+// Overrides the machine key, e.g. for a data directory shared between machines
+pub const PASSPHRASE_ENV: &str = "ILOADER_STORAGE_PASSPHRASE";
+
+const SALT_FILE: &str = "storage.salt";
+const PBKDF2_ROUNDS: u32 = 210_000;
+const NONCE_LEN: usize = 12;
+const EXTENSION: &str = "enc";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum KeySource {
+    Passphrase,
+    // The OS machine ID, so the files are useless when copied to another machine
+    MachineId,
+    // A random key next to the data, when the machine ID can't be read
+    KeyFile,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum StorageBackend {
+    Keyring,
+    EncryptedFile,
+}
+
+impl fmt::Display for StorageBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StorageBackend::Keyring => write!(f, "keyring"),
+            StorageBackend::EncryptedFile => write!(f, "encrypted file"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StorageBackendInfo {
+    pub backend: StorageBackend,
+    pub key_source: Option<KeySource>,
+    pub path: Option<String>,
+}
+
+// Deriving the key is deliberately slow, so it's done once per data directory
+static KEYS: Mutex<Vec<(PathBuf, Key<Aes256Gcm>, KeySource)>> = Mutex::new(vec![]);
+static MACHINE_ID: OnceLock<Option<String>> = OnceLock::new();
+
+fn machine_id() -> Option<String> {
+    MACHINE_ID
+        .get_or_init(|| {
+            let id = read_machine_id().map(|id| id.trim().to_string());
+            id.filter(|id| !id.is_empty())
+        })
+        .clone()
+}
+
+#[cfg(target_os = "linux")]
+fn read_machine_id() -> Option<String> {
+    ["/etc/machine-id", "/var/lib/dbus/machine-id"]
+        .iter()
+        .find_map(|path| fs::read_to_string(path).ok())
+}
+
+#[cfg(target_os = "macos")]
+fn read_machine_id() -> Option<String> {
+    let output = std::process::Command::new("ioreg")
+        .args(["-rd1", "-c", "IOPlatformExpertDevice"])
+        .output()
+        .ok()?;
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .find(|line| line.contains("IOPlatformUUID"))
+        .and_then(|line| line.split('"').nth(3))
+        .map(str::to_string)
+}
+
+#[cfg(target_os = "windows")]
+fn read_machine_id() -> Option<String> {
+    let output = std::process::Command::new("reg")
+        .args([
+            "query",
+            r"HKLM\SOFTWARE\Microsoft\Cryptography",
+            "/v",
+            "MachineGuid",
+        ])
+        .output()
+        .ok()?;
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .find(|line| line.contains("MachineGuid"))
+        .and_then(|line| line.split_whitespace().last())
+        .map(str::to_string)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "windows")))]
+fn read_machine_id() -> Option<String> {
+    None
+}
+
+fn key_source() -> KeySource {
+    if std::env::var(PASSPHRASE_ENV).is_ok_and(|p| !p.is_empty()) {
+        KeySource::Passphrase
+    } else if machine_id().is_some() {
+        KeySource::MachineId
+    } else {
+        KeySource::KeyFile
+    }
+}
+
+// Random bytes kept in the data directory, created on first use
+fn random_file(path: &Path, len: usize) -> Result<Vec<u8>, Report> {
+    if let Ok(bytes) = fs::read(path) {
+        if bytes.len() == len {
+            return Ok(bytes);
+        }
+        warn!("Ignoring malformed {}", path.display());
+    }
+    let bytes = random_bytes(len);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .map_err(|e| report!("Unable to create {}: {}", parent.display(), e))?;
+    }
+    fs::write(path, &bytes).map_err(|e| report!("Unable to write {}: {}", path.display(), e))?;
+    restrict_permissions(path);
+    Ok(bytes)
+}
+
+#[cfg(unix)]
+fn restrict_permissions(path: &Path) {
+    use std::os::unix::fs::PermissionsExt;
+    if let Err(e) = fs::set_permissions(path, fs::Permissions::from_mode(0o600)) {
+        warn!(
+            "Unable to restrict permissions of {}: {}",
+            path.display(),
+            e
+        );
+    }
+}
+
+#[cfg(not(unix))]
+fn restrict_permissions(_path: &Path) {}
+
+fn derive_key(data_dir: &Path) -> Result<(Key<Aes256Gcm>, KeySource), Report> {
+    let mut keys = KEYS.lock().unwrap();
+    if let Some((_, key, source)) = keys.iter().find(|(dir, _, _)| dir == data_dir) {
+        return Ok((*key, *source));
+    }
+
+    let source = key_source();
+    let secret = match source {
+        KeySource::Passphrase => std::env::var(PASSPHRASE_ENV)
+            .unwrap_or_default()
+            .into_bytes(),
+        KeySource::MachineId => machine_id().unwrap_or_default().into_bytes(),
+        KeySource::KeyFile => random_file(&data_dir.join("storage.key"), 32)?,
+    };
+    let salt = random_file(&data_dir.join(SALT_FILE), 16)?;
+
+    let key = password_key(&secret, &salt);
+    keys.push((data_dir.to_path_buf(), key, source));
+    Ok((key, source))
+}
+
+pub(crate) fn password_key(secret: &[u8], salt: &[u8]) -> Key<Aes256Gcm> {
+    let mut key = Key::<Aes256Gcm>::default();
+    pbkdf2::pbkdf2_hmac::<Sha256>(secret, salt, PBKDF2_ROUNDS, &mut key);
+    key
+}
+
+pub(crate) fn random_bytes(len: usize) -> Vec<u8> {
+    let mut bytes = vec![0u8; len];
+    OsRng.fill_bytes(&mut bytes);
+    bytes
+}
+
+// The nonce followed by the ciphertext
+pub(crate) fn seal(key: &Key<Aes256Gcm>, plaintext: &[u8]) -> Option<Vec<u8>> {
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let ciphertext = Aes256Gcm::new(key).encrypt(&nonce, plaintext).ok()?;
+    let mut sealed = nonce.to_vec();
+    sealed.extend(ciphertext);
+    Some(sealed)
+}
+
+// None if the data is truncated, tampered with or sealed with another key
+pub(crate) fn open(key: &Key<Aes256Gcm>, sealed: &[u8]) -> Option<Vec<u8>> {
+    if sealed.len() < NONCE_LEN {
+        return None;
+    }
+    let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+    Aes256Gcm::new(key)
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .ok()
+}
+
+// File storage for when there is no OS keyring. Every entry is its own AES-256-GCM encrypted
+// file, with the key derived from the passphrase in ILOADER_STORAGE_PASSPHRASE or the machine ID.
+// Entries left in plain text by FsStorage are moved over the first time they're read
+pub struct EncryptedFsStorage {
+    dir: PathBuf,
+    data_dir: PathBuf,
+    legacy: FsStorage,
+}
+
+impl EncryptedFsStorage {
+    // `dir` holds the entries and any plain text ones from before; the key comes from `data_dir`
+    pub fn new(data_dir: &Path, dir: PathBuf) -> Self {
+        EncryptedFsStorage {
+            legacy: FsStorage::new(dir.clone()),
+            dir,
+            data_dir: data_dir.to_path_buf(),
+        }
+    }
+
+    // Keys can contain anything, so file names are hex encoded
+    fn path(&self, key: &str) -> PathBuf {
+        let name: String = key.bytes().map(|b| format!("{:02x}", b)).collect();
+        self.dir.join(format!("{}.{}", name, EXTENSION))
+    }
+
+    // The keys of the encrypted entries, not counting plain text ones that haven't moved yet
+    pub fn keys(&self) -> Vec<String> {
+        let Ok(entries) = fs::read_dir(&self.dir) else {
+            return vec![];
+        };
+        entries
+            .flatten()
+            .filter_map(|entry| {
+                let name = entry.file_name().to_string_lossy().to_string();
+                let hex = name.strip_suffix(&format!(".{}", EXTENSION))?.to_string();
+                let bytes = (0..hex.len())
+                    .step_by(2)
+                    .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+                    .collect::<Option<Vec<u8>>>()?;
+                String::from_utf8(bytes).ok()
+            })
+            .collect()
+    }
+
+    // Plain text entries FsStorage left in the directory. It can't list them, so every other file
+    // name is tried as a key, with and without its extension. Keys with a slash, like isideload's
+    // "<hash>/key", are files one directory down
+    pub fn legacy_keys(&self) -> Vec<String> {
+        let Ok(entries) = fs::read_dir(&self.dir) else {
+            return vec![];
+        };
+        let mut names = vec![];
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.is_dir() {
+                let dir = entry.file_name().to_string_lossy().to_string();
+                let Ok(nested) = fs::read_dir(&path) else {
+                    continue;
+                };
+                names.extend(
+                    nested
+                        .flatten()
+                        .filter(|e| e.path().is_file())
+                        .map(|e| format!("{}/{}", dir, e.file_name().to_string_lossy())),
+                );
+            } else if path.extension().is_none_or(|ext| ext != EXTENSION) {
+                let stems = [path.file_name(), path.file_stem()];
+                names.extend(
+                    stems
+                        .into_iter()
+                        .flatten()
+                        .map(|name| name.to_string_lossy().to_string()),
+                );
+            }
+        }
+
+        let mut keys: Vec<String> = vec![];
+        for name in names {
+            if !keys.contains(&name) && matches!(self.legacy.retrieve_data(&name), Ok(Some(_))) {
+                keys.push(name);
+            }
+        }
+        keys
+    }
+
+    fn write(&self, key: &str, value: &str) -> Result<(), Report> {
+        let (cipher_key, _) = derive_key(&self.data_dir)?;
+        let contents = seal(&cipher_key, value.as_bytes())
+            .ok_or_else(|| report!("Unable to encrypt {}", key))?;
+
+        fs::create_dir_all(&self.dir)
+            .map_err(|e| report!("Unable to create {}: {}", self.dir.display(), e))?;
+        let path = self.path(key);
+        fs::write(&path, contents)
+            .map_err(|e| report!("Unable to write {}: {}", path.display(), e))?;
+        restrict_permissions(&path);
+        Ok(())
+    }
+
+    fn read(&self, key: &str) -> Result<Option<String>, Report> {
+        let path = self.path(key);
+        let contents = match fs::read(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(report!("Unable to read {}: {}", path.display(), e)),
+        };
+        let (cipher_key, _) = derive_key(&self.data_dir)?;
+        let plaintext = open(&cipher_key, &contents).ok_or_else(|| {
+            report!(
+                "Unable to decrypt {}, the passphrase or machine ID may have changed",
+                key
+            )
+        })?;
+        String::from_utf8(plaintext)
+            .map(Some)
+            .map_err(|e| report!("{} is not valid UTF-8: {}", key, e))
+    }
+
+    // FsStorage keeps values as raw bytes. Entries read as data, like the anisette state and the
+    // signing key, move over base64 encoded, which is how store_data keeps them here
+    fn migrate(&self, key: &str, data: bool) -> Result<Option<String>, Report> {
+        let Some(bytes) = self.legacy.retrieve_data(key)? else {
+            return Ok(None);
+        };
+        let value = if data {
+            BASE64.encode(&bytes)
+        } else {
+            String::from_utf8_lossy(&bytes).into_owned()
+        };
+        self.write(key, &value)?;
+        if let Err(e) = self.remove_legacy(key) {
+            warn!(
+                "Encrypted {} but couldn't delete the plain text copy: {}",
+                key, e
+            );
+        } else {
+            info!("Moved {} to encrypted storage", key);
+        }
+        Ok(Some(value))
+    }
+
+    // FsStorage has no delete of its own, and the trait's default would leave an empty file behind
+    fn remove_legacy(&self, key: &str) -> Result<(), Report> {
+        let path = self.dir.join(key);
+        match fs::remove_file(&path) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(report!("Unable to delete {}: {}", path.display(), e)),
+        }
+        // Keys like "<hash>/key" got a directory of their own
+        if let Some(parent) = path.parent().filter(|parent| *parent != self.dir) {
+            let _ = fs::remove_dir(parent);
+        }
+        Ok(())
+    }
+}
+
+impl SideloadingStorage for EncryptedFsStorage {
+    fn store(&self, key: &str, value: &str) -> Result<(), Report> {
+        self.write(key, value)?;
+        // A stale plain text copy would otherwise linger next to the new value
+        let _ = self.remove_legacy(key);
+        Ok(())
+    }
+
+    fn retrieve(&self, key: &str) -> Result<Option<String>, Report> {
+        match self.read(key)? {
+            Some(value) => Ok(Some(value)),
+            None => self.migrate(key, false),
+        }
+    }
+
+    fn retrieve_data(&self, key: &str) -> Result<Option<Vec<u8>>, Report> {
+        let value = match self.read(key)? {
+            Some(value) => value,
+            None => match self.migrate(key, true)? {
+                Some(value) => value,
+                None => return Ok(None),
+            },
+        };
+        BASE64
+            .decode(value)
+            .map(Some)
+            .map_err(|e| report!("{} is not valid base64: {}", key, e))
+    }
+
+    fn delete(&self, key: &str) -> Result<(), Report> {
+        let path = self.path(key);
+        match fs::remove_file(&path) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(report!("Unable to delete {}: {}", path.display(), e)),
+        }
+        self.remove_legacy(key)
+    }
+}
+
+pub fn backend_info(data_dir: &Path) -> StorageBackendInfo {
+    if keyring_available() {
+        StorageBackendInfo {
+            backend: StorageBackend::Keyring,
+            key_source: None,
+            path: None,
+        }
+    } else {
+        StorageBackendInfo {
+            backend: StorageBackend::EncryptedFile,
+            key_source: Some(key_source()),
+            path: Some(data_dir.display().to_string()),
+        }
+    }
+}
+
+#[tauri::command]
+pub fn storage_backend(handle: AppHandle) -> StorageBackendInfo {
+    let data_dir = handle
+        .path()
+        .app_data_dir()
+        .unwrap_or_else(|_| PathBuf::from("Failed to get app data directory"));
+    backend_info(&data_dir)
+}
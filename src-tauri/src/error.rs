@@ -0,0 +1,239 @@
+use idevice::IdeviceError;
+use serde::{Serialize, Serializer, ser::SerializeStruct};
+use serde_json::{Map, Value, json};
+
+// isideload reports are not std errors, so they are kept as their rendered text in `report`.
+// The frontend relies on the "●" lines in that text to pick out the most specific cause.
+#[derive(Debug, thiserror::Error)]
+pub enum IloaderError {
+    #[error("Failed to connect to usbmuxd")]
+    UsbmuxdUnreachable(#[source] IdeviceError),
+    #[error("Invalid USBMUXD_SOCKET_ADDRESS: {0}")]
+    InvalidUsbmuxdAddress(String),
+    #[error("No device selected")]
+    NoDeviceSelected,
+    #[error("Device {udid} is not connected")]
+    DeviceNotFound {
+        udid: String,
+        #[source]
+        source: Option<IdeviceError>,
+    },
+    #[error("Failed to get pairing record for device {device}")]
+    PairRecord {
+        device: String,
+        #[source]
+        source: IdeviceError,
+    },
+    #[error("Lockdown request failed: {action}")]
+    Lockdown {
+        action: &'static str,
+        #[source]
+        source: IdeviceError,
+    },
+    #[error("Failed to get installed apps")]
+    InstallationProxy(#[source] IdeviceError),
+    #[error("Failed to parse installed apps")]
+    InvalidAppList,
+    #[error("Failed to access the documents of {bundle_id}")]
+    HouseArrest {
+        bundle_id: String,
+        #[source]
+        source: IdeviceError,
+    },
+    #[error("Failed to write {path} on device")]
+    Afc {
+        path: String,
+        #[source]
+        source: IdeviceError,
+    },
+    #[error("Could not find SideStore's bundle ID")]
+    SideStoreNotFound,
+    #[error("Not logged in")]
+    NotLoggedIn,
+    #[error("Not logged in as {email}")]
+    SessionNotFound { email: String },
+    #[error("Two-factor authentication timed out")]
+    TwoFactorTimeout,
+    #[error("2FA cancelled")]
+    TwoFactorCancelled,
+    #[error("Timed out waiting for {prompt}")]
+    PromptTimeout { prompt: &'static str },
+    #[error("Maximum number of development certificates reached")]
+    MaxCertsReached,
+    #[error("No developer team selected")]
+    NoTeamSelected,
+    #[error("Failed to sign in as {email}")]
+    Auth { email: String, report: String },
+    #[error("No anisette server is reachable")]
+    AnisetteUnavailable { report: String },
+    #[error("Apple's servers can't be reached")]
+    AppleUnreachable { report: String },
+    #[error("Failed to reset anisette state for {account}")]
+    AnisetteReset { account: String, report: String },
+    #[error("Failed to migrate saved data between storage backends")]
+    StorageMigration { report: String },
+    #[error("Failed to {action} profile")]
+    Profile {
+        action: &'static str,
+        report: String,
+    },
+    #[error("Wrong profile password")]
+    ProfilePassword,
+    #[error("Failed to {action}")]
+    Certificate {
+        action: &'static str,
+        report: String,
+    },
+    #[error("Certificate {serial} is not a development certificate of this team")]
+    CertificateNotOnTeam { serial: String },
+    #[error("None of the team's certificates was issued for this computer's signing key")]
+    NoSigningIdentity,
+    #[error("Developer services request failed: {action}")]
+    DeveloperServices {
+        action: &'static str,
+        report: String,
+    },
+    #[error("Failed to install the app")]
+    Install { report: String },
+    #[error("Failed to access saved credentials for {account}")]
+    Keyring {
+        account: String,
+        #[source]
+        source: keyring::Error,
+    },
+    #[error("Failed to access app data")]
+    Store(#[source] tauri_plugin_store::Error),
+    #[error("Failed to download {url}")]
+    Download {
+        url: String,
+        #[source]
+        source: reqwest::Error,
+    },
+    #[error("Failed to download {url}: HTTP {status}")]
+    DownloadStatus { url: String, status: u16 },
+    #[error("Failed to access {path}")]
+    Io {
+        path: String,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("Save cancelled")]
+    SaveCancelled,
+    #[error("Operation cancelled")]
+    Cancelled,
+    #[error("Failed to emit status to frontend")]
+    Emit(#[source] tauri::Error),
+}
+
+impl IloaderError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            IloaderError::UsbmuxdUnreachable(_) => "usbmuxd_unreachable",
+            IloaderError::InvalidUsbmuxdAddress(_) => "invalid_usbmuxd_address",
+            IloaderError::NoDeviceSelected => "no_device_selected",
+            IloaderError::DeviceNotFound { .. } => "device_not_found",
+            IloaderError::PairRecord { .. } => "pair_record_failed",
+            IloaderError::Lockdown { .. } => "lockdown_failed",
+            IloaderError::InstallationProxy(_) => "installation_proxy_failed",
+            IloaderError::InvalidAppList => "invalid_app_list",
+            IloaderError::HouseArrest { .. } => "house_arrest_failed",
+            IloaderError::Afc { .. } => "afc_failed",
+            IloaderError::SideStoreNotFound => "sidestore_not_found",
+            IloaderError::NotLoggedIn => "not_logged_in",
+            IloaderError::SessionNotFound { .. } => "session_not_found",
+            IloaderError::TwoFactorTimeout => "two_factor_timeout",
+            IloaderError::TwoFactorCancelled => "two_factor_cancelled",
+            IloaderError::AnisetteUnavailable { .. } => "anisette_unavailable",
+            IloaderError::AppleUnreachable { .. } => "apple_unreachable",
+            IloaderError::AnisetteReset { .. } => "anisette_reset",
+            IloaderError::StorageMigration { .. } => "storage_migration_failed",
+            IloaderError::Profile { .. } => "profile_failed",
+            IloaderError::ProfilePassword => "profile_wrong_password",
+            IloaderError::Certificate { .. } => "certificate_failed",
+            IloaderError::CertificateNotOnTeam { .. } => "certificate_not_on_team",
+            IloaderError::NoSigningIdentity => "no_signing_identity",
+            IloaderError::PromptTimeout { .. } => "prompt_timeout",
+            IloaderError::MaxCertsReached => "max_certs_reached",
+            IloaderError::NoTeamSelected => "no_team_selected",
+            IloaderError::Auth { .. } => "auth_failed",
+            IloaderError::DeveloperServices { .. } => "developer_services_failed",
+            IloaderError::Install { .. } => "install_failed",
+            IloaderError::Keyring { .. } => "keyring_failed",
+            IloaderError::Store(_) => "store_failed",
+            IloaderError::Download { .. } => "download_failed",
+            IloaderError::DownloadStatus { .. } => "download_http_status",
+            IloaderError::Io { .. } => "io_failed",
+            IloaderError::SaveCancelled => "save_cancelled",
+            IloaderError::Cancelled => "cancelled",
+            IloaderError::Emit(_) => "frontend_emit_failed",
+        }
+    }
+
+    pub fn fields(&self) -> Map<String, Value> {
+        let fields = match self {
+            IloaderError::DeviceNotFound { udid, .. } => json!({ "udid": udid }),
+            IloaderError::PairRecord { device, .. } => json!({ "device": device }),
+            IloaderError::Lockdown { action, .. } => json!({ "action": action }),
+            IloaderError::PromptTimeout { prompt } => json!({ "prompt": prompt }),
+            IloaderError::HouseArrest { bundle_id, .. } => json!({ "bundleId": bundle_id }),
+            IloaderError::Afc { path, .. } => json!({ "path": path }),
+            IloaderError::Auth { email, .. } | IloaderError::SessionNotFound { email } => {
+                json!({ "email": email })
+            }
+            IloaderError::DeveloperServices { action, .. }
+            | IloaderError::Profile { action, .. }
+            | IloaderError::Certificate { action, .. } => {
+                json!({ "action": action })
+            }
+            IloaderError::Keyring { account, .. } | IloaderError::AnisetteReset { account, .. } => {
+                json!({ "account": account })
+            }
+            IloaderError::Download { url, .. } => json!({ "url": url }),
+            IloaderError::DownloadStatus { url, status } => {
+                json!({ "url": url, "status": status })
+            }
+            IloaderError::Io { path, .. } => json!({ "path": path }),
+            IloaderError::CertificateNotOnTeam { serial } => json!({ "serial": serial }),
+            _ => json!({}),
+        };
+        match fields {
+            Value::Object(map) => map,
+            _ => Map::new(),
+        }
+    }
+
+    // The full error text, including the underlying cause(s)
+    pub fn details(&self) -> String {
+        match self {
+            IloaderError::Auth { report, .. }
+            | IloaderError::AnisetteUnavailable { report }
+            | IloaderError::AppleUnreachable { report }
+            | IloaderError::AnisetteReset { report, .. }
+            | IloaderError::StorageMigration { report }
+            | IloaderError::Profile { report, .. }
+            | IloaderError::Certificate { report, .. }
+            | IloaderError::DeveloperServices { report, .. }
+            | IloaderError::Install { report } => format!("{}\n{}", self, report),
+            _ => {
+                let mut details = self.to_string();
+                let mut source = std::error::Error::source(self);
+                while let Some(e) = source {
+                    details.push_str(&format!("\n● {}", e));
+                    source = e.source();
+                }
+                details
+            }
+        }
+    }
+}
+
+impl Serialize for IloaderError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("IloaderError", 4)?;
+        state.serialize_field("code", self.code())?;
+        state.serialize_field("message", &self.to_string())?;
+        state.serialize_field("fields", &self.fields())?;
+        state.serialize_field("details", &self.details())?;
+        state.end()
+    }
+}
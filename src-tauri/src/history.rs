@@ -0,0 +1,228 @@
+use std::{
+    fs::OpenOptions,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use serde::{Deserialize, Serialize};
+use tauri::State;
+
+use crate::{device::DeviceInfo, error::IloaderError};
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct HistoryEntry {
+    pub operation: String,
+    pub started_at: String,
+    pub finished_at: Option<String>,
+    // "succeeded", "failed" or "cancelled"
+    pub outcome: Option<String>,
+    pub device_udid: Option<String>,
+    pub device_name: Option<String>,
+    pub apple_id: Option<String>,
+    // The IPA path or download URL
+    pub source: Option<String>,
+    // The bundle ID as installed, after isideload added the team ID
+    pub bundle_id: Option<String>,
+    pub steps: Vec<HistoryStep>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct HistoryStep {
+    pub id: String,
+    pub started_at: String,
+    pub finished_at: Option<String>,
+    pub outcome: Option<String>,
+    pub error: Option<HistoryError>,
+}
+
+// IloaderError as it was serialized to the frontend, so it can be read back
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct HistoryError {
+    pub code: String,
+    pub message: String,
+    pub details: String,
+}
+
+impl From<&IloaderError> for HistoryError {
+    fn from(error: &IloaderError) -> Self {
+        HistoryError {
+            code: error.code().to_string(),
+            message: error.to_string(),
+            details: error.details(),
+        }
+    }
+}
+
+fn now() -> String {
+    chrono::Utc::now().to_rfc3339()
+}
+
+impl HistoryEntry {
+    pub fn new(operation: &str) -> Self {
+        HistoryEntry {
+            operation: operation.to_string(),
+            started_at: now(),
+            finished_at: None,
+            outcome: None,
+            device_udid: None,
+            device_name: None,
+            apple_id: None,
+            source: None,
+            bundle_id: None,
+            steps: vec![],
+        }
+    }
+
+    pub fn set_device(&mut self, device: &DeviceInfo) {
+        self.device_udid = Some(device.uuid.clone());
+        self.device_name = Some(device.name.clone());
+    }
+
+    pub fn step_started(&mut self, id: &str) {
+        self.steps.push(HistoryStep {
+            id: id.to_string(),
+            started_at: now(),
+            finished_at: None,
+            outcome: None,
+            error: None,
+        });
+    }
+
+    pub fn step_finished(&mut self, id: &str, outcome: &str, error: Option<&IloaderError>) {
+        if !self
+            .steps
+            .iter()
+            .any(|s| s.id == id && s.finished_at.is_none())
+        {
+            self.step_started(id);
+        }
+        if let Some(step) = self
+            .steps
+            .iter_mut()
+            .rev()
+            .find(|s| s.id == id && s.finished_at.is_none())
+        {
+            step.finished_at = Some(now());
+            step.outcome = Some(outcome.to_string());
+            step.error = error.map(HistoryError::from);
+        }
+    }
+
+    pub fn finish<T>(&mut self, result: &Result<T, IloaderError>) {
+        self.finished_at = Some(now());
+        self.outcome = Some(
+            match result {
+                Ok(_) => "succeeded",
+                Err(IloaderError::Cancelled) => "cancelled",
+                Err(_) => "failed",
+            }
+            .to_string(),
+        );
+    }
+}
+
+#[derive(Deserialize, Default, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct HistoryFilter {
+    pub operation: Option<String>,
+    pub device_udid: Option<String>,
+    pub apple_id: Option<String>,
+    pub outcome: Option<String>,
+}
+
+impl HistoryFilter {
+    pub fn matches(&self, entry: &HistoryEntry) -> bool {
+        self.operation
+            .as_ref()
+            .is_none_or(|o| *o == entry.operation)
+            && self
+                .device_udid
+                .as_ref()
+                .is_none_or(|d| entry.device_udid.as_ref() == Some(d))
+            && self
+                .apple_id
+                .as_ref()
+                .is_none_or(|a| entry.apple_id.as_ref() == Some(a))
+            && self
+                .outcome
+                .as_ref()
+                .is_none_or(|o| entry.outcome.as_ref() == Some(o))
+    }
+}
+
+// One JSON line per finished operation, shared by the app and iloader-cli
+pub struct HistoryJournal {
+    path: PathBuf,
+    lock: Mutex<()>,
+}
+
+impl HistoryJournal {
+    pub fn new(data_dir: &Path) -> Self {
+        HistoryJournal {
+            path: data_dir.join("history.jsonl"),
+            lock: Mutex::new(()),
+        }
+    }
+
+    fn io_err(&self, source: std::io::Error) -> IloaderError {
+        IloaderError::Io {
+            path: self.path.to_string_lossy().to_string(),
+            source,
+        }
+    }
+
+    pub fn append(&self, entry: &HistoryEntry) -> Result<(), IloaderError> {
+        let _guard = self.lock.lock().unwrap();
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| self.io_err(e))?;
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .map_err(|e| self.io_err(e))?;
+        let line = serde_json::to_string(entry).map_err(|e| self.io_err(e.into()))?;
+        writeln!(file, "{}", line).map_err(|e| self.io_err(e))
+    }
+
+    // Newest first. Lines that can't be parsed (e.g. from a newer version) are skipped
+    pub fn entries(&self, filter: &HistoryFilter) -> Result<Vec<HistoryEntry>, IloaderError> {
+        let _guard = self.lock.lock().unwrap();
+        let contents = match std::fs::read_to_string(&self.path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(self.io_err(e)),
+        };
+
+        Ok(contents
+            .lines()
+            .rev()
+            .filter_map(|line| serde_json::from_str::<HistoryEntry>(line).ok())
+            .filter(|entry| filter.matches(entry))
+            .collect())
+    }
+
+    pub fn clear(&self) -> Result<(), IloaderError> {
+        let _guard = self.lock.lock().unwrap();
+        match std::fs::remove_file(&self.path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(self.io_err(e)),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[tauri::command]
+pub fn list_operation_history(
+    journal: State<'_, HistoryJournal>,
+    filter: Option<HistoryFilter>,
+) -> Result<Vec<HistoryEntry>, IloaderError> {
+    journal.entries(&filter.unwrap_or_default())
+}
+
+#[tauri::command]
+pub fn clear_operation_history(journal: State<'_, HistoryJournal>) -> Result<(), IloaderError> {
+    journal.clear()
+}
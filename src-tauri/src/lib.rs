@@ -1,23 +1,48 @@
 #[macro_use]
-mod account;
+pub mod account;
+pub mod anisette;
+pub mod certificate;
+pub mod credentials;
 #[macro_use]
-mod device;
+pub mod device;
+pub mod device_backend;
+pub mod encrypted_storage;
 #[macro_use]
-mod sideload;
+pub mod sideload;
 #[macro_use]
-mod pairing;
-mod logging;
-mod operation;
+pub mod pairing;
+pub mod error;
+#[macro_use]
+pub mod history;
+pub mod logging;
+pub mod max_certs;
+#[macro_use]
+pub mod operation;
+pub mod profile;
+pub mod prompt;
+pub mod storage_migration;
+pub mod two_factor;
 
 use crate::{
     account::{
-        delete_account, delete_app_id, get_certificates, invalidate_account, list_app_ids,
-        logged_in_as, login_new, login_stored, reset_anisette_state, revoke_certificate,
+        activate_session, delete_account, delete_app_id, get_certificates, get_machine_name,
+        invalidate_account, list_app_ids, list_sessions, list_teams, logged_in_as, login_new,
+        login_stored, revoke_certificate, set_machine_name,
     },
+    anisette::{anisette_servers, check_anisette_servers, reset_anisette_state},
+    certificate::{export_certificate, import_certificate, revoke_certificates},
+    credentials::{check_saved_credentials, restore_saved_account},
     device::{DeviceInfoMutex, list_devices, set_selected_device},
+    device_backend::{DeviceBackendState, UsbmuxdBackend},
+    encrypted_storage::storage_backend,
+    history::{HistoryJournal, clear_operation_history, list_operation_history},
+    operation::{OperationTokens, cancel_operation},
     pairing::{export_pairing_cmd, installed_pairing_apps, place_pairing_cmd},
-    sideload::{SideloaderMutex, install_sidestore_operation, sideload_operation},
+    profile::{export_profile, import_profile},
+    sideload::{SessionRegistry, install_sidestore_operation, sideload_operation},
+    storage_migration::migrate_storage,
 };
+use std::sync::Arc;
 use tauri::Manager;
 use tracing_subscriber::{Layer, Registry, fmt, layer::SubscriberExt, util::SubscriberInitExt};
 
@@ -90,27 +115,67 @@ pub fn run() {
             }));
 
             app.manage(DeviceInfoMutex::new(None));
-            app.manage(SideloaderMutex::new(None));
+            app.manage::<DeviceBackendState>(Arc::new(UsbmuxdBackend));
+            app.manage(SessionRegistry::default());
+            app.manage(OperationTokens::default());
+            app.manage(HistoryJournal::new(
+                &app.path()
+                    .app_data_dir()
+                    .expect("failed to get app data dir"),
+            ));
+
+            // Pick up data left in the other backend, e.g. written while the keyring was down, then
+            // log saved accounts that are missing half their credentials
+            let data_dir = app
+                .path()
+                .app_data_dir()
+                .expect("failed to get app data dir");
+            let handle = app.handle().clone();
+            tauri::async_runtime::spawn_blocking(move || {
+                storage_migration::migrate(&data_dir, &storage_migration::known_emails(&handle));
+                if let Err(e) = credentials::check_app(&handle) {
+                    tracing::warn!("Unable to check saved credentials: {}", e.details());
+                }
+            });
             Ok(())
         })
         .invoke_handler(tauri::generate_handler![
             login_new,
             invalidate_account,
             logged_in_as,
+            list_sessions,
+            activate_session,
             login_stored,
             delete_account,
+            check_saved_credentials,
+            restore_saved_account,
             list_devices,
             sideload_operation,
             set_selected_device,
             install_sidestore_operation,
             get_certificates,
+            get_machine_name,
+            set_machine_name,
             revoke_certificate,
+            revoke_certificates,
+            import_certificate,
+            export_certificate,
             list_app_ids,
             delete_app_id,
+            list_teams,
             installed_pairing_apps,
             place_pairing_cmd,
             reset_anisette_state,
+            anisette_servers,
+            check_anisette_servers,
+            storage_backend,
+            migrate_storage,
+            export_profile,
+            import_profile,
             export_pairing_cmd,
+            cancel_operation,
+            list_operation_history,
+            clear_operation_history,
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
@@ -0,0 +1,180 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use serde::{Deserialize, Serialize};
+use tauri::AppHandle;
+use tauri_plugin_store::StoreExt;
+use tracing::{info, warn};
+
+use crate::{
+    account::{CertificateInfo, LoginPrompter},
+    error::IloaderError,
+    prompt::PromptError,
+};
+
+// What to do when the team already has the maximum number of development certificates and a new
+// one is needed. Stored in the frontend preferences as "maxCertsPolicy"
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "camelCase")]
+pub enum MaxCertsPolicy {
+    // Ask which certificates to revoke
+    #[default]
+    Prompt,
+    // Revoke the oldest certificate created by iloader on any machine
+    RevokeOldestIloader,
+    // Revoke every certificate created by one of these machines
+    RevokeMachines {
+        machines: Vec<String>,
+    },
+    // Don't revoke anything, the install fails with max_certs_reached
+    Fail,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Decision {
+    Revoke(Vec<String>),
+    Prompt,
+    Refuse,
+}
+
+// Older versions registered every certificate as "iloader", newer ones as "iloader-<hostname>"
+fn created_by_iloader(cert: &CertificateInfo) -> bool {
+    cert.machine_name.as_deref().is_some_and(|name| {
+        name.eq_ignore_ascii_case("iloader") || name.to_lowercase().starts_with("iloader-")
+    })
+}
+
+impl MaxCertsPolicy {
+    pub fn from_preferences(app: &AppHandle) -> Self {
+        app.store("preferences.json")
+            .ok()
+            .and_then(|store| store.get("maxCertsPolicy"))
+            .and_then(|policy| serde_json::from_value(policy).ok())
+            .unwrap_or_default()
+    }
+
+    // The serial numbers to revoke, and logs why
+    pub fn decide(&self, certs: &[CertificateInfo]) -> Decision {
+        let revoke = |certs: Vec<&CertificateInfo>, reason: &str| {
+            if certs.is_empty() {
+                warn!(
+                    "Maximum certificates reached and no certificate {}, not revoking anything",
+                    reason
+                );
+                return Decision::Refuse;
+            }
+            for cert in &certs {
+                info!(
+                    "Revoking certificate {} of machine {}: it is {}",
+                    cert.serial_number.as_deref().unwrap_or("unknown"),
+                    cert.machine_name.as_deref().unwrap_or("unknown"),
+                    reason
+                );
+            }
+            Decision::Revoke(
+                certs
+                    .into_iter()
+                    .filter_map(|cert| cert.serial_number.clone())
+                    .collect(),
+            )
+        };
+
+        match self {
+            MaxCertsPolicy::Prompt => Decision::Prompt,
+            MaxCertsPolicy::Fail => {
+                warn!("Maximum certificates reached, failing as configured");
+                Decision::Refuse
+            }
+            MaxCertsPolicy::RevokeOldestIloader => {
+                // A certificate without a creation date can't be shown to be the oldest, so it's never
+                // picked. The dates are all RFC 3339 in UTC, so they compare as strings
+                let oldest = certs
+                    .iter()
+                    .filter(|cert| cert.this_machine || created_by_iloader(cert))
+                    .filter_map(|cert| Some((cert.created.as_deref()?, cert)))
+                    .min_by_key(|(created, _)| *created)
+                    .map(|(_, cert)| cert);
+                revoke(
+                    oldest.into_iter().collect(),
+                    "the oldest certificate created by iloader",
+                )
+            }
+            MaxCertsPolicy::RevokeMachines { machines } => revoke(
+                certs
+                    .iter()
+                    .filter(|cert| {
+                        cert.machine_name.as_deref().is_some_and(|name| {
+                            machines.iter().any(|m| m.trim().eq_ignore_ascii_case(name))
+                        })
+                    })
+                    .collect(),
+                "from a machine configured to be revoked",
+            ),
+        }
+    }
+
+    // The serial numbers to revoke, asking the prompter when the policy says to. Blocks while the
+    // prompt is open
+    pub fn choose(
+        &self,
+        certs: &[CertificateInfo],
+        prompter: &dyn LoginPrompter,
+    ) -> Result<Vec<String>, Refusal> {
+        match self.decide(certs) {
+            Decision::Revoke(serials) => Ok(serials),
+            Decision::Refuse => Err(Refusal::Declined),
+            Decision::Prompt => match prompter.certificates_to_revoke(certs) {
+                Ok(serials) => {
+                    info!("Revoking certificates chosen by the user: {:?}", serials);
+                    Ok(serials)
+                }
+                Err(PromptError::Cancelled) => {
+                    warn!("Certificate revocation was cancelled");
+                    Err(Refusal::Declined)
+                }
+                Err(PromptError::TimedOut) => {
+                    warn!("Timed out waiting for certificates to revoke");
+                    Err(Refusal::TimedOut)
+                }
+            },
+        }
+    }
+}
+
+// Why nothing was revoked at the certificate limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Refusal {
+    // The policy refused, or the user cancelled the prompt
+    Declined,
+    TimedOut,
+}
+
+impl From<Refusal> for IloaderError {
+    fn from(refusal: Refusal) -> Self {
+        match refusal {
+            Refusal::Declined => IloaderError::MaxCertsReached,
+            Refusal::TimedOut => IloaderError::PromptTimeout {
+                prompt: "certificate revocation",
+            },
+        }
+    }
+}
+
+// Accounts whose last install stopped at the certificate limit. isideload only sees that no
+// certificates were chosen, so this is how the install error becomes max_certs_reached
+static REFUSED: Mutex<Option<HashMap<String, Refusal>>> = Mutex::new(None);
+
+pub fn record_refusal(email: &str, refusal: Refusal) {
+    REFUSED
+        .lock()
+        .unwrap()
+        .get_or_insert_with(HashMap::new)
+        .insert(email.to_lowercase(), refusal);
+}
+
+pub fn take_refusal(email: &str) -> Option<Refusal> {
+    REFUSED
+        .lock()
+        .unwrap()
+        .as_mut()
+        .and_then(|refused| refused.remove(&email.to_lowercase()))
+}
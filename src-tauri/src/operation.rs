@@ -1,70 +1,279 @@
+use std::{
+    collections::HashMap,
+    io::Write,
+    sync::{
+        Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
 use serde::Serialize;
-use tauri::{Emitter, Window};
+use tauri::{Emitter, State, Window};
+use tokio_util::sync::CancellationToken;
+use tracing::warn;
+
+use crate::{
+    error::IloaderError,
+    history::{HistoryEntry, HistoryJournal},
+};
 
 pub struct Operation<'a> {
     id: String,
-    window: &'a Window,
+    sink: &'a dyn ProgressSink,
+    cancel: CancellationToken,
+    journal: Option<&'a HistoryJournal>,
+    history: Mutex<HistoryEntry>,
+}
+
+// Cancellation tokens of the running operations, keyed by run ID. Every run has its own ID, so
+// two runs of the same operation on different sessions can be cancelled separately
+#[derive(Default)]
+pub struct OperationTokens {
+    tokens: Mutex<HashMap<String, CancellationToken>>,
+    next_id: AtomicU64,
+}
+
+impl OperationTokens {
+    // Registers a run under the ID the frontend picked, or a new one if it didn't. Returns the ID
+    // to remove it with
+    pub fn register(&self, run_id: Option<String>) -> (String, CancellationToken) {
+        let run_id = run_id
+            .unwrap_or_else(|| format!("run-{}", self.next_id.fetch_add(1, Ordering::Relaxed)));
+        let token = CancellationToken::new();
+        let previous = self
+            .tokens
+            .lock()
+            .unwrap()
+            .insert(run_id.clone(), token.clone());
+        if previous.is_some() {
+            warn!("Operation run {} was registered twice", run_id);
+        }
+        (run_id, token)
+    }
+
+    pub fn remove(&self, id: &str) {
+        self.tokens.lock().unwrap().remove(id);
+    }
+
+    pub fn cancel(&self, id: &str) -> bool {
+        match self.tokens.lock().unwrap().get(id) {
+            Some(token) => {
+                token.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[tauri::command]
+pub fn cancel_operation(tokens: State<'_, OperationTokens>, id: String) -> bool {
+    tokens.cancel(&id)
 }
 
 #[derive(Clone, Serialize)]
 #[serde(rename_all = "camelCase")]
-struct OperationUpdate<'a> {
-    update_type: &'a str,
-    step_id: &'a str,
-    extra_details: Option<String>,
+pub struct OperationUpdate<'a> {
+    pub update_type: &'a str,
+    pub step_id: &'a str,
+    pub extra_details: Option<&'a IloaderError>,
+}
+
+// Where an operation reports its progress: the frontend, a terminal, a log file or a test
+pub trait ProgressSink: Send + Sync {
+    fn update(&self, operation_id: &str, update: &OperationUpdate) -> Result<(), IloaderError>;
+}
+
+impl ProgressSink for Window {
+    fn update(&self, operation_id: &str, update: &OperationUpdate) -> Result<(), IloaderError> {
+        self.emit(&format!("operation_{}", operation_id), update)
+            .map_err(IloaderError::Emit)
+    }
+}
+
+// Human readable progress on stderr, for iloader-cli
+pub struct TerminalProgress;
+
+impl ProgressSink for TerminalProgress {
+    fn update(&self, _operation_id: &str, update: &OperationUpdate) -> Result<(), IloaderError> {
+        match update.extra_details {
+            Some(error) => eprintln!("✗ {}: {}", update.step_id, error),
+            None if update.update_type == "finished" => eprintln!("✓ {}", update.step_id),
+            None if update.update_type == "cancelled" => {
+                eprintln!("✗ {}: cancelled", update.step_id)
+            }
+            None => eprintln!("… {}", update.step_id),
+        }
+        Ok(())
+    }
+}
+
+// One JSON object per update, in the same shape as the frontend events plus the operation ID
+pub struct JsonLinesProgress<W: Write + Send> {
+    writer: Mutex<W>,
+}
+
+impl<W: Write + Send> JsonLinesProgress<W> {
+    pub fn new(writer: W) -> Self {
+        JsonLinesProgress {
+            writer: Mutex::new(writer),
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer.into_inner().unwrap()
+    }
+}
+
+impl<W: Write + Send> ProgressSink for JsonLinesProgress<W> {
+    fn update(&self, operation_id: &str, update: &OperationUpdate) -> Result<(), IloaderError> {
+        let line = serde_json::json!({
+            "operation": operation_id,
+            "updateType": update.update_type,
+            "stepId": update.step_id,
+            "extraDetails": update.extra_details,
+        });
+        let mut writer = self.writer.lock().unwrap();
+        writeln!(writer, "{}", line)
+            .and_then(|_| writer.flush())
+            .map_err(|source| IloaderError::Io {
+                path: "<progress output>".to_string(),
+                source,
+            })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordedUpdate {
+    pub operation_id: String,
+    pub update_type: String,
+    pub step_id: String,
+    // The error code of a failed step
+    pub error: Option<String>,
+}
+
+// Keeps every update in memory so tests can check the exact step sequence
+#[derive(Default)]
+pub struct RecordingProgress {
+    updates: Mutex<Vec<RecordedUpdate>>,
+}
+
+impl RecordingProgress {
+    pub fn updates(&self) -> Vec<RecordedUpdate> {
+        self.updates.lock().unwrap().clone()
+    }
+}
+
+impl ProgressSink for RecordingProgress {
+    fn update(&self, operation_id: &str, update: &OperationUpdate) -> Result<(), IloaderError> {
+        self.updates.lock().unwrap().push(RecordedUpdate {
+            operation_id: operation_id.to_string(),
+            update_type: update.update_type.to_string(),
+            step_id: update.step_id.to_string(),
+            error: update.extra_details.map(|e| e.code().to_string()),
+        });
+        Ok(())
+    }
 }
 
 impl<'a> Operation<'a> {
-    pub fn new(id: String, window: &'a Window) -> Operation<'a> {
-        Operation { id, window }
+    pub fn new(id: String, sink: &'a dyn ProgressSink, cancel: CancellationToken) -> Operation<'a> {
+        Operation {
+            history: Mutex::new(HistoryEntry::new(&id)),
+            id,
+            sink,
+            cancel,
+            journal: None,
+        }
+    }
+
+    pub fn with_journal(mut self, journal: Option<&'a HistoryJournal>) -> Operation<'a> {
+        self.journal = journal;
+        self
     }
 
-    pub fn move_on(&self, old_id: &str, new_id: &str) -> Result<(), String> {
+    // Adds details such as the device or Apple ID to the history entry
+    pub fn record(&self, f: impl FnOnce(&mut HistoryEntry)) {
+        f(&mut self.history.lock().unwrap());
+    }
+
+    // Writes the history entry. Failing to do so is logged rather than failing the operation
+    pub fn finish<T>(&self, result: Result<T, IloaderError>) -> Result<T, IloaderError> {
+        let mut history = self.history.lock().unwrap();
+        history.finish(&result);
+        if let Some(journal) = self.journal
+            && let Err(e) = journal.append(&history)
+        {
+            warn!("Unable to write operation history: {}", e.details());
+        }
+        result
+    }
+
+    pub fn cancel_token(&self) -> &CancellationToken {
+        &self.cancel
+    }
+
+    pub fn move_on(&self, old_id: &str, new_id: &str) -> Result<(), IloaderError> {
         self.complete(old_id)?;
-        self.start(new_id)
-    }
-
-    pub fn start(&self, id: &str) -> Result<(), String> {
-        self.window
-            .emit(
-                &format!("operation_{}", self.id),
-                OperationUpdate {
-                    update_type: "started",
-                    step_id: id,
-                    extra_details: None,
-                },
-            )
-            .map_err(|_| "Failed to emit status to frontend".to_string())
-    }
-
-    pub fn complete(&self, id: &str) -> Result<(), String> {
-        self.window
-            .emit(
-                &format!("operation_{}", self.id),
-                OperationUpdate {
-                    update_type: "finished",
-                    step_id: id,
-                    extra_details: None,
-                },
-            )
-            .map_err(|_| "Failed to emit status to frontend".to_string())
-    }
-
-    pub fn fail<T>(&self, id: &str, error: String) -> Result<T, String> {
-        self.window
-            .emit(
-                &format!("operation_{}", self.id),
-                OperationUpdate {
-                    update_type: "failed",
-                    step_id: id,
-                    extra_details: Some(error.clone()),
-                },
-            )
-            .map_err(|_| "Failed to emit status to frontend".to_string())?;
+        self.start(new_id)?;
+        self.check_cancelled(new_id)
+    }
+
+    pub fn check_cancelled(&self, id: &str) -> Result<(), IloaderError> {
+        if self.cancel.is_cancelled() {
+            return self.fail(id, IloaderError::Cancelled);
+        }
+        Ok(())
+    }
+
+    pub fn start(&self, id: &str) -> Result<(), IloaderError> {
+        self.record(|h| h.step_started(id));
+        self.sink.update(
+            &self.id,
+            &OperationUpdate {
+                update_type: "started",
+                step_id: id,
+                extra_details: None,
+            },
+        )
+    }
+
+    pub fn complete(&self, id: &str) -> Result<(), IloaderError> {
+        self.record(|h| h.step_finished(id, "finished", None));
+        self.sink.update(
+            &self.id,
+            &OperationUpdate {
+                update_type: "finished",
+                step_id: id,
+                extra_details: None,
+            },
+        )
+    }
+
+    pub fn fail<T>(&self, id: &str, error: IloaderError) -> Result<T, IloaderError> {
+        let update = if matches!(error, IloaderError::Cancelled) {
+            OperationUpdate {
+                update_type: "cancelled",
+                step_id: id,
+                extra_details: None,
+            }
+        } else {
+            OperationUpdate {
+                update_type: "failed",
+                step_id: id,
+                extra_details: Some(&error),
+            }
+        };
+        self.record(|h| h.step_finished(id, update.update_type, update.extra_details));
+        self.sink.update(&self.id, &update)?;
         Err(error)
     }
 
-    pub fn fail_if_err<T>(&self, id: &str, res: Result<T, String>) -> Result<T, String> {
+    pub fn fail_if_err<T>(
+        &self,
+        id: &str,
+        res: Result<T, IloaderError>,
+    ) -> Result<T, IloaderError> {
         match res {
             Ok(t) => Ok(t),
             Err(e) => self.fail::<T>(id, e),
@@ -1,15 +1,15 @@
 use std::collections::HashMap;
 
 // used https://github.com/jkcoxson/idevice_pair/ as a guide
-use idevice::{
-    IdeviceService, house_arrest::HouseArrestClient, installation_proxy::InstallationProxyClient,
-    lockdown::LockdownClient, pairing_file::PairingFile, usbmuxd::UsbmuxdConnection,
-};
 use serde::Serialize;
 use tauri::{AppHandle, State};
 use tauri_plugin_dialog::DialogExt;
 
-use crate::device::{DeviceInfo, DeviceInfoMutex, get_provider, get_provider_from_connection};
+use crate::{
+    device::{DeviceInfo, DeviceInfoMutex},
+    device_backend::{DeviceBackend, DeviceBackendState},
+    error::IloaderError,
+};
 
 const PAIRING_APPS: &[(&str, &str)] = &[
     ("SideStore", "ALTPairingFile.mobiledevicepairing"),
@@ -29,129 +29,77 @@ const PAIRING_APPS: &[(&str, &str)] = &[
 ];
 
 async fn pairing_file(
-    device: DeviceInfo,
-    usbmuxd: &mut UsbmuxdConnection,
-) -> Result<PairingFile, String> {
-    let provider = get_provider(&device).await?;
-
-    let mut pairing_file = usbmuxd.get_pair_record(&provider.udid).await.map_err(|e| {
-        format!(
-            "Failed to get pairing record for device {}: {}",
-            device.name, e
+    backend: &dyn DeviceBackend,
+    device: &DeviceInfo,
+) -> Result<Vec<u8>, IloaderError> {
+    let pairing_file = backend.pair_record(&device.uuid).await?;
+
+    backend
+        .set_value(
+            &device.uuid,
+            "EnableWifiDebugging",
+            true.into(),
+            Some("com.apple.mobile.wireless_lockdown"),
         )
-    })?;
-
-    pairing_file.udid = Some(provider.udid.clone());
-
-    let mut lc = LockdownClient::connect(&provider)
-        .await
-        .map_err(|e| format!("Failed to connect to lockdown: {}", e))?;
-
-    lc.start_session(&pairing_file)
-        .await
-        .map_err(|e| format!("Failed to start lockdown session: {}", e))?;
-
-    lc.set_value(
-        "EnableWifiDebugging",
-        true.into(),
-        Some("com.apple.mobile.wireless_lockdown"),
-    )
-    .await
-    .map_err(|e| format!("Failed to enable wifi debugging: {}", e))?;
+        .await?;
 
     Ok(pairing_file)
 }
 
 pub async fn place_pairing(
+    backend: &dyn DeviceBackend,
     device: DeviceInfo,
     bundle_id: String,
     path: String,
-) -> Result<(), String> {
-    let mut usbmuxd = UsbmuxdConnection::default()
-        .await
-        .map_err(|e| format!("Failed to connect to usbmuxd: {}", e))?;
-
-    let provider = get_provider_from_connection(&device, &mut usbmuxd).await?;
-
-    let pairing_file = pairing_file(device, &mut usbmuxd).await?;
-
-    let house_arrest_client = HouseArrestClient::connect(&provider)
-        .await
-        .map_err(|e| format!("Failed to connect to house arrest: {}", e))?;
+) -> Result<(), IloaderError> {
+    let pairing_file = pairing_file(backend, &device).await?;
 
-    let mut afc_client = house_arrest_client
-        .vend_documents(bundle_id)
-        .await
-        .map_err(|e| format!("Failed to vend documents: {}", e))?;
-
-    afc_client
-        .mk_dir(format!(
-            "/Documents/{}",
-            path.rsplit_once('/').map(|x| x.0).unwrap_or("")
-        ))
-        .await
-        .map_err(|e| format!("Failed to create Documents directory: {}", e))?;
-
-    let mut file = afc_client
-        .open(
-            format!("/Documents/{}", path),
-            idevice::afc::opcode::AfcFopenMode::Wr,
-        )
+    backend
+        .write_document(&device.uuid, &bundle_id, &path, &pairing_file)
         .await
-        .map_err(|e| format!("Failed to open file on device: {}", e))?;
-
-    file.write_entire(
-        &pairing_file
-            .serialize()
-            .map_err(|e| format!("Failed to serialize pairing file: {}", e))?,
-    )
-    .await
-    .map_err(|e| format!("Failed to write pairing file: {}", e))?;
-    file.close()
-        .await
-        .map_err(|e| format!("Failed to close file: {}", e))?;
-
-    Ok(())
 }
 
 #[tauri::command]
 pub async fn place_pairing_cmd(
     device_state: State<'_, DeviceInfoMutex>,
+    backend: State<'_, DeviceBackendState>,
     bundle_id: String,
     path: String,
-) -> Result<(), String> {
+) -> Result<(), IloaderError> {
     let device = {
         let device_guard = device_state.lock().unwrap();
         match &*device_guard {
             Some(d) => d.clone(),
-            None => return Err("No device selected".to_string()),
+            None => return Err(IloaderError::NoDeviceSelected),
         }
     };
 
-    place_pairing(device, bundle_id, path).await
+    place_pairing(backend.as_ref(), device, bundle_id, path).await
+}
+
+pub async fn export_pairing(
+    backend: &dyn DeviceBackend,
+    device: &DeviceInfo,
+) -> Result<Vec<u8>, IloaderError> {
+    pairing_file(backend, device).await
 }
 
 // prompt for a location to save the pairing file, then export it there. This is for advanced users who want to use the pairing file with other tools, or just want a backup of it. Normal users should use the "Place" button next to the app they want to pair with instead, which will transfer the pairing file automatically.
 #[tauri::command]
 pub async fn export_pairing_cmd(
     device_state: State<'_, DeviceInfoMutex>,
+    backend: State<'_, DeviceBackendState>,
     app: AppHandle,
-) -> Result<(), String> {
+) -> Result<(), IloaderError> {
     let device = {
         let device_guard = device_state.lock().unwrap();
         match &*device_guard {
             Some(d) => d.clone(),
-            None => return Err("No device selected".to_string()),
+            None => return Err(IloaderError::NoDeviceSelected),
         }
     };
 
-    let pairing_file = {
-        let mut usbmuxd = UsbmuxdConnection::default()
-            .await
-            .map_err(|e| format!("Failed to connect to usbmuxd: {}", e))?;
-
-        pairing_file(device, &mut usbmuxd).await?
-    };
+    let pairing_file = export_pairing(backend.as_ref(), &device).await?;
 
     let save_path = app
         .dialog()
@@ -164,22 +112,20 @@ pub async fn export_pairing_cmd(
     if let Some(save_path) = save_path
         && let Some(save_path) = save_path.as_path()
     {
-        tokio::fs::write(
-            save_path,
-            &pairing_file
-                .serialize()
-                .map_err(|e| format!("Failed to serialize pairing file: {}", e))?,
-        )
-        .await
-        .map_err(|e| format!("Failed to write pairing file: {}", e))?;
+        tokio::fs::write(save_path, &pairing_file)
+            .await
+            .map_err(|source| IloaderError::Io {
+                path: save_path.to_string_lossy().to_string(),
+                source,
+            })?;
 
         Ok(())
     } else {
-        Err("Save cancelled".to_string())
+        Err(IloaderError::SaveCancelled)
     }
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, Debug, Clone, PartialEq)]
 #[serde(rename_all = "camelCase")]
 pub struct PairingAppInfo {
     pub name: String,
@@ -190,30 +136,31 @@ pub struct PairingAppInfo {
 #[tauri::command]
 pub async fn installed_pairing_apps(
     device_state: State<'_, DeviceInfoMutex>,
-) -> Result<Vec<PairingAppInfo>, String> {
+    backend: State<'_, DeviceBackendState>,
+) -> Result<Vec<PairingAppInfo>, IloaderError> {
     let device = {
         let device_guard = device_state.lock().unwrap();
         match &*device_guard {
             Some(d) => d.clone(),
-            None => return Err("No device selected".to_string()),
+            None => return Err(IloaderError::NoDeviceSelected),
         }
     };
-    let provider = get_provider(&device).await?;
-    let mut installation_proxy = InstallationProxyClient::connect(&provider)
-        .await
-        .map_err(|e| format!("Failed to connect to installation proxy: {}", e))?;
 
-    let installed_apps = installation_proxy
-        .get_apps(Some("User"), None)
-        .await
-        .map_err(|e| format!("Failed to get installed apps: {}", e))?;
+    pairing_apps(backend.as_ref(), &device).await
+}
+
+pub async fn pairing_apps(
+    backend: &dyn DeviceBackend,
+    device: &DeviceInfo,
+) -> Result<Vec<PairingAppInfo>, IloaderError> {
+    let installed_apps = backend.installed_apps(&device.uuid).await?;
 
     let mut installed = HashMap::new();
     for (bundle_id, app) in installed_apps {
         let n = app
             .as_dictionary()
             .and_then(|x| x.get("CFBundleDisplayName").and_then(|x| x.as_string()))
-            .ok_or("Failed to parse installed apps".to_string())?;
+            .ok_or(IloaderError::InvalidAppList)?;
 
         if PAIRING_APPS.iter().any(|(name, _)| name == &n) {
             if bundle_id.contains("com.stik.stikdebug") {
@@ -238,24 +185,17 @@ pub async fn installed_pairing_apps(
 }
 
 pub async fn get_sidestore_info(
-    device: DeviceInfo,
+    backend: &dyn DeviceBackend,
+    device: &DeviceInfo,
     live_container: bool,
-) -> Result<Option<PairingAppInfo>, String> {
-    let provider = get_provider(&device).await?;
-    let mut installation_proxy = InstallationProxyClient::connect(&provider)
-        .await
-        .map_err(|e| format!("Failed to connect to installation proxy: {}", e))?;
-
-    let installed_apps = installation_proxy
-        .get_apps(Some("User"), None)
-        .await
-        .map_err(|e| format!("Failed to get installed apps: {}", e))?;
+) -> Result<Option<PairingAppInfo>, IloaderError> {
+    let installed_apps = backend.installed_apps(&device.uuid).await?;
 
     for (bundle_id, app) in installed_apps {
         let n = app
             .as_dictionary()
             .and_then(|x| x.get("CFBundleDisplayName").and_then(|x| x.as_string()))
-            .ok_or("Failed to parse installed apps".to_string())?;
+            .ok_or(IloaderError::InvalidAppList)?;
 
         if n == "SideStore" || (live_container && n == "LiveContainer") {
             return Ok(Some(PairingAppInfo {
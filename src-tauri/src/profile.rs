@@ -0,0 +1,374 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use base64::{Engine, engine::general_purpose::STANDARD as BASE64};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use tauri::{AppHandle, Manager};
+use tauri_plugin_dialog::DialogExt;
+use tauri_plugin_store::StoreExt;
+use tracing::{info, warn};
+
+use crate::{
+    account::{machine_name, save_password, stored_password},
+    certificate::signing_key_key,
+    encrypted_storage::{open, password_key, random_bytes, seal},
+    error::IloaderError,
+    storage_migration::{data_key, read_all, service_storage},
+};
+
+const FORMAT: &str = "iloader-profile";
+const VERSION: u32 = 1;
+
+// What's written to disk. Everything but the salt is in `data`, sealed with a key derived from
+// the export password
+#[derive(Serialize, Deserialize)]
+struct Bundle {
+    format: String,
+    version: u32,
+    salt: String,
+    data: String,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ProfileAccount {
+    email: String,
+    // Only for saved accounts whose password could be read
+    password: Option<String>,
+    team_id: Option<String>,
+    // isideload only reuses a certificate registered under the machine name it's given, so the
+    // other machine takes this one over along with the signing key
+    #[serde(default)]
+    machine_name: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Profile {
+    exported_at: String,
+    accounts: Vec<ProfileAccount>,
+    // Storage entries by keyring service and key: each account's signing key, session tokens and
+    // anisette identity. Data entries are base64 encoded
+    storage: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProfileSummary {
+    pub accounts: Vec<String>,
+    pub entries: usize,
+    // Entries left alone on import because this machine already has them
+    pub skipped: usize,
+    // Accounts without a saved signing key. The other machine registers a new certificate for these
+    pub missing_identities: Vec<String>,
+}
+
+// The saved accounts, team choices and machine names to add to data.json after an import
+#[derive(Debug)]
+pub struct ImportedProfile {
+    pub summary: ProfileSummary,
+    pub ids: Vec<String>,
+    pub teams: Vec<(String, String)>,
+    pub machine_names: Vec<(String, String)>,
+}
+
+fn export_err<E: std::fmt::Display>(e: E) -> IloaderError {
+    IloaderError::Profile {
+        action: "export",
+        report: e.to_string(),
+    }
+}
+
+fn import_err<E: std::fmt::Display>(e: E) -> IloaderError {
+    IloaderError::Profile {
+        action: "import",
+        report: e.to_string(),
+    }
+}
+
+pub fn export(
+    data_dir: &Path,
+    ids: &[String],
+    teams: &Map<String, Value>,
+    machine_names: Option<&Value>,
+    password: &str,
+) -> Result<(Vec<u8>, ProfileSummary), IloaderError> {
+    if password.is_empty() {
+        return Err(export_err("A password is required"));
+    }
+
+    let mut emails: Vec<String> = ids.to_vec();
+    emails.extend(
+        teams
+            .keys()
+            .filter(|email| !ids.iter().any(|id| id.eq_ignore_ascii_case(email)))
+            .cloned(),
+    );
+    let accounts: Vec<ProfileAccount> = emails
+        .iter()
+        .map(|email| ProfileAccount {
+            email: email.clone(),
+            password: ids
+                .contains(email)
+                .then(|| stored_password(email))
+                .and_then(|password| {
+                    password
+                        .inspect_err(|e| warn!("Exporting {} without its password: {}", email, e))
+                        .ok()
+                }),
+            team_id: teams
+                .get(&email.to_lowercase())
+                .and_then(|team| team.as_str())
+                .map(str::to_string),
+            machine_name: Some(machine_name(machine_names, email)),
+        })
+        .collect();
+    let storage = read_all(data_dir, &emails).map_err(export_err)?;
+
+    let shared = storage.get("iloader");
+    let missing_identities: Vec<String> = emails
+        .iter()
+        .filter(|email| {
+            !shared.is_some_and(|entries| entries.contains_key(&signing_key_key(email)))
+        })
+        .cloned()
+        .collect();
+    for email in &missing_identities {
+        warn!(
+            "Exporting {} without a signing key, importing it will need a new certificate",
+            email
+        );
+    }
+
+    let summary = ProfileSummary {
+        accounts: emails,
+        entries: storage.values().map(BTreeMap::len).sum(),
+        skipped: 0,
+        missing_identities,
+    };
+    let profile = Profile {
+        exported_at: chrono::Utc::now().to_rfc3339(),
+        accounts,
+        storage,
+    };
+
+    let salt = random_bytes(16);
+    let plaintext = serde_json::to_vec(&profile).map_err(export_err)?;
+    let sealed = seal(&password_key(password.as_bytes(), &salt), &plaintext)
+        .ok_or_else(|| export_err("Unable to encrypt the profile"))?;
+    let bundle = Bundle {
+        format: FORMAT.to_string(),
+        version: VERSION,
+        salt: BASE64.encode(salt),
+        data: BASE64.encode(sealed),
+    };
+    let bundle = serde_json::to_vec_pretty(&bundle).map_err(export_err)?;
+    Ok((bundle, summary))
+}
+
+// Restores the storage entries and saved passwords. Entries this machine already has are kept
+// unless `overwrite` is set
+pub fn import(
+    data_dir: &Path,
+    bundle: &[u8],
+    password: &str,
+    overwrite: bool,
+) -> Result<ImportedProfile, IloaderError> {
+    let bundle: Bundle =
+        serde_json::from_slice(bundle).map_err(|_| import_err("This is not an iloader profile"))?;
+    if bundle.format != FORMAT {
+        return Err(import_err("This is not an iloader profile"));
+    }
+    if bundle.version > VERSION {
+        return Err(import_err(
+            "This profile was exported by a newer version of iloader",
+        ));
+    }
+    let salt = BASE64.decode(&bundle.salt).map_err(import_err)?;
+    let sealed = BASE64.decode(&bundle.data).map_err(import_err)?;
+    let plaintext = open(&password_key(password.as_bytes(), &salt), &sealed)
+        .ok_or(IloaderError::ProfilePassword)?;
+    let profile: Profile = serde_json::from_slice(&plaintext).map_err(import_err)?;
+
+    let mut imported = ImportedProfile {
+        summary: ProfileSummary {
+            accounts: vec![],
+            entries: 0,
+            skipped: 0,
+            missing_identities: vec![],
+        },
+        ids: vec![],
+        teams: vec![],
+        machine_names: vec![],
+    };
+    for (service, entries) in &profile.storage {
+        let Some(storage) = service_storage(data_dir, service) else {
+            warn!("Skipping unknown storage {} in profile", service);
+            continue;
+        };
+        for (key, value) in entries {
+            let data = data_key(key);
+            let present = if data {
+                matches!(storage.retrieve_data(key), Ok(Some(_)))
+            } else {
+                matches!(storage.retrieve(key), Ok(Some(_)))
+            };
+            if !overwrite && present {
+                imported.summary.skipped += 1;
+                continue;
+            }
+            if data {
+                let value = BASE64.decode(value).map_err(import_err)?;
+                storage.store_data(key, &value).map_err(import_err)?;
+            } else {
+                storage.store(key, value).map_err(import_err)?;
+            }
+            imported.summary.entries += 1;
+        }
+    }
+    for account in profile.accounts {
+        if let Some(password) = &account.password {
+            match save_password(&account.email, password) {
+                Ok(()) => imported.ids.push(account.email.clone()),
+                Err(e) => warn!(
+                    "Unable to save the password of {}: {}",
+                    account.email,
+                    e.details()
+                ),
+            }
+        }
+        if let Some(team_id) = account.team_id {
+            imported.teams.push((account.email.to_lowercase(), team_id));
+        }
+        if let Some(machine_name) = account.machine_name {
+            imported
+                .machine_names
+                .push((account.email.to_lowercase(), machine_name));
+        }
+        imported.summary.accounts.push(account.email);
+    }
+
+    info!(
+        "Imported a profile exported at {} with {} accounts, {} storage entries ({} kept)",
+        profile.exported_at,
+        imported.summary.accounts.len(),
+        imported.summary.entries,
+        imported.summary.skipped
+    );
+    Ok(imported)
+}
+
+fn data_dir(app: &AppHandle, action: &'static str) -> Result<PathBuf, IloaderError> {
+    app.path()
+        .app_data_dir()
+        .map_err(|e| IloaderError::Profile {
+            action,
+            report: e.to_string(),
+        })
+}
+
+#[tauri::command]
+pub async fn export_profile(
+    app: AppHandle,
+    password: String,
+) -> Result<ProfileSummary, IloaderError> {
+    let store = app.store("data.json").map_err(IloaderError::Store)?;
+    let ids: Vec<String> = store
+        .get("ids")
+        .and_then(|ids| ids.as_array().cloned())
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|id| id.as_str().map(str::to_string))
+        .collect();
+    let teams = match store.get("teams") {
+        Some(Value::Object(teams)) => teams,
+        _ => Map::new(),
+    };
+    let machine_names = store.get("machineNames");
+    let (bundle, summary) = export(
+        &data_dir(&app, "export")?,
+        &ids,
+        &teams,
+        machine_names.as_ref(),
+        &password,
+    )?;
+
+    let save_path = app
+        .dialog()
+        .file()
+        .add_filter("iloader Profile", &["iloaderprofile"])
+        .set_file_name("profile.iloaderprofile")
+        .set_title("Export Profile")
+        .blocking_save_file();
+    let Some(save_path) = save_path.as_ref().and_then(|path| path.as_path()) else {
+        return Err(IloaderError::SaveCancelled);
+    };
+    tokio::fs::write(save_path, &bundle)
+        .await
+        .map_err(|source| IloaderError::Io {
+            path: save_path.to_string_lossy().to_string(),
+            source,
+        })?;
+    Ok(summary)
+}
+
+#[tauri::command]
+pub async fn import_profile(
+    app: AppHandle,
+    password: String,
+    overwrite: bool,
+) -> Result<ProfileSummary, IloaderError> {
+    let open_path = app
+        .dialog()
+        .file()
+        .add_filter("iloader Profile", &["iloaderprofile"])
+        .set_title("Import Profile")
+        .blocking_pick_file();
+    let Some(open_path) = open_path.as_ref().and_then(|path| path.as_path()) else {
+        return Err(IloaderError::SaveCancelled);
+    };
+    let bundle = tokio::fs::read(open_path)
+        .await
+        .map_err(|source| IloaderError::Io {
+            path: open_path.to_string_lossy().to_string(),
+            source,
+        })?;
+    let imported = import(&data_dir(&app, "import")?, &bundle, &password, overwrite)?;
+
+    let store = app.store("data.json").map_err(IloaderError::Store)?;
+    let mut ids = store
+        .get("ids")
+        .and_then(|ids| ids.as_array().cloned())
+        .unwrap_or_default();
+    for id in imported.ids {
+        let value = Value::String(id);
+        if !ids.contains(&value) {
+            ids.push(value);
+        }
+    }
+    store.set("ids", Value::Array(ids));
+    let mut teams = match store.get("teams") {
+        Some(Value::Object(teams)) => teams,
+        _ => Map::new(),
+    };
+    for (email, team_id) in imported.teams {
+        if overwrite || !teams.contains_key(&email) {
+            teams.insert(email, Value::String(team_id));
+        }
+    }
+    store.set("teams", Value::Object(teams));
+    let mut machine_names = match store.get("machineNames") {
+        Some(Value::Object(names)) => names,
+        _ => Map::new(),
+    };
+    for (email, machine_name) in imported.machine_names {
+        if overwrite || !machine_names.contains_key(&email) {
+            machine_names.insert(email, Value::String(machine_name));
+        }
+    }
+    store.set("machineNames", Value::Object(machine_names));
+    Ok(imported.summary)
+}
@@ -0,0 +1,93 @@
+use std::{sync::mpsc, time::Duration};
+
+use serde::Serialize;
+use tauri::{AppHandle, Emitter, Listener, Window};
+use tauri_plugin_store::StoreExt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PromptError {
+    TimedOut,
+    Cancelled,
+}
+
+// How long prompts wait for an answer, from the frontend preferences
+#[derive(Debug, Clone, Copy)]
+pub struct PromptTimeouts {
+    pub two_factor: Duration,
+    // Certificate revocation and team selection
+    pub selection: Duration,
+}
+
+impl Default for PromptTimeouts {
+    fn default() -> Self {
+        PromptTimeouts {
+            two_factor: Duration::from_secs(120),
+            selection: Duration::from_secs(300),
+        }
+    }
+}
+
+impl PromptTimeouts {
+    pub fn from_preferences(app: &AppHandle) -> Self {
+        let mut timeouts = PromptTimeouts::default();
+        let Ok(store) = app.store("preferences.json") else {
+            return timeouts;
+        };
+        let seconds = |key: &str| {
+            store
+                .get(key)
+                .and_then(|v| v.as_u64())
+                .filter(|s| *s > 0)
+                .map(Duration::from_secs)
+        };
+        if let Some(timeout) = seconds("twoFactorTimeout") {
+            timeouts.two_factor = timeout;
+        }
+        if let Some(timeout) = seconds("promptTimeout") {
+            timeouts.selection = timeout;
+        }
+        timeouts
+    }
+}
+
+// Asks the frontend a question and blocks until it answers. The frontend answers on the
+// response event, or cancels by emitting "prompt-cancelled" with the name of the request event
+pub fn ask<T: Serialize + Clone>(
+    window: &Window,
+    event: &str,
+    payload: T,
+    response_event: &str,
+    timeout: Duration,
+) -> Result<String, PromptError> {
+    let (tx, rx) = mpsc::channel::<Result<String, PromptError>>();
+    let answer_id = window.listen(response_event, {
+        let tx = tx.clone();
+        move |e| {
+            let _ = tx.send(Ok(e.payload().to_string()));
+        }
+    });
+    let cancel_id = window.listen("prompt-cancelled", {
+        let event = event.to_string();
+        move |e| {
+            if serde_json::from_str::<String>(e.payload()).is_ok_and(|cancelled| cancelled == event)
+            {
+                let _ = tx.send(Err(PromptError::Cancelled));
+            }
+        }
+    });
+
+    // Listen first so an instant answer isn't missed
+    let result = match window.emit(event, payload) {
+        Ok(()) => rx
+            .recv_timeout(timeout)
+            .unwrap_or(Err(PromptError::TimedOut)),
+        Err(e) => {
+            tracing::error!("Failed to emit {} event: {}", event, e);
+            Err(PromptError::Cancelled)
+        }
+    };
+
+    window.unlisten(answer_id);
+    window.unlisten(cancel_id);
+    result
+}
@@ -1,174 +1,608 @@
-use std::{path::PathBuf, sync::Mutex};
+use std::{
+    collections::HashMap,
+    io::{Cursor, Read},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
 
 use crate::{
-    device::{get_provider, DeviceInfoMutex},
-    operation::Operation,
+    device::{DeviceInfo, DeviceInfoMutex, get_provider},
+    device_backend::{DeviceBackend, DeviceBackendState},
+    error::IloaderError,
+    history::HistoryJournal,
+    max_certs,
+    operation::{Operation, OperationTokens},
     pairing::{get_sidestore_info, place_pairing},
 };
-use isideload::sideload::{application::SpecialApp, sideloader::Sideloader};
-use tauri::{AppHandle, Manager, State, Window};
+use isideload::{
+    dev::{app_ids::AppIdsApi, developer_session::DeveloperSession, teams::DeveloperTeam},
+    sideload::{application::SpecialApp, sideloader::Sideloader},
+};
+use serde::Serialize;
+use tauri::{State, Window};
+use tokio::io::AsyncWriteExt;
+use tokio_util::sync::CancellationToken;
+use tracing::warn;
+
+// The logged in Sideloaders, keyed by lowercased email. Each session has its own async lock, so a
+// command waits for a running install on the same account instead of seeing a logged out state,
+// while other accounts stay usable.
+#[derive(Default)]
+pub struct SessionRegistry {
+    sessions: Mutex<HashMap<String, Arc<tokio::sync::Mutex<Sideloader>>>>,
+    // The session used by commands that don't name an account
+    active: Mutex<Option<String>>,
+}
 
-pub type SideloaderMutex = Mutex<Option<Sideloader>>;
+pub struct SideloaderGuard {
+    guard: tokio::sync::OwnedMutexGuard<Sideloader>,
+}
 
-pub struct SideloaderGuard<'a> {
-    state: &'a SideloaderMutex,
-    sideloader: Option<Sideloader>,
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionInfo {
+    pub email: String,
+    pub active: bool,
+    // An operation is currently using this session
+    pub busy: bool,
 }
 
-impl<'a> SideloaderGuard<'a> {
-    pub fn take(state: &'a SideloaderMutex) -> Result<Self, String> {
-        let mut guard = state.lock().unwrap();
-        let sideloader = guard.take().ok_or_else(|| "Not logged in".to_string())?;
-        Ok(Self {
-            state,
-            sideloader: Some(sideloader),
+impl SessionRegistry {
+    // Replaces any existing session for the same account and makes it the active one
+    pub fn insert(&self, sideloader: Sideloader) {
+        let email = sideloader.get_email().to_lowercase();
+        self.sessions
+            .lock()
+            .unwrap()
+            .insert(email.clone(), Arc::new(tokio::sync::Mutex::new(sideloader)));
+        *self.active.lock().unwrap() = Some(email);
+    }
+
+    // Removes the named session, or the active one. Operations already using it finish normally
+    pub fn remove(&self, email: Option<&str>) -> Option<String> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let mut active = self.active.lock().unwrap();
+        let email = match email {
+            Some(email) => email.to_lowercase(),
+            None => active.clone()?,
+        };
+        sessions.remove(&email)?;
+
+        if active.as_deref() == Some(email.as_str()) {
+            *active = sessions.keys().min().cloned();
+        }
+        Some(email)
+    }
+
+    pub fn activate(&self, email: &str) -> Result<(), IloaderError> {
+        let email = email.to_lowercase();
+        if !self.sessions.lock().unwrap().contains_key(&email) {
+            return Err(IloaderError::SessionNotFound { email });
+        }
+        *self.active.lock().unwrap() = Some(email);
+        Ok(())
+    }
+
+    // Answers immediately, even while an operation holds a session
+    pub fn active_email(&self) -> Option<String> {
+        self.active.lock().unwrap().clone()
+    }
+
+    pub fn sessions(&self) -> Vec<SessionInfo> {
+        let active = self.active_email();
+        let mut sessions: Vec<SessionInfo> = self
+            .sessions
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(email, sideloader)| SessionInfo {
+                email: email.clone(),
+                active: active.as_deref() == Some(email.as_str()),
+                busy: sideloader.try_lock().is_err(),
+            })
+            .collect();
+        sessions.sort_by(|a, b| a.email.cmp(&b.email));
+        sessions
+    }
+
+    // Waits for any other command using the same session to finish
+    pub async fn lock(&self, email: Option<&str>) -> Result<SideloaderGuard, IloaderError> {
+        let sideloader = {
+            let sessions = self.sessions.lock().unwrap();
+            match email {
+                Some(email) => sessions
+                    .get(&email.to_lowercase())
+                    .cloned()
+                    .ok_or_else(|| IloaderError::SessionNotFound {
+                        email: email.to_string(),
+                    })?,
+                None => self
+                    .active
+                    .lock()
+                    .unwrap()
+                    .as_ref()
+                    .and_then(|email| sessions.get(email).cloned())
+                    .ok_or(IloaderError::NotLoggedIn)?,
+            }
+        };
+        Ok(SideloaderGuard {
+            guard: sideloader.lock_owned().await,
         })
     }
 
+    pub fn select(&self, email: Option<String>) -> SessionRef<'_> {
+        SessionRef {
+            registry: self,
+            email,
+        }
+    }
+}
+
+impl SideloaderGuard {
     pub fn get_mut(&mut self) -> &mut Sideloader {
-        self.sideloader
-            .as_mut()
-            .expect("Sideloader should be present")
+        &mut self.guard
     }
 }
 
-impl Drop for SideloaderGuard<'_> {
-    fn drop(&mut self) {
-        let mut guard = self.state.lock().unwrap();
-        *guard = self.sideloader.take();
+// The session an operation should use, looked up only when the operation gets to it
+pub struct SessionRef<'a> {
+    registry: &'a SessionRegistry,
+    email: Option<String>,
+}
+
+impl SessionRef<'_> {
+    pub async fn lock(&self) -> Result<SideloaderGuard, IloaderError> {
+        self.registry.lock(self.email.as_deref()).await
     }
 }
 
 pub async fn sideload(
-    device_state: State<'_, DeviceInfoMutex>,
-    sideloader_state: State<'_, SideloaderMutex>,
+    device: &DeviceInfo,
+    sideloader: &mut Sideloader,
+    app_path: String,
+) -> Result<Option<SpecialApp>, IloaderError> {
+    let provider = get_provider(&device.uuid).await?;
+    let email = sideloader.get_email().to_string();
+    // A refusal left over from an earlier install says nothing about this one
+    max_certs::take_refusal(&email);
+
+    sideloader
+        .install_app(&provider, app_path.into(), false)
+        .await
+        .map_err(|e| {
+            // Nothing was revoked at the certificate limit, so the install had no certificate
+            match max_certs::take_refusal(&email) {
+                Some(refusal) => refusal.into(),
+                None => IloaderError::Install {
+                    report: e.to_string(),
+                },
+            }
+        })
+}
+
+// install_app can't be stopped from the inside, so on cancel its future is dropped and any
+// App ID it registered for the app in the meantime is deleted again
+pub async fn sideload_cancellable(
+    device: &DeviceInfo,
+    sideloader: &mut Sideloader,
     app_path: String,
-) -> Result<Option<SpecialApp>, String> {
-    let device = {
-        let device_lock = device_state.lock().unwrap();
-        match &*device_lock {
-            Some(d) => d.clone(),
-            None => return Err("No device selected".to_string()),
+    cancel: &CancellationToken,
+) -> Result<Option<SpecialApp>, IloaderError> {
+    if cancel.is_cancelled() {
+        return Err(IloaderError::Cancelled);
+    }
+    let app_ids = AppIdList::for_app(sideloader, &app_path).await;
+
+    // The App IDs the app already had only matter if the install is cancelled, so they're listed
+    // alongside it rather than before it. isideload registers App IDs after the device and
+    // certificate steps, so the list is back well before then
+    let listing = async {
+        match &app_ids {
+            Some(app_ids) => Some(app_ids.list().await),
+            None => None,
+        }
+    };
+    tokio::pin!(listing);
+    let mut install = Box::pin(sideload(device, sideloader, app_path));
+    let mut before = None;
+    let result = loop {
+        tokio::select! {
+            result = &mut install => break result,
+            _ = cancel.cancelled() => break Err(IloaderError::Cancelled),
+            listed = &mut listing, if before.is_none() => before = Some(listed),
         }
     };
+    drop(install);
+    if !matches!(result, Err(IloaderError::Cancelled)) {
+        return result;
+    }
+
+    let before = match before {
+        Some(before) => before,
+        None => listing.await,
+    };
+    match (&app_ids, before) {
+        (Some(app_ids), Some(Ok(before))) => app_ids.remove_new(&before).await,
+        (_, Some(Err(e))) => warn!(
+            "Unable to list App IDs before installing, so none are cleaned up after cancelling: {}",
+            e.details()
+        ),
+        _ => {}
+    }
+    result
+}
 
-    let provider = get_provider(&device).await?;
+// The App IDs isideload registers for an app: `<bundle ID>.<team ID>` for the app and that
+// followed by the rest of the bundle ID for each extension
+struct AppIdList {
+    dev_session: DeveloperSession,
+    team: DeveloperTeam,
+    identifier: String,
+}
 
-    let mut sideloader = SideloaderGuard::take(&sideloader_state)?;
+impl AppIdList {
+    // None when the app's bundle ID can't be read or there's no team, so there's nothing to list
+    async fn for_app(sideloader: &mut Sideloader, app_path: &str) -> Option<AppIdList> {
+        let bundle_id = ipa_bundle_id(Path::new(app_path))?;
+        let team = match sideloader.get_team().await {
+            Ok(team) => team,
+            Err(e) => {
+                warn!("Unable to get the team to clean up App IDs for: {}", e);
+                return None;
+            }
+        };
+        Some(AppIdList {
+            dev_session: sideloader.get_dev_session().clone(),
+            identifier: format!("{}.{}", bundle_id, team.team_id),
+            team,
+        })
+    }
 
-    sideloader
-        .get_mut()
-        .install_app(&provider, app_path.into(), false)
+    fn matches(&self, identifier: &str) -> bool {
+        identifier == self.identifier
+            || identifier
+                .strip_prefix(&self.identifier)
+                .is_some_and(|rest| rest.starts_with('.'))
+    }
+
+    async fn list(&self) -> Result<Vec<String>, IloaderError> {
+        let response = self
+            .dev_session
+            .clone()
+            .list_app_ids(&self.team, None)
+            .await
+            .map_err(|e| IloaderError::DeveloperServices {
+                action: "list App IDs",
+                report: e.to_string(),
+            })?;
+
+        Ok(response
+            .app_ids
+            .iter()
+            .filter(|app_id| self.matches(&app_id.identifier))
+            .map(|app_id| app_id.app_id_id.clone())
+            .collect())
+    }
+
+    async fn remove_new(&self, before: &[String]) {
+        let after = match self.list().await {
+            Ok(after) => after,
+            Err(e) => {
+                warn!(
+                    "Unable to clean up App IDs after cancelling: {}",
+                    e.details()
+                );
+                return;
+            }
+        };
+
+        let mut dev_session = self.dev_session.clone();
+        for app_id_id in after.iter().filter(|id| !before.contains(id)) {
+            if let Err(e) = dev_session.delete_app_id(&self.team, app_id_id, None).await {
+                warn!(
+                    "Unable to delete App ID {} after cancelling: {}",
+                    app_id_id, e
+                );
+            }
+        }
+    }
+}
+
+async fn sideload_selected(
+    op: &Operation<'_>,
+    device: &DeviceInfo,
+    session: &SessionRef<'_>,
+    app_path: String,
+) -> Result<Option<SpecialApp>, IloaderError> {
+    // Another operation may hold the session for a while, so waiting for it can be cancelled too
+    let mut sideloader = tokio::select! {
+        sideloader = session.lock() => sideloader?,
+        _ = op.cancel_token().cancelled() => return Err(IloaderError::Cancelled),
+    };
+    op.record(|h| h.apple_id = Some(sideloader.get_mut().get_email().to_string()));
+
+    sideload_cancellable(device, sideloader.get_mut(), app_path, op.cancel_token()).await
+}
+
+fn selected_device(device_state: &DeviceInfoMutex) -> Result<DeviceInfo, IloaderError> {
+    let device_lock = device_state.lock().unwrap();
+    match &*device_lock {
+        Some(d) => Ok(d.clone()),
+        None => Err(IloaderError::NoDeviceSelected),
+    }
+}
+
+// The CFBundleIdentifier of the app inside an IPA, before isideload adds the team ID
+fn ipa_bundle_id(path: &Path) -> Option<String> {
+    let mut archive = zip::ZipArchive::new(std::fs::File::open(path).ok()?).ok()?;
+    let info_plist = archive
+        .file_names()
+        .find(|name| {
+            let parts: Vec<&str> = name.split('/').collect();
+            parts.len() == 3
+                && parts[0] == "Payload"
+                && parts[1].ends_with(".app")
+                && parts[2] == "Info.plist"
+        })?
+        .to_string();
+
+    let mut contents = Vec::new();
+    archive
+        .by_name(&info_plist)
+        .ok()?
+        .read_to_end(&mut contents)
+        .ok()?;
+    plist::Value::from_reader(Cursor::new(contents))
+        .ok()?
+        .as_dictionary()?
+        .get("CFBundleIdentifier")?
+        .as_string()
+        .map(str::to_string)
+}
+
+// Finds the bundle ID the app was actually installed under
+async fn installed_bundle_id(backend: &dyn DeviceBackend, udid: &str, bundle_id: &str) -> String {
+    let prefix = format!("{}.", bundle_id);
+    backend
+        .installed_apps(udid)
         .await
-        .map_err(|e| e.to_string())
+        .ok()
+        .and_then(|apps| {
+            apps.into_keys()
+                .find(|id| id == bundle_id || id.starts_with(&prefix))
+        })
+        .unwrap_or_else(|| bundle_id.to_string())
 }
 
+// `run_id` is what cancel_operation takes to cancel this run
 #[tauri::command]
+#[allow(clippy::too_many_arguments)]
 pub async fn sideload_operation(
     window: Window,
+    tokens: State<'_, OperationTokens>,
+    journal: State<'_, HistoryJournal>,
+    backend: State<'_, DeviceBackendState>,
     device_state: State<'_, DeviceInfoMutex>,
-    sideloader_state: State<'_, SideloaderMutex>,
+    sessions: State<'_, SessionRegistry>,
+    app_path: String,
+    email: Option<String>,
+    run_id: Option<String>,
+) -> Result<(), IloaderError> {
+    let (run_id, cancel) = tokens.register(run_id);
+    let op = Operation::new("sideload".to_string(), &window, cancel).with_journal(Some(&journal));
+    let result = run_sideload(
+        &op,
+        backend.as_ref(),
+        &device_state,
+        &sessions.select(email),
+        app_path,
+    )
+    .await;
+    tokens.remove(&run_id);
+    result
+}
+
+pub async fn run_sideload(
+    op: &Operation<'_>,
+    backend: &dyn DeviceBackend,
+    device_state: &DeviceInfoMutex,
+    session: &SessionRef<'_>,
     app_path: String,
-) -> Result<(), String> {
-    let op = Operation::new("sideload".to_string(), &window);
+) -> Result<(), IloaderError> {
+    op.record(|h| h.source = Some(app_path.clone()));
+    let result = sideload_steps(op, backend, device_state, session, app_path).await;
+    op.finish(result)
+}
+
+async fn sideload_steps(
+    op: &Operation<'_>,
+    backend: &dyn DeviceBackend,
+    device_state: &DeviceInfoMutex,
+    session: &SessionRef<'_>,
+    app_path: String,
+) -> Result<(), IloaderError> {
     op.start("install")?;
+    op.check_cancelled("install")?;
+    let device = op.fail_if_err("install", selected_device(device_state))?;
+    op.record(|h| h.set_device(&device));
+
+    let bundle_id = ipa_bundle_id(Path::new(&app_path));
     op.fail_if_err(
         "install",
-        sideload(device_state, sideloader_state, app_path).await,
+        sideload_selected(op, &device, session, app_path).await,
     )?;
+    if let Some(bundle_id) = bundle_id {
+        let installed = installed_bundle_id(backend, &device.uuid, &bundle_id).await;
+        op.record(|h| h.bundle_id = Some(installed));
+    }
     op.complete("install")?;
     Ok(())
 }
 
 #[tauri::command]
+#[allow(clippy::too_many_arguments)]
 pub async fn install_sidestore_operation(
-    handle: AppHandle,
     window: Window,
+    tokens: State<'_, OperationTokens>,
+    journal: State<'_, HistoryJournal>,
     device_state: State<'_, DeviceInfoMutex>,
-    sideloader_state: State<'_, SideloaderMutex>,
+    backend: State<'_, DeviceBackendState>,
+    sessions: State<'_, SessionRegistry>,
     nightly: bool,
     live_container: bool,
-) -> Result<(), String> {
-    let op = Operation::new("install_sidestore".to_string(), &window);
-    op.start("download")?;
+    email: Option<String>,
+    run_id: Option<String>,
+) -> Result<(), IloaderError> {
+    let (run_id, cancel) = tokens.register(run_id);
+    let op = Operation::new("install_sidestore".to_string(), &window, cancel)
+        .with_journal(Some(&journal));
+    let result = run_install_sidestore(
+        &op,
+        backend.as_ref(),
+        &device_state,
+        &sessions.select(email),
+        nightly,
+        live_container,
+    )
+    .await;
+    tokens.remove(&run_id);
+    result
+}
+
+pub async fn run_install_sidestore(
+    op: &Operation<'_>,
+    backend: &dyn DeviceBackend,
+    device_state: &DeviceInfoMutex,
+    session: &SessionRef<'_>,
+    nightly: bool,
+    live_container: bool,
+) -> Result<(), IloaderError> {
     // TODO: Cache & check version to avoid re-downloading
-    let (filename, url) = if live_container {
-        if nightly {
-            ("LiveContainerSideStore-Nightly.ipa", "https://github.com/LiveContainer/LiveContainer/releases/download/nightly/LiveContainer+SideStore.ipa")
-        } else {
-            ("LiveContainerSideStore.ipa", "https://github.com/LiveContainer/LiveContainer/releases/latest/download/LiveContainer+SideStore.ipa")
-        }
-    } else if nightly {
-        (
-            "SideStore-Nightly.ipa",
-            "https://github.com/SideStore/SideStore/releases/download/nightly/SideStore.ipa",
-        )
-    } else {
-        (
-            "SideStore.ipa",
-            "https://github.com/SideStore/SideStore/releases/latest/download/SideStore.ipa",
-        )
-    };
+    let (filename, url) = sidestore_source(nightly, live_container);
+    let dest = std::env::temp_dir().join(filename);
+    op.record(|h| h.source = Some(url.to_string()));
 
-    let dest = handle
-        .path()
-        .temp_dir()
-        .map_err(|e| format!("Failed to get temp dir: {:?}", e))?
-        .join(filename);
-    op.fail_if_err("download", download(url, &dest).await)?;
+    let result = install_sidestore_steps(
+        op,
+        backend,
+        device_state,
+        session,
+        url,
+        &dest,
+        live_container,
+    )
+    .await;
+    if matches!(result, Err(IloaderError::Cancelled)) {
+        let _ = tokio::fs::remove_file(&dest).await;
+    }
+    op.finish(result)
+}
+
+async fn install_sidestore_steps(
+    op: &Operation<'_>,
+    backend: &dyn DeviceBackend,
+    device_state: &DeviceInfoMutex,
+    session: &SessionRef<'_>,
+    url: &str,
+    dest: &PathBuf,
+    live_container: bool,
+) -> Result<(), IloaderError> {
+    op.start("download")?;
+    op.check_cancelled("download")?;
+    op.fail_if_err("download", download(url, dest, op.cancel_token()).await)?;
     op.move_on("download", "install")?;
-    let device = {
-        let device_guard = device_state.lock().unwrap();
-        match &*device_guard {
-            Some(d) => d.clone(),
-            None => return op.fail("install", "No device selected".to_string()),
-        }
-    };
+    let device = op.fail_if_err("install", selected_device(device_state))?;
+    op.record(|h| h.set_device(&device));
     op.fail_if_err(
         "install",
-        sideload(
-            device_state,
-            sideloader_state,
-            dest.to_string_lossy().to_string(),
-        )
-        .await,
+        sideload_selected(op, &device, session, dest.to_string_lossy().to_string()).await,
     )?;
     op.move_on("install", "pairing")?;
     let sidestore_info = op.fail_if_err(
         "pairing",
-        get_sidestore_info(device.clone(), live_container).await,
+        get_sidestore_info(backend, &device, live_container).await,
     )?;
+    op.check_cancelled("pairing")?;
     if let Some(info) = sidestore_info {
+        op.record(|h| h.bundle_id = Some(info.bundle_id.clone()));
         op.fail_if_err(
             "pairing",
-            place_pairing(device, info.bundle_id, info.path).await,
+            place_pairing(backend, device, info.bundle_id, info.path).await,
         )?;
     } else {
-        return op.fail(
-            "pairing",
-            "Could not find SideStore's bundle ID".to_string(),
-        );
+        return op.fail("pairing", IloaderError::SideStoreNotFound);
     }
 
     op.complete("pairing")?;
     Ok(())
 }
 
-pub async fn download(url: impl AsRef<str>, dest: &PathBuf) -> Result<(), String> {
-    let response = reqwest::get(url.as_ref())
-        .await
-        .map_err(|e| e.to_string())?;
+pub fn sidestore_source(nightly: bool, live_container: bool) -> (&'static str, &'static str) {
+    if live_container {
+        if nightly {
+            (
+                "LiveContainerSideStore-Nightly.ipa",
+                "https://github.com/LiveContainer/LiveContainer/releases/download/nightly/LiveContainer+SideStore.ipa",
+            )
+        } else {
+            (
+                "LiveContainerSideStore.ipa",
+                "https://github.com/LiveContainer/LiveContainer/releases/latest/download/LiveContainer+SideStore.ipa",
+            )
+        }
+    } else if nightly {
+        (
+            "SideStore-Nightly.ipa",
+            "https://github.com/SideStore/SideStore/releases/download/nightly/SideStore.ipa",
+        )
+    } else {
+        (
+            "SideStore.ipa",
+            "https://github.com/SideStore/SideStore/releases/latest/download/SideStore.ipa",
+        )
+    }
+}
+
+// Removes the partially written file if cancelled
+pub async fn download(
+    url: impl AsRef<str>,
+    dest: &PathBuf,
+    cancel: &CancellationToken,
+) -> Result<(), IloaderError> {
+    let url = url.as_ref();
+    let download_err = |source| IloaderError::Download {
+        url: url.to_string(),
+        source,
+    };
+    let io_err = |source| IloaderError::Io {
+        path: dest.to_string_lossy().to_string(),
+        source,
+    };
+
+    let mut response = reqwest::get(url).await.map_err(download_err)?;
     if !response.status().is_success() {
-        return Err(format!(
-            "Failed to download file: HTTP {}",
-            response.status()
-        ));
+        return Err(IloaderError::DownloadStatus {
+            url: url.to_string(),
+            status: response.status().as_u16(),
+        });
     }
 
-    let bytes = response.bytes().await.map_err(|e| e.to_string())?;
-    tokio::fs::write(dest, &bytes)
-        .await
-        .map_err(|e| e.to_string())?;
+    let mut file = tokio::fs::File::create(dest).await.map_err(io_err)?;
+    loop {
+        let chunk = tokio::select! {
+            chunk = response.chunk() => chunk.map_err(download_err)?,
+            _ = cancel.cancelled() => {
+                drop(file);
+                let _ = tokio::fs::remove_file(dest).await;
+                return Err(IloaderError::Cancelled);
+            }
+        };
+        match chunk {
+            Some(bytes) => file.write_all(&bytes).await.map_err(io_err)?,
+            None => break,
+        }
+    }
+    file.flush().await.map_err(io_err)?;
 
     Ok(())
 }
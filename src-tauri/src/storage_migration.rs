@@ -0,0 +1,391 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use base64::{Engine, engine::general_purpose::STANDARD as BASE64};
+use isideload::util::{keyring_storage::KeyringStorage, storage::SideloadingStorage};
+use rootcause::Report;
+use serde::Serialize;
+use serde_json::Value;
+use tauri::{AppHandle, Manager};
+use tauri_plugin_store::StoreExt;
+use tracing::{debug, info, warn};
+
+use crate::{
+    account::{keyring_available, session_tokens_key, storage},
+    anisette,
+    certificate::signing_key_key,
+    encrypted_storage::{EncryptedFsStorage, StorageBackend},
+    error::IloaderError,
+};
+
+// The keyring can't list its entries, so the keys written to it are recorded here
+const KEY_INDEX_FILE: &str = "storage-keys.json";
+
+static KEY_INDEX: Mutex<()> = Mutex::new(());
+
+type KeyIndex = BTreeMap<String, BTreeSet<String>>;
+
+fn read_index(data_dir: &Path) -> KeyIndex {
+    fs::read_to_string(data_dir.join(KEY_INDEX_FILE))
+        .ok()
+        .and_then(|s| serde_json::from_str(&s).ok())
+        .unwrap_or_default()
+}
+
+fn update_index(data_dir: &Path, f: impl FnOnce(&mut KeyIndex)) {
+    let _guard = KEY_INDEX.lock().unwrap();
+    let mut index = read_index(data_dir);
+    f(&mut index);
+    let result = fs::create_dir_all(data_dir).and_then(|_| {
+        let json = serde_json::to_string_pretty(&index).map_err(std::io::Error::from)?;
+        fs::write(data_dir.join(KEY_INDEX_FILE), json)
+    });
+    if let Err(e) = result {
+        warn!("Unable to update the storage key index: {}", e);
+    }
+}
+
+// KeyringStorage that remembers which keys it holds, so they can be moved to file storage later
+pub struct IndexedKeyringStorage {
+    inner: KeyringStorage,
+    service: String,
+    data_dir: PathBuf,
+}
+
+impl IndexedKeyringStorage {
+    pub fn new(data_dir: &Path, service: String) -> Self {
+        IndexedKeyringStorage {
+            inner: KeyringStorage::new(service.clone()),
+            service,
+            data_dir: data_dir.to_path_buf(),
+        }
+    }
+
+    fn index(&self, key: &str) {
+        update_index(&self.data_dir, |index| {
+            index
+                .entry(self.service.clone())
+                .or_default()
+                .insert(key.to_string());
+        });
+    }
+}
+
+// Data goes through KeyringStorage's own store_data, which keeps it as a binary secret on Windows
+impl SideloadingStorage for IndexedKeyringStorage {
+    fn store(&self, key: &str, value: &str) -> Result<(), Report> {
+        self.inner.store(key, value)?;
+        self.index(key);
+        Ok(())
+    }
+
+    fn retrieve(&self, key: &str) -> Result<Option<String>, Report> {
+        self.inner.retrieve(key)
+    }
+
+    fn store_data(&self, key: &str, value: &[u8]) -> Result<(), Report> {
+        self.inner.store_data(key, value)?;
+        self.index(key);
+        Ok(())
+    }
+
+    fn retrieve_data(&self, key: &str) -> Result<Option<Vec<u8>>, Report> {
+        self.inner.retrieve_data(key)
+    }
+
+    fn delete(&self, key: &str) -> Result<(), Report> {
+        self.inner.delete(key)?;
+        update_index(&self.data_dir, |index| {
+            if let Some(keys) = index.get_mut(&self.service) {
+                keys.remove(key);
+            }
+        });
+        Ok(())
+    }
+}
+
+// A place data is kept, either as a keyring service or as a directory of encrypted files
+struct Location {
+    service: String,
+    dir: PathBuf,
+    known_keys: Vec<String>,
+    // Which plain text files in `dir` are entries, for directories shared with other files
+    legacy_key: fn(&str) -> bool,
+}
+
+impl Location {
+    fn open(&self, data_dir: &Path, backend: StorageBackend) -> Box<dyn SideloadingStorage> {
+        match backend {
+            StorageBackend::Keyring => {
+                Box::new(IndexedKeyringStorage::new(data_dir, self.service.clone()))
+            }
+            StorageBackend::EncryptedFile => {
+                Box::new(EncryptedFsStorage::new(data_dir, self.dir.clone()))
+            }
+        }
+    }
+
+    fn keys(&self, data_dir: &Path, index: &KeyIndex) -> BTreeSet<String> {
+        let mut keys: BTreeSet<String> = self.known_keys.iter().cloned().collect();
+        keys.extend(index.get(&self.service).into_iter().flatten().cloned());
+        let files = EncryptedFsStorage::new(data_dir, self.dir.clone());
+        keys.extend(files.keys());
+        keys.extend(
+            files
+                .legacy_keys()
+                .into_iter()
+                .filter(|key| (self.legacy_key)(key)),
+        );
+        keys
+    }
+}
+
+// The keys iloader and isideload write to the data directory, which also holds the stores, logs
+// and history
+fn shared_key(key: &str) -> bool {
+    key == anisette::STATE_KEY
+        || key.starts_with("session_tokens_")
+        || key
+            .strip_suffix("/key")
+            .is_some_and(|hash| hash.len() == 64 && hash.bytes().all(|b| b.is_ascii_hexdigit()))
+}
+
+// Entries isideload writes with store_data. They're read back the same way, since FsStorage and
+// the Windows keyring don't keep data as base64 text
+pub(crate) fn data_key(key: &str) -> bool {
+    key == anisette::STATE_KEY || key.ends_with("/key")
+}
+
+// The shared location, with each account's session tokens and signing key, plus one per account for
+// its anisette identity. Accounts are the given emails and any that have anisette data on disk
+fn locations(data_dir: &Path, emails: &[String]) -> Vec<Location> {
+    let mut emails: BTreeSet<String> = emails.iter().map(|e| e.to_lowercase()).collect();
+    if let Ok(entries) = fs::read_dir(data_dir.join("anisette")) {
+        emails.extend(
+            entries
+                .flatten()
+                .filter(|entry| entry.path().is_dir())
+                .map(|entry| entry.file_name().to_string_lossy().to_string()),
+        );
+    }
+
+    let mut shared_keys = vec![anisette::STATE_KEY.to_string()];
+    shared_keys.extend(emails.iter().map(|email| session_tokens_key(email)));
+    shared_keys.extend(emails.iter().map(|email| signing_key_key(email)));
+    let mut locations = vec![Location {
+        service: "iloader".to_string(),
+        dir: data_dir.to_path_buf(),
+        known_keys: shared_keys,
+        legacy_key: shared_key,
+    }];
+    locations.extend(emails.iter().map(|email| Location {
+        service: anisette::keyring_service(email),
+        dir: anisette::storage_dir(data_dir, email),
+        known_keys: vec![
+            anisette::STATE_KEY.to_string(),
+            anisette::SERIAL_KEY.to_string(),
+        ],
+        legacy_key: |_| true,
+    }));
+    locations
+}
+
+fn active_backend() -> StorageBackend {
+    if keyring_available() {
+        StorageBackend::Keyring
+    } else {
+        StorageBackend::EncryptedFile
+    }
+}
+
+// Everything saved in the active backend for the given accounts, by keyring service and key. Data
+// entries are base64 encoded
+pub(crate) fn read_all(
+    data_dir: &Path,
+    emails: &[String],
+) -> Result<BTreeMap<String, BTreeMap<String, String>>, Report> {
+    let index = read_index(data_dir);
+    let mut all = BTreeMap::new();
+    for location in locations(data_dir, emails) {
+        let storage = location.open(data_dir, active_backend());
+        let mut entries = BTreeMap::new();
+        for key in location.keys(data_dir, &index) {
+            let value = if data_key(&key) {
+                storage.retrieve_data(&key)?.map(|data| BASE64.encode(data))
+            } else {
+                storage.retrieve(&key)?
+            };
+            if let Some(value) = value {
+                entries.insert(key, value);
+            }
+        }
+        if !entries.is_empty() {
+            all.insert(location.service, entries);
+        }
+    }
+    Ok(all)
+}
+
+// The active storage behind a keyring service name from read_all
+pub(crate) fn service_storage(
+    data_dir: &Path,
+    service: &str,
+) -> Option<Box<dyn SideloadingStorage>> {
+    if service == "iloader" {
+        return Some(storage(data_dir));
+    }
+    let email = service.strip_prefix("iloader.anisette.")?;
+    Some(anisette::account_storage(data_dir, email))
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MigrationFailure {
+    pub key: String,
+    pub error: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StorageMigration {
+    pub from: StorageBackend,
+    pub to: StorageBackend,
+    // Entries are "<keyring service>/<key>"
+    pub moved: Vec<String>,
+    // In both backends; the active one wins and the other copy is left alone
+    pub conflicts: Vec<String>,
+    pub failed: Vec<MigrationFailure>,
+}
+
+enum Outcome {
+    Moved,
+    Missing,
+    // In both backends
+    Conflict,
+}
+
+fn migrate_key(
+    key: &str,
+    source: &dyn SideloadingStorage,
+    dest: &dyn SideloadingStorage,
+) -> Result<Outcome, Report> {
+    if data_key(key) {
+        let Some(value) = source.retrieve_data(key)? else {
+            return Ok(Outcome::Missing);
+        };
+        if dest.retrieve_data(key)?.is_some() {
+            return Ok(Outcome::Conflict);
+        }
+        dest.store_data(key, &value)?;
+    } else {
+        let Some(value) = source.retrieve(key)? else {
+            return Ok(Outcome::Missing);
+        };
+        if dest.retrieve(key)?.is_some() {
+            return Ok(Outcome::Conflict);
+        }
+        dest.store(key, &value)?;
+    }
+    source.delete(key)?;
+    Ok(Outcome::Moved)
+}
+
+// Moves data from the inactive backend into the active one, e.g. files written while the keyring
+// was down into the keyring once it's back. Blocks on the keyring, so run it off the async runtime
+pub fn migrate(data_dir: &Path, emails: &[String]) -> StorageMigration {
+    let to = active_backend();
+    let from = match to {
+        StorageBackend::Keyring => StorageBackend::EncryptedFile,
+        StorageBackend::EncryptedFile => StorageBackend::Keyring,
+    };
+    let mut migration = StorageMigration {
+        from,
+        to,
+        moved: vec![],
+        conflicts: vec![],
+        failed: vec![],
+    };
+    if from == StorageBackend::Keyring {
+        // Data in the keyring can't be read now, it moves back once the keyring is available
+        debug!("Keyring is unavailable, nothing to migrate");
+        return migration;
+    }
+
+    let index = read_index(data_dir);
+    for location in locations(data_dir, emails) {
+        let source = location.open(data_dir, from);
+        let dest = location.open(data_dir, to);
+        for key in location.keys(data_dir, &index) {
+            let name = format!("{}/{}", location.service, key);
+            match migrate_key(&key, source.as_ref(), dest.as_ref()) {
+                Ok(Outcome::Moved) => migration.moved.push(name),
+                Ok(Outcome::Missing) => {}
+                Ok(Outcome::Conflict) => migration.conflicts.push(name),
+                Err(e) => migration.failed.push(MigrationFailure {
+                    key: name,
+                    error: e.to_string(),
+                }),
+            }
+        }
+    }
+
+    if !migration.moved.is_empty() {
+        info!(
+            "Moved {} storage entries from {} to {} storage: {}",
+            migration.moved.len(),
+            from,
+            to,
+            migration.moved.join(", ")
+        );
+    }
+    if !migration.conflicts.is_empty() {
+        warn!(
+            "Kept the {} copy of entries also in {} storage: {}",
+            to,
+            from,
+            migration.conflicts.join(", ")
+        );
+    }
+    for failure in &migration.failed {
+        warn!("Unable to migrate {}: {}", failure.key, failure.error);
+    }
+    migration
+}
+
+// Every account iloader knows about: saved ones and ones with a remembered team
+pub fn known_emails(handle: &AppHandle) -> Vec<String> {
+    let Ok(store) = handle.store("data.json") else {
+        return vec![];
+    };
+    let mut emails: Vec<String> = store
+        .get("ids")
+        .and_then(|ids| ids.as_array().cloned())
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|id| id.as_str().map(str::to_string))
+        .collect();
+    if let Some(Value::Object(teams)) = store.get("teams") {
+        emails.extend(teams.keys().cloned());
+    }
+    emails
+}
+
+#[tauri::command]
+pub async fn migrate_storage(handle: AppHandle) -> Result<StorageMigration, IloaderError> {
+    let data_dir = handle
+        .path()
+        .app_data_dir()
+        .map_err(|e| IloaderError::StorageMigration {
+            report: e.to_string(),
+        })?;
+    let emails = known_emails(&handle);
+    tauri::async_runtime::spawn_blocking(move || migrate(&data_dir, &emails))
+        .await
+        .map_err(|e| IloaderError::StorageMigration {
+            report: e.to_string(),
+        })
+}
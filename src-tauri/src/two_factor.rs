@@ -0,0 +1,133 @@
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+};
+
+use async_trait::async_trait;
+use isideload::auth::apple_account::AppleAccount;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::{account::LoginPrompter, error::IloaderError, prompt::PromptError};
+
+// Sent with the 2fa-required event. isideload decides whether Apple pushes the code to the trusted
+// devices or texts it, and doesn't say which, so the prompt can only tell a new code apart
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TwoFactorRequest {
+    // The user asked for another code, so this is a new one
+    pub resent: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum TwoFactorResponse {
+    Code(String),
+    // Sign in again, which makes Apple send a new code
+    Resend,
+}
+
+impl TwoFactorResponse {
+    // The frontend sends either a response object or, like before, just the code
+    pub fn parse(payload: &str) -> Option<TwoFactorResponse> {
+        if let Ok(response) = serde_json::from_str::<TwoFactorResponse>(payload) {
+            return Some(response);
+        }
+        let code = serde_json::from_str::<String>(payload)
+            .unwrap_or_else(|_| payload.trim_matches('"').to_string());
+        if code.is_empty() {
+            None
+        } else {
+            Some(TwoFactorResponse::Code(code))
+        }
+    }
+}
+
+pub(crate) fn auth_err<E: fmt::Display>(email: &str) -> impl Fn(E) -> IloaderError + '_ {
+    move |e| IloaderError::Auth {
+        email: email.to_string(),
+        report: e.to_string(),
+    }
+}
+
+// One sign in through isideload, which asks for a 2FA code through the callback when Apple wants
+// one. The login loop runs against a script in tests through this
+#[async_trait]
+pub trait PasswordLogin: Send {
+    async fn login(
+        &mut self,
+        two_factor: &(dyn Fn() -> Option<String> + Sync),
+    ) -> Result<(), IloaderError>;
+}
+
+pub(crate) struct AccountLogin<'a> {
+    pub account: &'a mut AppleAccount,
+    pub email: &'a str,
+    pub password: &'a str,
+}
+
+#[async_trait]
+impl PasswordLogin for AccountLogin<'_> {
+    async fn login(
+        &mut self,
+        two_factor: &(dyn Fn() -> Option<String> + Sync),
+    ) -> Result<(), IloaderError> {
+        self.account
+            .login(self.password, two_factor)
+            .await
+            .map_err(auth_err(self.email))
+    }
+}
+
+// Every resend is a new sign in, so this many codes are the most one login asks Apple for
+pub const MAX_TWO_FACTOR_CODES: usize = 5;
+
+// Why the prompt gave isideload no code, which makes its login fail
+enum Interruption {
+    Resend,
+    Prompt(PromptError),
+}
+
+// Signs in, asking for a 2FA code whenever Apple wants one and signing in again when the user asks
+// for a new code. The prompt blocks inside isideload's callback, which can't be async
+pub async fn login_with_two_factor(
+    login: &mut dyn PasswordLogin,
+    email: &str,
+    prompter: Arc<dyn LoginPrompter>,
+) -> Result<(), IloaderError> {
+    let interruption = Mutex::new(None);
+    for attempt in 0..MAX_TWO_FACTOR_CODES {
+        let ask = || {
+            let request = TwoFactorRequest {
+                resent: attempt > 0,
+            };
+            let (code, interrupted) = match prompter.two_factor(&request) {
+                Ok(TwoFactorResponse::Code(code)) => (Some(code), None),
+                Ok(TwoFactorResponse::Resend) => (None, Some(Interruption::Resend)),
+                Err(e) => (None, Some(Interruption::Prompt(e))),
+            };
+            *interruption.lock().unwrap() = interrupted;
+            code
+        };
+
+        let result = login.login(&ask).await;
+        let interrupted = interruption.lock().unwrap().take();
+        match interrupted {
+            Some(Interruption::Resend) => info!("Signing in again for a new 2FA code"),
+            Some(Interruption::Prompt(PromptError::TimedOut)) => {
+                return Err(IloaderError::TwoFactorTimeout);
+            }
+            Some(Interruption::Prompt(PromptError::Cancelled)) => {
+                return Err(IloaderError::TwoFactorCancelled);
+            }
+            None => return result,
+        }
+    }
+    Err(IloaderError::Auth {
+        email: email.to_string(),
+        report: format!(
+            "Stopped after sending {} two-factor codes",
+            MAX_TWO_FACTOR_CODES
+        ),
+    })
+}
@@ -0,0 +1,100 @@
+use iloader_lib::anisette::{emulator::AnisetteEmulator, fallback_order, probe};
+use serde_json::{Value, json};
+
+// Nothing listens on a port that was just released, so connecting fails right away
+async fn closed_server() -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    drop(listener);
+    url
+}
+
+async fn send(request: reqwest::RequestBuilder) -> Value {
+    let body = request.send().await.unwrap().text().await.unwrap();
+    serde_json::from_str(&body).unwrap()
+}
+
+#[tokio::test]
+async fn probe_reports_reachable_servers() {
+    let emulator = AnisetteEmulator::start().await.unwrap();
+    let client = reqwest::Client::new();
+
+    let status = probe(&client, &emulator.url()).await;
+    assert!(status.reachable);
+    assert!(status.latency_ms.is_some());
+    assert_eq!(emulator.requests(), vec!["GET /v3/client_info"]);
+
+    emulator.set_failing(true);
+    let status = probe(&client, &emulator.url()).await;
+    assert!(!status.reachable);
+    assert!(status.error.is_some());
+
+    let status = probe(&client, &closed_server().await).await;
+    assert!(!status.reachable);
+}
+
+#[tokio::test]
+async fn only_the_preferred_server_is_checked_when_up() {
+    let preferred = AnisetteEmulator::start().await.unwrap();
+    let other = AnisetteEmulator::start().await.unwrap();
+    let servers = vec![preferred.url(), other.url()];
+
+    assert_eq!(fallback_order(servers.clone()).await, servers);
+    assert_eq!(preferred.requests(), vec!["GET /v3/client_info"]);
+    assert!(other.requests().is_empty());
+}
+
+#[tokio::test]
+async fn first_reachable_server_replaces_a_down_preferred_one() {
+    let preferred = closed_server().await;
+    let broken = AnisetteEmulator::start().await.unwrap();
+    broken.set_failing(true);
+    let up = AnisetteEmulator::start().await.unwrap();
+    let unchecked = closed_server().await;
+
+    let order = fallback_order(vec![
+        preferred.clone(),
+        broken.url(),
+        up.url(),
+        unchecked.clone(),
+    ])
+    .await;
+    assert_eq!(order, vec![up.url(), broken.url(), unchecked, preferred]);
+}
+
+#[tokio::test]
+async fn all_servers_down_keeps_the_order() {
+    let preferred = closed_server().await;
+    let other = closed_server().await;
+
+    let order = fallback_order(vec![preferred.clone(), other.clone()]).await;
+    assert_eq!(order, vec![other, preferred]);
+}
+
+#[tokio::test]
+async fn emulator_answers_v3_calls() {
+    let emulator = AnisetteEmulator::start().await.unwrap();
+    let client = reqwest::Client::new();
+
+    let info = send(client.get(format!("{}/v3/client_info", emulator.url()))).await;
+    assert!(info["client_info"].as_str().is_some());
+    assert!(info["user_agent"].as_str().is_some());
+
+    let headers = send(
+        client
+            .post(format!("{}/v3/get_headers", emulator.url()))
+            .body(json!({ "identifier": "abc", "adi_pb": "def" }).to_string()),
+    )
+    .await;
+    assert_eq!(headers["result"], "Headers");
+    assert!(headers["X-Apple-I-MD"].as_str().is_some());
+    assert!(headers["X-Apple-I-MD-M"].as_str().is_some());
+
+    let unprovisioned = send(
+        client
+            .post(format!("{}/v3/get_headers", emulator.url()))
+            .body("{}"),
+    )
+    .await;
+    assert_eq!(unprovisioned["result"], "GetHeadersError");
+}
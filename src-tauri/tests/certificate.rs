@@ -0,0 +1,259 @@
+mod common;
+
+use std::{fs, path::Path};
+
+use common::{data_dir, signing_key_key};
+use iloader_lib::{
+    account::{CertificateInfo, certificate_infos, disable_keyring},
+    certificate::{RevokeFilter, public_key, signing_certificate, stored_serial},
+    encrypted_storage::EncryptedFsStorage,
+    error::IloaderError,
+};
+use isideload::{dev::certificates::DevelopmentCertificate, util::storage::SideloadingStorage};
+use rsa::{RsaPrivateKey, pkcs1::EncodeRsaPublicKey, pkcs8::EncodePrivateKey};
+
+const LOCAL_SERIAL: &str = "03";
+
+fn cert(serial: &str, name: &str, machine: &str, created: Option<&str>) -> CertificateInfo {
+    CertificateInfo {
+        name: Some(name.to_string()),
+        in_local_storage: serial == LOCAL_SERIAL,
+        ..common::cert(serial, machine, created)
+    }
+}
+
+fn certs() -> Vec<CertificateInfo> {
+    vec![
+        cert(
+            "01",
+            "Apple Development: Jane",
+            "Xcode Mac",
+            Some("2024-01-01T00:00:00+00:00"),
+        ),
+        cert(
+            "02",
+            "iOS Development: Jane",
+            "iloader-laptop",
+            Some("2024-06-01T12:00:00+00:00"),
+        ),
+        cert(
+            "03",
+            "Apple Development: Jane",
+            "iloader-desktop",
+            Some("2025-01-01T00:00:00+00:00"),
+        ),
+        cert("04", "Apple Development: Jane", "iloader-laptop", None),
+    ]
+}
+
+fn serials(certs: &[CertificateInfo]) -> Vec<&str> {
+    certs
+        .iter()
+        .filter_map(|cert| cert.serial_number.as_deref())
+        .collect()
+}
+
+#[test]
+fn machine_name_ignores_case_and_whitespace() {
+    let filter = RevokeFilter::MachineName {
+        machine_name: " ILOADER-Laptop ".to_string(),
+    };
+    let selected = filter.select(certs(), Some(LOCAL_SERIAL)).unwrap();
+    assert_eq!(serials(&selected), vec!["02", "04"]);
+}
+
+#[test]
+fn name_pattern_matches_globs() {
+    let filter = RevokeFilter::NamePattern {
+        pattern: "apple development: *".to_string(),
+    };
+    let selected = filter.select(certs(), None).unwrap();
+    assert_eq!(serials(&selected), vec!["01", "03", "04"]);
+
+    let filter = RevokeFilter::NamePattern {
+        pattern: "i?S*".to_string(),
+    };
+    let selected = filter.select(certs(), None).unwrap();
+    assert_eq!(serials(&selected), vec!["02"]);
+}
+
+#[test]
+fn created_before_skips_unknown_dates() {
+    let filter = RevokeFilter::CreatedBefore {
+        date: "2024-07-01".to_string(),
+    };
+    let selected = filter.select(certs(), None).unwrap();
+    // 04 has no creation date, so it can't be shown to be older
+    assert_eq!(serials(&selected), vec!["01", "02"]);
+
+    let filter = RevokeFilter::CreatedBefore {
+        date: "2024-06-01T12:00:00Z".to_string(),
+    };
+    let selected = filter.select(certs(), None).unwrap();
+    assert_eq!(serials(&selected), vec!["01"]);
+}
+
+#[test]
+fn created_before_rejects_bad_dates() {
+    let filter = RevokeFilter::CreatedBefore {
+        date: "last year".to_string(),
+    };
+    assert!(filter.select(certs(), None).is_err());
+}
+
+#[test]
+fn all_except_local_keeps_the_local_certificate() {
+    let selected = RevokeFilter::AllExceptLocal
+        .select(certs(), Some(LOCAL_SERIAL))
+        .unwrap();
+    assert_eq!(serials(&selected), vec!["01", "02", "04"]);
+}
+
+#[test]
+fn all_except_local_needs_a_local_identity() {
+    let err = RevokeFilter::AllExceptLocal
+        .select(certs(), None)
+        .unwrap_err();
+    assert!(matches!(err, IloaderError::NoSigningIdentity));
+}
+
+#[test]
+fn certificates_without_serial_are_skipped() {
+    let mut certs = certs();
+    certs[0].serial_number = None;
+    let selected = RevokeFilter::MachineName {
+        machine_name: "Xcode Mac".to_string(),
+    }
+    .select(certs, None)
+    .unwrap();
+    assert!(selected.is_empty());
+}
+
+#[test]
+fn filters_read_frontend_json() {
+    let filter: RevokeFilter =
+        serde_json::from_str(r#"{"type":"machineName","machineName":"iloader-laptop"}"#).unwrap();
+    let selected = filter.select(certs(), None).unwrap();
+    assert_eq!(serials(&selected), vec!["02", "04"]);
+
+    let filter: RevokeFilter = serde_json::from_str(r#"{"type":"allExceptLocal"}"#).unwrap();
+    assert!(matches!(filter, RevokeFilter::AllExceptLocal));
+}
+
+// Signs for the RSA key in the certificate, which rcgen can't generate itself
+struct SubjectKey(Vec<u8>);
+
+impl rcgen::PublicKeyData for SubjectKey {
+    fn der_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    fn algorithm(&self) -> &'static rcgen::SignatureAlgorithm {
+        &rcgen::PKCS_RSA_SHA256
+    }
+}
+
+// A PKCS#8 RSA key like the one isideload creates, with a certificate issued for it
+fn signing_identity() -> (Vec<u8>, Vec<u8>) {
+    let key = RsaPrivateKey::new(&mut rsa::rand_core::OsRng, 1024).unwrap();
+    let key_der = key.to_pkcs8_der().unwrap().as_bytes().to_vec();
+    let public_key = key.to_public_key().to_pkcs1_der().unwrap();
+
+    let issuer_key = rcgen::KeyPair::generate().unwrap();
+    let issuer_params = rcgen::CertificateParams::new(vec!["Apple".to_string()]).unwrap();
+    let issuer = rcgen::Issuer::new(issuer_params, issuer_key);
+    let cert = rcgen::CertificateParams::new(vec!["Apple Development".to_string()])
+        .unwrap()
+        .signed_by(&SubjectKey(public_key.as_bytes().to_vec()), &issuer)
+        .unwrap();
+    (key_der, cert.der().to_vec())
+}
+
+fn development_cert(serial: &str, cert_der: Option<Vec<u8>>) -> DevelopmentCertificate {
+    DevelopmentCertificate {
+        name: Some("Apple Development: Jane".to_string()),
+        certificate_id: Some(format!("ID{}", serial)),
+        serial_number: Some(serial.to_string()),
+        machine_id: Some(format!("MACHINE{}", serial)),
+        machine_name: Some("iloader-laptop".to_string()),
+        cert_content: cert_der.map(plist::Data::new),
+        certificate_platform: None,
+        certificate_type: None,
+        status: None,
+        status_code: None,
+        expiration_date: None,
+    }
+}
+
+#[test]
+fn signing_certificate_is_found_by_public_key() {
+    let (key, cert) = signing_identity();
+    let (_, other_cert) = signing_identity();
+    let certs = vec![
+        development_cert("01", Some(other_cert)),
+        development_cert("02", None),
+        development_cert("03", Some(cert)),
+    ];
+
+    let found = signing_certificate(&certs, &public_key(&key).unwrap()).unwrap();
+    assert_eq!(found.serial_number.as_deref(), Some("03"));
+    assert!(signing_certificate(&certs[..2], &public_key(&key).unwrap()).is_none());
+}
+
+const EMAIL: &str = "user@example.com";
+
+// Saves the key where isideload keeps it, which with the keyring disabled is an encrypted file in
+// the data directory named after the hex SHA-256 of the email
+fn store_signing_key(data_dir: &Path, key_der: &[u8]) {
+    disable_keyring();
+    EncryptedFsStorage::new(data_dir, data_dir.to_path_buf())
+        .store_data(&signing_key_key(EMAIL), key_der)
+        .unwrap();
+}
+
+#[test]
+fn local_certificate_is_the_one_issued_for_the_stored_key() {
+    let dir = data_dir("local");
+    let (key, cert) = signing_identity();
+    let (_, other_cert) = signing_identity();
+    let certs = vec![
+        development_cert("01", Some(other_cert)),
+        development_cert("03", Some(cert)),
+    ];
+    assert_eq!(stored_serial(&dir, EMAIL, &certs), None);
+
+    store_signing_key(&dir, &key);
+    let local_serial = stored_serial(&dir, EMAIL, &certs);
+    assert_eq!(local_serial.as_deref(), Some("03"));
+    let infos = certificate_infos(&certs, local_serial.as_deref(), Some("iloader-laptop"), 30);
+    let local: Vec<bool> = infos.iter().map(|info| info.in_local_storage).collect();
+    assert_eq!(local, vec![false, true]);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn all_except_local_keeps_the_certificate_issued_for_the_stored_key() {
+    let dir = data_dir("all-except-local");
+    let (key, cert) = signing_identity();
+    let (_, other_cert) = signing_identity();
+    let certs = vec![
+        development_cert("01", Some(other_cert)),
+        development_cert("02", None),
+        development_cert("03", Some(cert)),
+    ];
+    let infos = || certificate_infos(&certs, None, Some("iloader-laptop"), 30);
+
+    let local_serial = stored_serial(&dir, EMAIL, &certs);
+    let err = RevokeFilter::AllExceptLocal
+        .select(infos(), local_serial.as_deref())
+        .unwrap_err();
+    assert!(matches!(err, IloaderError::NoSigningIdentity));
+
+    store_signing_key(&dir, &key);
+    let local_serial = stored_serial(&dir, EMAIL, &certs);
+    let selected = RevokeFilter::AllExceptLocal
+        .select(infos(), local_serial.as_deref())
+        .unwrap();
+    assert_eq!(serials(&selected), vec!["01", "02"]);
+    let _ = fs::remove_dir_all(&dir);
+}
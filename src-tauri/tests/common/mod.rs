@@ -0,0 +1,45 @@
+// Fixtures shared by the integration tests. Each test binary uses only some of them
+#![allow(dead_code)]
+
+use std::{fs, path::PathBuf};
+
+use iloader_lib::account::{CertificateInfo, CertificateStatus};
+use sha2::{Digest, Sha256};
+
+// A fresh data directory per test, named after the test binary so parallel binaries never share
+// one. Each directory derives its own storage key from its own salt
+pub fn data_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "iloader-{}-{}-{}",
+        env!("CARGO_CRATE_NAME"),
+        name,
+        std::process::id()
+    ));
+    let _ = fs::remove_dir_all(&dir);
+    dir
+}
+
+// A team certificate that isn't the one this install signs with
+pub fn cert(serial: &str, machine: &str, created: Option<&str>) -> CertificateInfo {
+    CertificateInfo {
+        name: Some("Apple Development".to_string()),
+        certificate_id: Some(format!("ID{}", serial)),
+        serial_number: Some(serial.to_string()),
+        machine_name: Some(machine.to_string()),
+        machine_id: None,
+        created: created.map(str::to_string),
+        expires: None,
+        status: CertificateStatus::Unknown,
+        in_local_storage: false,
+        this_machine: false,
+    }
+}
+
+// Where isideload keeps an account's private key: the hex SHA-256 of the email, then "/key"
+pub fn signing_key_key(email: &str) -> String {
+    let hash: String = Sha256::digest(email.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    format!("{}/key", hash)
+}
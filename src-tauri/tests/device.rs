@@ -0,0 +1,90 @@
+use iloader_lib::{
+    device::connected_devices,
+    device_backend::fake::{FakeDevice, FakeDeviceBackend},
+    error::IloaderError,
+    pairing::{get_sidestore_info, pairing_apps, place_pairing},
+};
+
+const UDID: &str = "00008030-001A2B3C4D5E6F70";
+
+fn backend() -> FakeDeviceBackend {
+    FakeDeviceBackend::new(vec![
+        FakeDevice::new(1, UDID, "Test iPhone")
+            .with_app("com.SideStore.SideStore.ABCDE12345", "SideStore")
+            .with_app("com.stik.stikdebug.ABCDE12345", "StikDebug")
+            .with_app("com.example.notes", "Notes"),
+    ])
+}
+
+#[tokio::test]
+async fn lists_devices_with_names() {
+    let backend = backend();
+    let devices = connected_devices(&backend).await.unwrap();
+
+    assert_eq!(devices.len(), 1);
+    assert_eq!(devices[0].name, "Test iPhone");
+    assert_eq!(devices[0].uuid, UDID);
+    assert_eq!(devices[0].connection_type, "USB");
+}
+
+#[tokio::test]
+async fn finds_installed_pairing_apps() {
+    let backend = backend();
+    let device = connected_devices(&backend).await.unwrap().remove(0);
+
+    let apps = pairing_apps(&backend, &device).await.unwrap();
+    let names: Vec<&str> = apps.iter().map(|a| a.name.as_str()).collect();
+
+    assert_eq!(names, vec!["SideStore", "StikDebug (Sideloaded)"]);
+    assert_eq!(apps[0].path, "ALTPairingFile.mobiledevicepairing");
+}
+
+#[tokio::test]
+async fn finds_sidestore_and_places_pairing_file() {
+    let backend = backend();
+    let device = connected_devices(&backend).await.unwrap().remove(0);
+
+    let info = get_sidestore_info(&backend, &device, false)
+        .await
+        .unwrap()
+        .expect("SideStore should be installed");
+    assert_eq!(info.bundle_id, "com.SideStore.SideStore.ABCDE12345");
+
+    place_pairing(&backend, device, info.bundle_id.clone(), info.path.clone())
+        .await
+        .unwrap();
+
+    let fake = backend.device(UDID).unwrap();
+    assert_eq!(
+        fake.documents.get(&(info.bundle_id, info.path)),
+        fake.pair_record.as_ref()
+    );
+    assert_eq!(
+        fake.values.get(&(
+            Some("com.apple.mobile.wireless_lockdown".to_string()),
+            "EnableWifiDebugging".to_string()
+        )),
+        Some(&true.into())
+    );
+}
+
+#[tokio::test]
+async fn place_pairing_fails_without_pair_record() {
+    let backend = FakeDeviceBackend::new(vec![
+        FakeDevice::new(1, UDID, "Test iPhone")
+            .with_app("com.SideStore.SideStore", "SideStore")
+            .without_pair_record(),
+    ]);
+    let device = connected_devices(&backend).await.unwrap().remove(0);
+
+    let err = place_pairing(
+        &backend,
+        device,
+        "com.SideStore.SideStore".to_string(),
+        "ALTPairingFile.mobiledevicepairing".to_string(),
+    )
+    .await
+    .unwrap_err();
+
+    assert!(matches!(err, IloaderError::PairRecord { .. }));
+}
@@ -0,0 +1,169 @@
+mod common;
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use common::data_dir;
+use iloader_lib::encrypted_storage::EncryptedFsStorage;
+use isideload::util::{fs_storage::FsStorage, storage::SideloadingStorage};
+
+fn encrypted_files(dir: &Path) -> Vec<PathBuf> {
+    fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .flatten()
+                .map(|entry| entry.path())
+                .filter(|path| path.extension().is_some_and(|ext| ext == "enc"))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+#[test]
+fn values_round_trip() {
+    let data_dir = data_dir("round-trip");
+    let storage = EncryptedFsStorage::new(&data_dir, data_dir.join("entries"));
+
+    storage
+        .store("TEAM123_certificate", "secret value")
+        .unwrap();
+    assert_eq!(
+        storage.retrieve("TEAM123_certificate").unwrap().as_deref(),
+        Some("secret value")
+    );
+    assert_eq!(storage.keys(), vec!["TEAM123_certificate".to_string()]);
+    assert_eq!(storage.retrieve("missing").unwrap(), None);
+
+    // The value must not be readable from the file itself
+    let files = encrypted_files(&data_dir.join("entries"));
+    assert_eq!(files.len(), 1);
+    let contents = fs::read(&files[0]).unwrap();
+    assert!(!String::from_utf8_lossy(&contents).contains("secret value"));
+
+    storage.delete("TEAM123_certificate").unwrap();
+    assert_eq!(storage.retrieve("TEAM123_certificate").unwrap(), None);
+    let _ = fs::remove_dir_all(&data_dir);
+}
+
+#[test]
+fn tampered_entries_are_rejected() {
+    let data_dir = data_dir("tampered");
+    let storage = EncryptedFsStorage::new(&data_dir, data_dir.join("entries"));
+    storage.store("key", "value").unwrap();
+
+    let file = encrypted_files(&data_dir.join("entries")).remove(0);
+    let mut contents = fs::read(&file).unwrap();
+    let last = contents.len() - 1;
+    contents[last] ^= 0x01;
+    fs::write(&file, contents).unwrap();
+
+    assert!(storage.retrieve("key").is_err());
+    let _ = fs::remove_dir_all(&data_dir);
+}
+
+#[test]
+fn entries_from_another_key_are_rejected() {
+    let first_dir = data_dir("first-key");
+    let second_dir = data_dir("second-key");
+    let first = EncryptedFsStorage::new(&first_dir, first_dir.join("entries"));
+    let second = EncryptedFsStorage::new(&second_dir, second_dir.join("entries"));
+    // Creates the second data directory's salt, and with it a different key
+    second.store("other", "value").unwrap();
+    first.store("key", "value").unwrap();
+
+    let file = encrypted_files(&first_dir.join("entries")).remove(0);
+    fs::copy(
+        &file,
+        second_dir.join("entries").join(file.file_name().unwrap()),
+    )
+    .unwrap();
+
+    assert!(second.retrieve("key").is_err());
+    let _ = fs::remove_dir_all(&first_dir);
+    let _ = fs::remove_dir_all(&second_dir);
+}
+
+#[test]
+fn plain_text_entries_are_encrypted_on_first_read() {
+    let data_dir = data_dir("legacy");
+    let entries = data_dir.join("entries");
+    let legacy = FsStorage::new(entries.clone());
+    legacy.store("anisette_state", "plain text state").unwrap();
+
+    let storage = EncryptedFsStorage::new(&data_dir, entries.clone());
+    assert!(storage.keys().is_empty());
+    assert_eq!(
+        storage.retrieve("anisette_state").unwrap().as_deref(),
+        Some("plain text state")
+    );
+
+    assert_eq!(legacy.retrieve("anisette_state").unwrap(), None);
+    assert_eq!(storage.keys(), vec!["anisette_state".to_string()]);
+    assert_eq!(encrypted_files(&entries).len(), 1);
+    assert_eq!(
+        storage.retrieve("anisette_state").unwrap().as_deref(),
+        Some("plain text state")
+    );
+    let _ = fs::remove_dir_all(&data_dir);
+}
+
+#[test]
+fn data_written_by_fs_storage_is_encrypted_on_first_read() {
+    let data_dir = data_dir("legacy-data");
+    let entries = data_dir.join("entries");
+    let legacy = FsStorage::new(entries.clone());
+    // Like the PKCS#8 signing key isideload keeps under the hash of the email, not valid UTF-8
+    let key_der = [0x30, 0x82, 0x04, 0xbe, 0x02, 0x01, 0x00, 0xff, 0xfe];
+    let state = b"<?xml version=\"1.0\"?><plist><dict/></plist>";
+    legacy.store_data("0123abcd/key", &key_der).unwrap();
+    legacy.store_data("anisette_state", state).unwrap();
+
+    let storage = EncryptedFsStorage::new(&data_dir, entries.clone());
+    assert_eq!(
+        storage.retrieve_data("0123abcd/key").unwrap().as_deref(),
+        Some(&key_der[..])
+    );
+    assert_eq!(
+        storage.retrieve_data("anisette_state").unwrap().as_deref(),
+        Some(&state[..])
+    );
+
+    // The plain text files are gone rather than emptied, along with the key's directory
+    assert!(!entries.join("0123abcd").exists());
+    assert!(!entries.join("anisette_state").exists());
+    assert_eq!(legacy.retrieve_data("anisette_state").unwrap(), None);
+    assert_eq!(encrypted_files(&entries).len(), 2);
+    assert_eq!(
+        storage.retrieve_data("0123abcd/key").unwrap().as_deref(),
+        Some(&key_der[..])
+    );
+    let _ = fs::remove_dir_all(&data_dir);
+}
+
+#[test]
+fn plain_text_entries_are_listed() {
+    let data_dir = data_dir("legacy-keys");
+    let entries = data_dir.join("entries");
+    let legacy = FsStorage::new(entries.clone());
+    legacy
+        .store("TEAM123_certificate", "plain text certificate")
+        .unwrap();
+    // isideload's signing key, which FsStorage puts in a directory named after the hash
+    legacy.store_data("0123abcd/key", &[0x30, 0xff]).unwrap();
+
+    let storage = EncryptedFsStorage::new(&data_dir, entries);
+    storage.store("anisette_state", "state").unwrap();
+    let mut legacy_keys = storage.legacy_keys();
+    legacy_keys.sort();
+    assert_eq!(
+        legacy_keys,
+        vec![
+            "0123abcd/key".to_string(),
+            "TEAM123_certificate".to_string()
+        ]
+    );
+    assert_eq!(storage.keys(), vec!["anisette_state".to_string()]);
+    let _ = fs::remove_dir_all(&data_dir);
+}
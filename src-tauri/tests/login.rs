@@ -0,0 +1,355 @@
+mod common;
+
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+use async_trait::async_trait;
+use common::cert;
+use iloader_lib::{
+    account::{CertificateInfo, LoginPrompter, TeamInfo, choose_team},
+    error::IloaderError,
+    max_certs::{MaxCertsPolicy, Refusal},
+    prompt::PromptError,
+    two_factor::{
+        MAX_TWO_FACTOR_CODES, PasswordLogin, TwoFactorRequest, TwoFactorResponse,
+        login_with_two_factor,
+    },
+};
+
+// Answers each prompt from a script and records what it was asked. A prompt without a scripted
+// answer is cancelled
+#[derive(Default)]
+struct ScriptedPrompter {
+    two_factor: Mutex<VecDeque<Result<TwoFactorResponse, PromptError>>>,
+    certificates: Mutex<Option<Result<Vec<String>, PromptError>>>,
+    team: Mutex<Option<Result<String, PromptError>>>,
+    asked: Mutex<Vec<String>>,
+}
+
+impl ScriptedPrompter {
+    fn asked(&self) -> Vec<String> {
+        self.asked.lock().unwrap().clone()
+    }
+}
+
+impl LoginPrompter for ScriptedPrompter {
+    fn two_factor(&self, request: &TwoFactorRequest) -> Result<TwoFactorResponse, PromptError> {
+        self.asked.lock().unwrap().push(if request.resent {
+            "2fa (resent)".to_string()
+        } else {
+            "2fa".to_string()
+        });
+        self.two_factor
+            .lock()
+            .unwrap()
+            .pop_front()
+            .unwrap_or(Err(PromptError::Cancelled))
+    }
+
+    fn certificates_to_revoke(
+        &self,
+        certs: &[CertificateInfo],
+    ) -> Result<Vec<String>, PromptError> {
+        self.asked
+            .lock()
+            .unwrap()
+            .push(format!("{} certificates", certs.len()));
+        self.certificates
+            .lock()
+            .unwrap()
+            .take()
+            .unwrap_or(Err(PromptError::Cancelled))
+    }
+
+    fn select_team(&self, teams: &[TeamInfo]) -> Result<String, PromptError> {
+        self.asked
+            .lock()
+            .unwrap()
+            .push(format!("{} teams", teams.len()));
+        self.team
+            .lock()
+            .unwrap()
+            .take()
+            .unwrap_or(Err(PromptError::Cancelled))
+    }
+}
+
+// Signs in like isideload without talking to Apple: when 2FA is needed a code is "sent" and the
+// callback asked for it. Only VALID_CODE verifies
+struct ScriptedLogin {
+    two_factor: bool,
+    events: Vec<String>,
+}
+
+const VALID_CODE: &str = "123456";
+
+impl ScriptedLogin {
+    fn new(two_factor: bool) -> Self {
+        ScriptedLogin {
+            two_factor,
+            events: vec![],
+        }
+    }
+}
+
+#[async_trait]
+impl PasswordLogin for ScriptedLogin {
+    async fn login(
+        &mut self,
+        two_factor: &(dyn Fn() -> Option<String> + Sync),
+    ) -> Result<(), IloaderError> {
+        self.events.push("sign in".to_string());
+        if !self.two_factor {
+            return Ok(());
+        }
+        self.events.push("send code".to_string());
+        let Some(code) = two_factor() else {
+            return Err(auth_error("No 2FA code provided, aborting"));
+        };
+        self.events.push(format!("verify {}", code));
+        if code == VALID_CODE {
+            Ok(())
+        } else {
+            Err(auth_error("Incorrect verification code"))
+        }
+    }
+}
+
+fn auth_error(report: &str) -> IloaderError {
+    IloaderError::Auth {
+        email: EMAIL.to_string(),
+        report: report.to_string(),
+    }
+}
+
+const EMAIL: &str = "user@example.com";
+
+fn team(team_id: &str) -> TeamInfo {
+    TeamInfo {
+        team_id: team_id.to_string(),
+        name: Some(format!("Team {}", team_id)),
+        team_type: Some("Individual".to_string()),
+    }
+}
+
+const CREATED: Option<&str> = Some("2024-01-01T00:00:00+00:00");
+
+#[tokio::test]
+async fn single_or_remembered_team_is_not_asked_for() {
+    let prompter = Arc::new(ScriptedPrompter::default());
+
+    let index = choose_team(&[team("ONE")], None, prompter.clone())
+        .await
+        .unwrap();
+    assert_eq!(index, 0);
+    let teams = [team("ONE"), team("TWO")];
+    let index = choose_team(&teams, Some("TWO"), prompter.clone())
+        .await
+        .unwrap();
+    assert_eq!(index, 1);
+    assert!(prompter.asked().is_empty());
+}
+
+#[tokio::test]
+async fn team_is_asked_for_when_there_are_several() {
+    let prompter = Arc::new(ScriptedPrompter::default());
+    *prompter.team.lock().unwrap() = Some(Ok("TWO".to_string()));
+    let teams = [team("ONE"), team("TWO")];
+
+    // A remembered team that no longer exists is asked about again
+    let index = choose_team(&teams, Some("GONE"), prompter.clone())
+        .await
+        .unwrap();
+    assert_eq!(index, 1);
+    assert_eq!(prompter.asked(), vec!["2 teams"]);
+}
+
+#[tokio::test]
+async fn team_prompt_cancel_and_timeout_are_told_apart() {
+    let teams = [team("ONE"), team("TWO")];
+
+    let prompter = Arc::new(ScriptedPrompter::default());
+    let err = choose_team(&teams, None, prompter).await.unwrap_err();
+    assert!(matches!(err, IloaderError::NoTeamSelected));
+
+    let prompter = Arc::new(ScriptedPrompter::default());
+    *prompter.team.lock().unwrap() = Some(Err(PromptError::TimedOut));
+    let err = choose_team(&teams, None, prompter).await.unwrap_err();
+    assert!(matches!(
+        err,
+        IloaderError::PromptTimeout {
+            prompt: "team selection"
+        }
+    ));
+
+    let prompter = Arc::new(ScriptedPrompter::default());
+    *prompter.team.lock().unwrap() = Some(Ok("UNKNOWN".to_string()));
+    let err = choose_team(&teams, None, prompter).await.unwrap_err();
+    assert!(matches!(err, IloaderError::NoTeamSelected));
+}
+
+#[test]
+fn max_certs_prompt_revokes_the_chosen_certificates() {
+    let certs = [
+        cert("01", "iloader-laptop", CREATED),
+        cert("02", "Xcode Mac", CREATED),
+    ];
+    let prompter = ScriptedPrompter::default();
+    *prompter.certificates.lock().unwrap() = Some(Ok(vec!["02".to_string()]));
+
+    let serials = MaxCertsPolicy::Prompt.choose(&certs, &prompter).unwrap();
+    assert_eq!(serials, vec!["02".to_string()]);
+    assert_eq!(prompter.asked(), vec!["2 certificates"]);
+}
+
+#[test]
+fn max_certs_prompt_cancel_and_timeout_are_told_apart() {
+    let certs = [cert("01", "iloader-laptop", CREATED)];
+
+    let prompter = ScriptedPrompter::default();
+    assert_eq!(
+        MaxCertsPolicy::Prompt.choose(&certs, &prompter),
+        Err(Refusal::Declined)
+    );
+
+    let prompter = ScriptedPrompter::default();
+    *prompter.certificates.lock().unwrap() = Some(Err(PromptError::TimedOut));
+    assert_eq!(
+        MaxCertsPolicy::Prompt.choose(&certs, &prompter),
+        Err(Refusal::TimedOut)
+    );
+}
+
+#[test]
+fn max_certs_policies_other_than_prompt_never_ask() {
+    let certs = [
+        cert("01", "iloader-laptop", CREATED),
+        cert("02", "Xcode Mac", CREATED),
+    ];
+    let prompter = ScriptedPrompter::default();
+
+    assert_eq!(
+        MaxCertsPolicy::RevokeOldestIloader.choose(&certs, &prompter),
+        Ok(vec!["01".to_string()])
+    );
+    assert_eq!(
+        MaxCertsPolicy::Fail.choose(&certs, &prompter),
+        Err(Refusal::Declined)
+    );
+    assert!(prompter.asked().is_empty());
+}
+
+#[tokio::test]
+async fn login_without_two_factor_never_asks() {
+    let prompter = Arc::new(ScriptedPrompter::default());
+    let mut login = ScriptedLogin::new(false);
+
+    login_with_two_factor(&mut login, EMAIL, prompter.clone())
+        .await
+        .unwrap();
+    assert_eq!(login.events, vec!["sign in"]);
+    assert!(prompter.asked().is_empty());
+}
+
+#[tokio::test]
+async fn two_factor_resend_signs_in_again() {
+    let prompter = Arc::new(ScriptedPrompter::default());
+    prompter.two_factor.lock().unwrap().extend([
+        Ok(TwoFactorResponse::Resend),
+        Ok(TwoFactorResponse::Code(VALID_CODE.to_string())),
+    ]);
+    let mut login = ScriptedLogin::new(true);
+
+    login_with_two_factor(&mut login, EMAIL, prompter.clone())
+        .await
+        .unwrap();
+    assert_eq!(
+        login.events,
+        vec![
+            "sign in".to_string(),
+            "send code".to_string(),
+            "sign in".to_string(),
+            "send code".to_string(),
+            format!("verify {}", VALID_CODE),
+        ]
+    );
+    assert_eq!(prompter.asked(), vec!["2fa", "2fa (resent)"]);
+}
+
+#[tokio::test]
+async fn two_factor_resends_are_capped() {
+    let prompter = Arc::new(ScriptedPrompter::default());
+    prompter
+        .two_factor
+        .lock()
+        .unwrap()
+        .extend((0..MAX_TWO_FACTOR_CODES).map(|_| Ok(TwoFactorResponse::Resend)));
+    let mut login = ScriptedLogin::new(true);
+
+    let err = login_with_two_factor(&mut login, EMAIL, prompter.clone())
+        .await
+        .unwrap_err();
+    assert!(matches!(err, IloaderError::Auth { .. }));
+    assert_eq!(prompter.asked().len(), MAX_TWO_FACTOR_CODES);
+}
+
+#[tokio::test]
+async fn two_factor_wrong_code_fails() {
+    let prompter = Arc::new(ScriptedPrompter::default());
+    prompter
+        .two_factor
+        .lock()
+        .unwrap()
+        .push_back(Ok(TwoFactorResponse::Code("000000".to_string())));
+    let mut login = ScriptedLogin::new(true);
+
+    let err = login_with_two_factor(&mut login, EMAIL, prompter)
+        .await
+        .unwrap_err();
+    assert!(matches!(err, IloaderError::Auth { .. }));
+    assert_eq!(login.events.last().unwrap(), "verify 000000");
+}
+
+#[tokio::test]
+async fn two_factor_cancel_and_timeout_are_told_apart() {
+    let prompter = Arc::new(ScriptedPrompter::default());
+    let err = login_with_two_factor(&mut ScriptedLogin::new(true), EMAIL, prompter)
+        .await
+        .unwrap_err();
+    assert!(matches!(err, IloaderError::TwoFactorCancelled));
+
+    let prompter = Arc::new(ScriptedPrompter::default());
+    prompter
+        .two_factor
+        .lock()
+        .unwrap()
+        .push_back(Err(PromptError::TimedOut));
+    let err = login_with_two_factor(&mut ScriptedLogin::new(true), EMAIL, prompter)
+        .await
+        .unwrap_err();
+    assert!(matches!(err, IloaderError::TwoFactorTimeout));
+}
+
+#[test]
+fn two_factor_responses_parse_from_frontend_payloads() {
+    assert_eq!(
+        TwoFactorResponse::parse(r#"{"code":"123456"}"#),
+        Some(TwoFactorResponse::Code("123456".to_string()))
+    );
+    assert_eq!(
+        TwoFactorResponse::parse(r#""resend""#),
+        Some(TwoFactorResponse::Resend)
+    );
+    // Older frontends send just the code
+    assert_eq!(
+        TwoFactorResponse::parse(r#""654321""#),
+        Some(TwoFactorResponse::Code("654321".to_string()))
+    );
+    assert_eq!(
+        TwoFactorResponse::parse("654321"),
+        Some(TwoFactorResponse::Code("654321".to_string()))
+    );
+    assert_eq!(TwoFactorResponse::parse(r#""""#), None);
+}
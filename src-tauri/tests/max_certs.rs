@@ -0,0 +1,123 @@
+mod common;
+
+use common::cert;
+use iloader_lib::{
+    account::CertificateInfo,
+    error::IloaderError,
+    max_certs::{Decision, MaxCertsPolicy, Refusal, record_refusal, take_refusal},
+};
+
+fn certs() -> Vec<CertificateInfo> {
+    vec![
+        cert("01", "Xcode Mac", Some("2024-01-01T00:00:00+00:00")),
+        cert("02", "iloader-laptop", Some("2024-06-01T00:00:00+00:00")),
+        cert("03", "iloader", Some("2024-03-01T00:00:00+00:00")),
+        cert("04", "AltStore", Some("2023-01-01T00:00:00+00:00")),
+    ]
+}
+
+fn revoke(serials: &[&str]) -> Decision {
+    Decision::Revoke(serials.iter().map(|s| s.to_string()).collect())
+}
+
+#[test]
+fn prompt_asks() {
+    assert_eq!(MaxCertsPolicy::Prompt.decide(&certs()), Decision::Prompt);
+}
+
+#[test]
+fn fail_refuses() {
+    assert_eq!(MaxCertsPolicy::Fail.decide(&certs()), Decision::Refuse);
+}
+
+#[test]
+fn revoke_oldest_picks_the_oldest_iloader_certificate() {
+    // Older certificates from other tools are left alone
+    assert_eq!(
+        MaxCertsPolicy::RevokeOldestIloader.decide(&certs()),
+        revoke(&["03"])
+    );
+}
+
+#[test]
+fn revoke_oldest_counts_this_machine() {
+    let mut certs = certs();
+    certs.push(CertificateInfo {
+        this_machine: true,
+        ..cert("05", "Custom Name", Some("2024-02-01T00:00:00+00:00"))
+    });
+    assert_eq!(
+        MaxCertsPolicy::RevokeOldestIloader.decide(&certs),
+        revoke(&["05"])
+    );
+}
+
+#[test]
+fn revoke_oldest_skips_certificates_without_a_date() {
+    let mut certs = certs();
+    certs.push(cert("06", "iloader-desktop", None));
+    assert_eq!(
+        MaxCertsPolicy::RevokeOldestIloader.decide(&certs),
+        revoke(&["03"])
+    );
+
+    let undated = vec![
+        cert("06", "iloader-desktop", None),
+        cert("07", "iloader", None),
+    ];
+    assert_eq!(
+        MaxCertsPolicy::RevokeOldestIloader.decide(&undated),
+        Decision::Refuse
+    );
+}
+
+#[test]
+fn revoke_oldest_refuses_without_iloader_certificates() {
+    let certs = vec![cert("01", "Xcode Mac", Some("2024-01-01T00:00:00+00:00"))];
+    assert_eq!(
+        MaxCertsPolicy::RevokeOldestIloader.decide(&certs),
+        Decision::Refuse
+    );
+}
+
+#[test]
+fn revoke_machines_matches_names_loosely() {
+    let policy = MaxCertsPolicy::RevokeMachines {
+        machines: vec![" xcode mac ".to_string(), "iloader".to_string()],
+    };
+    assert_eq!(policy.decide(&certs()), revoke(&["01", "03"]));
+}
+
+#[test]
+fn revoke_machines_refuses_without_a_match() {
+    let policy = MaxCertsPolicy::RevokeMachines {
+        machines: vec!["Old Laptop".to_string()],
+    };
+    assert_eq!(policy.decide(&certs()), Decision::Refuse);
+}
+
+#[test]
+fn policy_reads_frontend_preferences() {
+    let policy: MaxCertsPolicy =
+        serde_json::from_str(r#"{"mode":"revokeMachines","machines":["Old Laptop"]}"#).unwrap();
+    assert_eq!(
+        policy,
+        MaxCertsPolicy::RevokeMachines {
+            machines: vec!["Old Laptop".to_string()]
+        }
+    );
+    let policy: MaxCertsPolicy = serde_json::from_str(r#"{"mode":"revokeOldestIloader"}"#).unwrap();
+    assert_eq!(policy, MaxCertsPolicy::RevokeOldestIloader);
+}
+
+#[test]
+fn refusals_are_taken_once() {
+    record_refusal("Refused@Example.com", Refusal::TimedOut);
+    assert_eq!(take_refusal("refused@example.com"), Some(Refusal::TimedOut));
+    assert_eq!(take_refusal("refused@example.com"), None);
+
+    let err: IloaderError = Refusal::Declined.into();
+    assert_eq!(err.code(), "max_certs_reached");
+    let err: IloaderError = Refusal::TimedOut.into();
+    assert_eq!(err.code(), "prompt_timeout");
+}
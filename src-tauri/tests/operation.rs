@@ -0,0 +1,179 @@
+use std::sync::Mutex;
+
+use iloader_lib::{
+    device::DeviceInfo,
+    device_backend::fake::FakeDeviceBackend,
+    error::IloaderError,
+    history::{HistoryFilter, HistoryJournal},
+    operation::{JsonLinesProgress, Operation, RecordedUpdate, RecordingProgress},
+    sideload::{SessionRegistry, run_sideload},
+};
+use tokio_util::sync::CancellationToken;
+
+const UDID: &str = "00008030-001A2B3C4D5E6F70";
+
+fn update(update_type: &str, step_id: &str, error: Option<&str>) -> RecordedUpdate {
+    RecordedUpdate {
+        operation_id: "sideload".to_string(),
+        update_type: update_type.to_string(),
+        step_id: step_id.to_string(),
+        error: error.map(str::to_string),
+    }
+}
+
+fn device() -> DeviceInfo {
+    DeviceInfo {
+        name: "Test iPhone".to_string(),
+        id: 1,
+        uuid: UDID.to_string(),
+        connection_type: "USB".to_string(),
+    }
+}
+
+// Runs a sideload without a logged in account, so it always fails at the install step
+async fn sideload_logged_out(
+    op: &Operation<'_>,
+    device: Option<DeviceInfo>,
+) -> Result<(), IloaderError> {
+    run_sideload(
+        op,
+        &FakeDeviceBackend::default(),
+        &Mutex::new(device),
+        &SessionRegistry::default().select(None),
+        "App.ipa".to_string(),
+    )
+    .await
+}
+
+#[tokio::test]
+async fn sideload_fails_without_device() {
+    let progress = RecordingProgress::default();
+    let op = Operation::new("sideload".to_string(), &progress, CancellationToken::new());
+    let err = sideload_logged_out(&op, None).await.unwrap_err();
+
+    assert_eq!(err.code(), "no_device_selected");
+    assert_eq!(
+        progress.updates(),
+        vec![
+            update("started", "install", None),
+            update("failed", "install", Some("no_device_selected")),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn sideload_fails_when_logged_out() {
+    let progress = RecordingProgress::default();
+    let op = Operation::new("sideload".to_string(), &progress, CancellationToken::new());
+    sideload_logged_out(&op, Some(device())).await.unwrap_err();
+
+    assert_eq!(
+        progress.updates(),
+        vec![
+            update("started", "install", None),
+            update("failed", "install", Some("not_logged_in")),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn cancelled_sideload_reports_cancelled_step() {
+    let progress = RecordingProgress::default();
+    let cancel = CancellationToken::new();
+    cancel.cancel();
+    let op = Operation::new("sideload".to_string(), &progress, cancel);
+
+    let err = sideload_logged_out(&op, Some(device())).await.unwrap_err();
+
+    assert_eq!(err.code(), "cancelled");
+    assert_eq!(
+        progress.updates(),
+        vec![
+            update("started", "install", None),
+            update("cancelled", "install", None),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn failed_sideload_is_written_to_history() {
+    let dir = std::env::temp_dir().join(format!("iloader-history-{}", std::process::id()));
+    let journal = HistoryJournal::new(&dir);
+    journal.clear().unwrap();
+
+    let progress = RecordingProgress::default();
+    let op = Operation::new("sideload".to_string(), &progress, CancellationToken::new())
+        .with_journal(Some(&journal));
+    sideload_logged_out(&op, Some(device())).await.unwrap_err();
+
+    let entries = journal.entries(&HistoryFilter::default()).unwrap();
+    assert_eq!(entries.len(), 1);
+    let entry = &entries[0];
+    assert_eq!(entry.operation, "sideload");
+    assert_eq!(entry.outcome.as_deref(), Some("failed"));
+    assert_eq!(entry.device_udid.as_deref(), Some(UDID));
+    assert_eq!(entry.source.as_deref(), Some("App.ipa"));
+    assert_eq!(entry.steps.len(), 1);
+    assert_eq!(entry.steps[0].outcome.as_deref(), Some("failed"));
+    assert_eq!(
+        entry.steps[0].error.as_ref().map(|e| e.code.as_str()),
+        Some("not_logged_in")
+    );
+
+    let succeeded = HistoryFilter {
+        outcome: Some("succeeded".to_string()),
+        ..Default::default()
+    };
+    assert!(journal.entries(&succeeded).unwrap().is_empty());
+
+    journal.clear().unwrap();
+    assert!(
+        journal
+            .entries(&HistoryFilter::default())
+            .unwrap()
+            .is_empty()
+    );
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn json_lines_match_frontend_events() {
+    let progress = JsonLinesProgress::new(Vec::new());
+    let op = Operation::new("sideload".to_string(), &progress, CancellationToken::new());
+    op.move_on("download", "install").unwrap();
+    drop(op);
+
+    let output = progress.into_inner();
+    let lines: Vec<serde_json::Value> = String::from_utf8(output)
+        .unwrap()
+        .lines()
+        .map(|l| serde_json::from_str(l).unwrap())
+        .collect();
+    assert_eq!(
+        lines,
+        vec![
+            serde_json::json!({"operation": "sideload", "updateType": "finished", "stepId": "download", "extraDetails": null}),
+            serde_json::json!({"operation": "sideload", "updateType": "started", "stepId": "install", "extraDetails": null}),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn sideload_fails_for_unknown_session() {
+    let progress = RecordingProgress::default();
+    let op = Operation::new("sideload".to_string(), &progress, CancellationToken::new());
+    let sessions = SessionRegistry::default();
+    let err = run_sideload(
+        &op,
+        &FakeDeviceBackend::default(),
+        &Mutex::new(Some(device())),
+        &sessions.select(Some("other@example.com".to_string())),
+        "App.ipa".to_string(),
+    )
+    .await
+    .unwrap_err();
+
+    assert_eq!(err.code(), "session_not_found");
+    assert!(sessions.sessions().is_empty());
+    assert_eq!(sessions.active_email(), None);
+}
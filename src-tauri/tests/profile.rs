@@ -0,0 +1,103 @@
+mod common;
+
+use std::{fs, path::Path};
+
+use common::{data_dir, signing_key_key};
+use iloader_lib::{
+    account::disable_keyring, encrypted_storage::EncryptedFsStorage, error::IloaderError, profile,
+};
+use isideload::util::storage::SideloadingStorage;
+use serde_json::{Map, Value, json};
+
+const EMAIL: &str = "user@example.com";
+const TEAM_ID: &str = "TEAM123456";
+const MACHINE_NAME: &str = "iloader-laptop";
+// Stands in for the PKCS#8 DER isideload stores, which isn't valid UTF-8
+const SIGNING_KEY: &[u8] = &[0x30, 0x82, 0x04, 0xbe, 0x02, 0x01, 0x00, 0xff, 0xfe];
+
+// With the keyring disabled, the shared storage is encrypted files directly in the data directory
+fn shared_storage(data_dir: &Path) -> EncryptedFsStorage {
+    EncryptedFsStorage::new(data_dir, data_dir.to_path_buf())
+}
+
+fn teams(team_id: &str) -> Map<String, Value> {
+    let mut teams = Map::new();
+    teams.insert(EMAIL.to_string(), Value::String(team_id.to_string()));
+    teams
+}
+
+// No saved ids, so no passwords are read from or written to the keyring
+fn export_with_signing_key(name: &str) -> Vec<u8> {
+    disable_keyring();
+    let dir = data_dir(name);
+    shared_storage(&dir)
+        .store_data(&signing_key_key(EMAIL), SIGNING_KEY)
+        .unwrap();
+
+    let machine_names = json!({ EMAIL: MACHINE_NAME });
+    let (bundle, summary) =
+        profile::export(&dir, &[], &teams(TEAM_ID), Some(&machine_names), "hunter2").unwrap();
+    assert_eq!(summary.accounts, vec![EMAIL.to_string()]);
+    assert_eq!(summary.entries, 1);
+    assert!(summary.missing_identities.is_empty());
+    let _ = fs::remove_dir_all(&dir);
+    bundle
+}
+
+#[test]
+fn signing_key_round_trips() {
+    let bundle = export_with_signing_key("export");
+
+    let dir = data_dir("import");
+    let imported = profile::import(&dir, &bundle, "hunter2", false).unwrap();
+    assert_eq!(
+        imported.teams,
+        vec![(EMAIL.to_string(), TEAM_ID.to_string())]
+    );
+    // The certificate is only reused under the name it was registered with
+    assert_eq!(
+        imported.machine_names,
+        vec![(EMAIL.to_string(), MACHINE_NAME.to_string())]
+    );
+    assert!(imported.ids.is_empty());
+
+    assert_eq!(
+        shared_storage(&dir)
+            .retrieve_data(&signing_key_key(EMAIL))
+            .unwrap()
+            .as_deref(),
+        Some(SIGNING_KEY)
+    );
+
+    // Importing again keeps what's already there
+    let again = profile::import(&dir, &bundle, "hunter2", false).unwrap();
+    assert_eq!(again.summary.entries, 0);
+    assert_eq!(again.summary.skipped, imported.summary.entries);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn wrong_password_is_rejected() {
+    let bundle = export_with_signing_key("wrong-password");
+
+    let dir = data_dir("wrong-password-import");
+    let err = profile::import(&dir, &bundle, "hunter3", false).unwrap_err();
+    assert!(matches!(err, IloaderError::ProfilePassword));
+    assert_eq!(
+        shared_storage(&dir)
+            .retrieve_data(&signing_key_key(EMAIL))
+            .unwrap(),
+        None
+    );
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn accounts_without_signing_key_are_reported() {
+    disable_keyring();
+    let dir = data_dir("missing-identity");
+
+    let (_, summary) = profile::export(&dir, &[], &teams(TEAM_ID), None, "hunter2").unwrap();
+    assert_eq!(summary.missing_identities, vec![EMAIL.to_string()]);
+    let _ = fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,109 @@
+#![cfg(unix)]
+
+use std::sync::{Arc, Mutex};
+
+use iloader_lib::{
+    device::{DeviceInfoMutex, list_devices, set_selected_device},
+    device_backend::{
+        DeviceBackendState, UsbmuxdBackend,
+        emulator::UsbmuxdEmulator,
+        fake::{FakeDevice, FakeDeviceBackend},
+    },
+    pairing::{installed_pairing_apps, place_pairing_cmd},
+};
+use tauri::Manager;
+
+const UDID: &str = "00008030-001A2B3C4D5E6F70";
+
+fn pair_record() -> Vec<u8> {
+    let root = rcgen::generate_simple_self_signed(vec!["root".to_string()]).unwrap();
+    let host = rcgen::generate_simple_self_signed(vec!["host".to_string()]).unwrap();
+    let device = rcgen::generate_simple_self_signed(vec!["device".to_string()]).unwrap();
+
+    let mut record = plist::Dictionary::new();
+    let mut insert_pem = |key: &str, pem: String| {
+        record.insert(key.to_string(), plist::Value::Data(pem.into_bytes()));
+    };
+    insert_pem("DeviceCertificate", device.cert.pem());
+    insert_pem("HostCertificate", host.cert.pem());
+    insert_pem("HostPrivateKey", host.signing_key.serialize_pem());
+    insert_pem("RootCertificate", root.cert.pem());
+    insert_pem("RootPrivateKey", root.signing_key.serialize_pem());
+    record.insert("HostID".to_string(), "ILOADER-TEST-HOST".into());
+    record.insert("SystemBUID".to_string(), "ILOADER-TEST-BUID".into());
+    record.insert(
+        "EscrowBag".to_string(),
+        plist::Value::Data(b"escrow".to_vec()),
+    );
+    record.insert("WiFiMACAddress".to_string(), "00:00:00:00:00:00".into());
+
+    let mut buf = Vec::new();
+    plist::to_writer_xml(&mut buf, &plist::Value::Dictionary(record)).unwrap();
+    buf
+}
+
+// Drives the real commands through idevice against the emulator. This is the only test in this
+// binary since it points the process-wide USBMUXD_SOCKET_ADDRESS at the emulator.
+#[tokio::test(flavor = "multi_thread")]
+async fn pairs_sidestore_through_usbmuxd() {
+    let emulator = UsbmuxdEmulator::start(Arc::new(FakeDeviceBackend::new(vec![
+        FakeDevice::new(1, UDID, "Test iPhone")
+            .with_pair_record(pair_record())
+            .with_app("com.SideStore.SideStore.ABCDE12345", "SideStore")
+            .with_app("com.example.notes", "Notes"),
+    ])))
+    .await
+    .unwrap();
+    unsafe { std::env::set_var("USBMUXD_SOCKET_ADDRESS", emulator.socket_path()) };
+
+    let app = tauri::test::mock_builder()
+        .manage::<DeviceBackendState>(Arc::new(UsbmuxdBackend))
+        .manage::<DeviceInfoMutex>(Mutex::new(None))
+        .build(tauri::test::mock_context(tauri::test::noop_assets()))
+        .unwrap();
+
+    let devices = list_devices(app.state()).await.unwrap();
+    assert_eq!(devices.len(), 1);
+    assert_eq!(devices[0].name, "Test iPhone");
+    assert_eq!(devices[0].uuid, UDID);
+
+    set_selected_device(app.state(), Some(devices[0].clone()))
+        .await
+        .unwrap();
+
+    let apps = installed_pairing_apps(app.state(), app.state())
+        .await
+        .unwrap();
+    assert_eq!(apps.len(), 1);
+    assert_eq!(apps[0].bundle_id, "com.SideStore.SideStore.ABCDE12345");
+
+    place_pairing_cmd(
+        app.state(),
+        app.state(),
+        apps[0].bundle_id.clone(),
+        apps[0].path.clone(),
+    )
+    .await
+    .unwrap();
+
+    let fake = emulator.backend().device(UDID).unwrap();
+    let written = fake
+        .documents
+        .get(&(apps[0].bundle_id.clone(), apps[0].path.clone()))
+        .expect("pairing file should be written through AFC");
+    let written = plist::Value::from_reader(std::io::Cursor::new(written)).unwrap();
+    assert_eq!(
+        written
+            .as_dictionary()
+            .and_then(|d| d.get("UDID"))
+            .and_then(|v| v.as_string()),
+        Some(UDID)
+    );
+    assert_eq!(
+        fake.values.get(&(
+            Some("com.apple.mobile.wireless_lockdown".to_string()),
+            "EnableWifiDebugging".to_string()
+        )),
+        Some(&true.into())
+    );
+}
@@ -82,9 +82,12 @@ function App() {
       operation: Operation,
       params: { [key: string]: any },
     ): Promise<void> => {
+      const runId = crypto.randomUUID();
       setOperationState({
         current: operation,
+        runId,
         started: [],
+        cancelled: [],
         failed: [],
         completed: [],
       });
@@ -104,6 +107,11 @@ function App() {
                   ...old,
                   completed: [...old.completed, event.payload.stepId],
                 };
+              } else if (event.payload.updateType === "cancelled") {
+                return {
+                  ...old,
+                  cancelled: [...old.cancelled, event.payload.stepId],
+                };
               } else if (event.payload.updateType === "failed") {
                 return {
                   ...old,
@@ -121,7 +129,7 @@ function App() {
           },
         );
         try {
-          await invoke(operation.id + "_operation", params);
+          await invoke(operation.id + "_operation", { ...params, runId });
           unlistenFn();
           resolve();
         } catch (e) {
@@ -12,6 +12,29 @@ import { useTranslation } from "react-i18next";
 
 const store = await load("data.json");
 
+export type TwoFactorRequest = {
+  resent: boolean;
+};
+
+export type Session = {
+  email: string;
+  active: boolean;
+  busy: boolean;
+};
+
+export type Team = {
+  teamId: string;
+  name: string | null;
+  teamType: string | null;
+};
+
+type CredentialsCheck = {
+  keyringAvailable: boolean;
+  missingPasswords: string[];
+  orphanedPasswords: string[];
+  unreadable: { email: string; error: string }[];
+};
+
 export const AppleID = ({
   loggedInAs,
   setLoggedInAs,
@@ -21,12 +44,16 @@ export const AppleID = ({
 }) => {
   const { t } = useTranslation();
   const [storedIds, setStoredIds] = useState<string[]>([]);
+  const [credentialsCheck, setCredentialsCheck] =
+    useState<CredentialsCheck | null>(null);
+  const [sessions, setSessions] = useState<Session[]>([]);
   const [forceUpdateIds, setForceUpdateIds] = useState<number>(0);
   const [emailInput, setEmailInput] = useState<string>("");
   const [passwordInput, setPasswordInput] = useState<string>("");
   const [saveCredentials, setSaveCredentials] = useState<boolean>(false);
   const [tfaOpen, setTfaOpen] = useState<boolean>(false);
   const [tfaCode, setTfaCode] = useState<string>("");
+  const [tfaRequest, setTfaRequest] = useState<TwoFactorRequest | null>(null);
   const [addAccountOpen, setAddAccountOpen] = useState<boolean>(false);
   const [anisetteServer] = useStore<string>(
     "anisetteServer",
@@ -35,16 +62,31 @@ export const AppleID = ({
   const [certs, setCerts] = useState<Certificate[] | null>(null);
   const [selectedSerials, setSelectedSerials] = useState<string[]>([]);
   const [chooseCertsOpen, setChooseCertsOpen] = useState<boolean>(false);
+  const [teams, setTeams] = useState<Team[] | null>(null);
+  const [machineName, setMachineName] = useState<string>("");
   const { err } = useError();
 
+  useEffect(() => {
+    if (!loggedInAs) return;
+    invoke<string>("get_machine_name", { email: loggedInAs }).then(
+      setMachineName,
+    );
+  }, [loggedInAs]);
+
   useEffect(() => {
     let getLoggedInAs = async () => {
       let account = await invoke<string | null>("logged_in_as");
       setLoggedInAs(account);
+      setSessions(await invoke<Session[]>("list_sessions"));
     };
     let getStoredIds = async () => {
       let ids = (await store.get<string[]>("ids")) ?? [];
       setStoredIds(ids);
+      setCredentialsCheck(
+        await invoke<CredentialsCheck>("check_saved_credentials").catch(
+          () => null,
+        ),
+      );
     };
 
     getLoggedInAs();
@@ -61,9 +103,13 @@ export const AppleID = ({
   useEffect(() => {
     if (!listenerAdded.current) {
       (async () => {
-        const unlistenFn = await listen("2fa-required", () => {
-          setTfaOpen(true);
-        });
+        const unlistenFn = await listen<TwoFactorRequest>(
+          "2fa-required",
+          (event) => {
+            setTfaRequest(event.payload);
+            setTfaOpen(true);
+          },
+        );
         unlisten.current = unlistenFn;
       })();
       listenerAdded.current = true;
@@ -94,6 +140,53 @@ export const AppleID = ({
     };
   }, []);
 
+  const methodListenerAdded = useRef<boolean>(false);
+  const methodUnlisten = useRef<() => void>(() => { });
+
+  useEffect(() => {
+    if (!methodListenerAdded.current) {
+      (async () => {
+        const unlistenFn = await listen<{
+          email: string;
+          method: "resumed" | "password";
+          resumeError: unknown | null;
+        }>("login-method", (event) => {
+          if (event.payload.method === "resumed") {
+            toast.info(t("apple_id.session_resumed"));
+          } else if (event.payload.resumeError) {
+            toast.info(t("apple_id.session_expired"));
+          }
+        });
+        methodUnlisten.current = unlistenFn;
+      })();
+      methodListenerAdded.current = true;
+    }
+    return () => {
+      methodUnlisten.current();
+    };
+  }, []);
+
+  const teamListenerAdded = useRef<boolean>(false);
+  const teamUnlisten = useRef<() => void>(() => { });
+
+  useEffect(() => {
+    if (!teamListenerAdded.current) {
+      (async () => {
+        const unlistenFn = await listen<Team[]>(
+          "team-selection-required",
+          (teams) => {
+            setTeams(teams.payload);
+          },
+        );
+        teamUnlisten.current = unlistenFn;
+      })();
+      teamListenerAdded.current = true;
+    }
+    return () => {
+      teamUnlisten.current();
+    };
+  }, []);
+
   return (
     <>
       <h2 style={{ marginTop: 0 }}>{t("apple_id.title")}</h2>
@@ -103,6 +196,27 @@ export const AppleID = ({
             <div className="logged-info">
               <span className="logged-label">{t("apple_id.logged_in_as")}</span>
               <span className="logged-value">{loggedInAs}</span>
+              <label className="logged-label">
+                {t("apple_id.machine_name")}
+                <input
+                  type="text"
+                  value={machineName}
+                  title={t("apple_id.machine_name_hint")}
+                  onChange={(e) => setMachineName(e.target.value)}
+                  onBlur={async () => {
+                    try {
+                      setMachineName(
+                        await invoke<string>("set_machine_name", {
+                          email: loggedInAs,
+                          machineName,
+                        }),
+                      );
+                    } catch (e) {
+                      err(t("apple_id.machine_name_failed"), e);
+                    }
+                  }}
+                />
+              </label>
             </div>
             <div className="action-row">
               <button
@@ -125,6 +239,144 @@ export const AppleID = ({
             </div>
           </div>
         )}
+        {sessions.filter((s) => !s.active).length > 0 && (
+          <div className="stored-ids">
+            <h3 style={{ margin: 0 }}>{t("apple_id.other_sessions")}</h3>
+            <div className="stored-container card">
+              {sessions
+                .filter((s) => !s.active)
+                .map((session) => (
+                  <div key={session.email} className="stored">
+                    <div className="stored-email">
+                      {session.email}
+                      {session.busy && ` (${t("apple_id.session_busy")})`}
+                    </div>
+                    <div className="action-row">
+                      <button
+                        type="button"
+                        className="action-button primary"
+                        onClick={async () => {
+                          try {
+                            await invoke("activate_session", {
+                              email: session.email,
+                            });
+                            setForceUpdateIds((v) => v + 1);
+                          } catch (e) {
+                            err(t("apple_id.switch_failed"), e);
+                          }
+                        }}
+                      >
+                        {t("apple_id.switch")}
+                      </button>
+                      <button
+                        type="button"
+                        className="action-button danger"
+                        onClick={async () => {
+                          await invoke("invalidate_account", {
+                            email: session.email,
+                          });
+                          setForceUpdateIds((v) => v + 1);
+                        }}
+                      >
+                        {t("apple_id.sign_out")}
+                      </button>
+                    </div>
+                  </div>
+                ))}
+            </div>
+          </div>
+        )}
+        {credentialsCheck &&
+          (credentialsCheck.missingPasswords.length > 0 ||
+            credentialsCheck.orphanedPasswords.length > 0) && (
+            <div className="stored-ids">
+              <h3 style={{ margin: 0 }}>
+                {t("apple_id.credential_issues")}
+              </h3>
+              <div className="stored-container card">
+                {credentialsCheck.missingPasswords.map((id) => (
+                  <div key={id} className="stored">
+                    <div className="stored-email">
+                      {t("apple_id.missing_password", { email: id })}
+                    </div>
+                    <div className="action-row">
+                      <button
+                        type="button"
+                        className="action-button primary"
+                        onClick={() => {
+                          setEmailInput(id);
+                          setSaveCredentials(true);
+                          setAddAccountOpen(true);
+                        }}
+                      >
+                        {t("apple_id.sign_in_again")}
+                      </button>
+                      <button
+                        type="button"
+                        className="action-button danger"
+                        onClick={() =>
+                          toast.promise(
+                            invoke("delete_account", { email: id }).then(() =>
+                              setForceUpdateIds((v) => v + 1),
+                            ),
+                            {
+                              loading: t("apple_id.deleting"),
+                              error: (e) =>
+                                err(t("apple_id.deletion_failed"), e),
+                              success: t("apple_id.deleted_success"),
+                            },
+                          )
+                        }
+                      >
+                        {t("common.delete")}
+                      </button>
+                    </div>
+                  </div>
+                ))}
+                {credentialsCheck.orphanedPasswords.map((email) => (
+                  <div key={email} className="stored">
+                    <div className="stored-email">
+                      {t("apple_id.orphaned_password", { email })}
+                    </div>
+                    <div className="action-row">
+                      <button
+                        type="button"
+                        className="action-button primary"
+                        onClick={() =>
+                          invoke("restore_saved_account", { email })
+                            .then(() => setForceUpdateIds((v) => v + 1))
+                            .catch((e) =>
+                              err(t("apple_id.restore_account_failed"), e),
+                            )
+                        }
+                      >
+                        {t("apple_id.restore_account")}
+                      </button>
+                      <button
+                        type="button"
+                        className="action-button danger"
+                        onClick={() =>
+                          toast.promise(
+                            invoke("delete_account", { email }).then(() =>
+                              setForceUpdateIds((v) => v + 1),
+                            ),
+                            {
+                              loading: t("apple_id.deleting"),
+                              error: (e) =>
+                                err(t("apple_id.deletion_failed"), e),
+                              success: t("apple_id.deleted_success"),
+                            },
+                          )
+                        }
+                      >
+                        {t("common.delete")}
+                      </button>
+                    </div>
+                  </div>
+                ))}
+              </div>
+            </div>
+          )}
         {storedIds.length > 0 && (
           <div className="stored-ids">
             <h3 style={{ margin: 0 }}>{t("apple_id.saved_logins")}</h3>
@@ -133,7 +385,7 @@ export const AppleID = ({
                 <div key={id} className="stored">
                   <div className="stored-email">{id}</div>
                   <div className="action-row">
-                    {!loggedInAs && (
+                    {!sessions.some((s) => s.email === id.toLowerCase()) && (
                       <button
                         type="button"
                         className="action-button primary"
@@ -257,7 +509,11 @@ export const AppleID = ({
       </div>
       <Modal sizeFit isOpen={tfaOpen} zIndex={2000}>
         <h2>{t("apple_id.two_factor_title")}</h2>
-        <p>{t("apple_id.two_factor_prompt")}</p>
+        <p>
+          {tfaRequest?.resent
+            ? t("apple_id.two_factor_resent")
+            : t("apple_id.two_factor_prompt")}
+        </p>
         <input
           type="text"
           placeholder={t("apple_id.verification_placeholder")}
@@ -271,13 +527,35 @@ export const AppleID = ({
               toast.warning(t("apple_id.valid_6digit"));
               return;
             }
-            await emit("2fa-recieved", tfaCode);
+            await emit("2fa-recieved", { code: tfaCode });
             setTfaOpen(false);
             setTfaCode("");
           }}
         >
           {t("apple_id.submit")}
         </button>
+        <button
+          className="action-button danger"
+          style={{ marginLeft: "0.5em" }}
+          onClick={async () => {
+            await emit("prompt-cancelled", "2fa-required");
+            setTfaOpen(false);
+            setTfaCode("");
+          }}
+        >
+          {t("common.cancel")}
+        </button>
+        <div className="tfa-actions">
+          <button
+            className="action-button"
+            onClick={async () => {
+              await emit("2fa-recieved", "resend");
+              setTfaOpen(false);
+            }}
+          >
+            {t("apple_id.two_factor_resend")}
+          </button>
+        </div>
       </Modal>
       <Modal sizeFit isOpen={certs !== null} zIndex={2000}>
         <h2 className="cert-header">{t("apple_id.max_certs_title")}</h2>
@@ -319,6 +597,7 @@ export const AppleID = ({
                 />
                 <label htmlFor={cert.serialNumber}>
                   {cert.name} - {cert.machineName}
+                  {cert.thisMachine && ` (${t("certificates.this_machine")})`}
                 </label>
               </div>
             ))}
@@ -341,7 +620,7 @@ export const AppleID = ({
           <button
             className="action-button danger"
             onClick={async () => {
-              await emit("max-certs-response", null);
+              await emit("prompt-cancelled", "max-certs-reached");
               setCerts(null);
               setChooseCertsOpen(false);
             }}
@@ -350,6 +629,38 @@ export const AppleID = ({
           </button>
         </div>
       </Modal>
+      <Modal sizeFit isOpen={teams !== null} zIndex={2000}>
+        <h2>{t("apple_id.select_team_title")}</h2>
+        <p className="certs-desc">{t("apple_id.select_team_desc")}</p>
+        <div className="certs-list">
+          {teams?.map((team) => (
+            <div
+              key={team.teamId}
+              className="cert-item"
+              role="button"
+              tabIndex={0}
+              onClick={async () => {
+                await emit("team-selected", team.teamId);
+                setTeams(null);
+              }}
+            >
+              {team.name ?? t("apple_id.unknown_team")} ({team.teamId})
+              {team.teamType && ` - ${team.teamType}`}
+            </div>
+          ))}
+        </div>
+        <div className="certs-buttons">
+          <button
+            className="action-button danger"
+            onClick={async () => {
+              await emit("prompt-cancelled", "team-selection-required");
+              setTeams(null);
+            }}
+          >
+            {t("common.cancel")}
+          </button>
+        </div>
+      </Modal>
     </>
   );
 };
@@ -4,9 +4,15 @@ import "./ErrorContext.css";
 import { toast } from "sonner";
 import { openUrl } from "@tauri-apps/plugin-opener";
 import { Trans, useTranslation } from "react-i18next";
+import {
+  errorDetails,
+  errorHint,
+  errorMessage,
+  isIloaderError,
+} from "./errors";
 
 export const ErrorContext = createContext<{
-  err: (msg: string, err: string | null) => string;
+  err: (msg: string, err: unknown) => string;
 }>({ err: () => "" });
 
 export const ErrorProvider: React.FC<{ children: React.ReactNode }> = ({
@@ -16,6 +22,7 @@ export const ErrorProvider: React.FC<{ children: React.ReactNode }> = ({
   const [msg, setMsg] = useState<string | null>(null);
   const [error, setError] = useState<string | null>(null);
   const [simpleError, setSimpleError] = useState<string | null>(null);
+  const [hint, setHint] = useState<string | null>(null);
   const [moreDetailsOpen, setMoreDetailsOpen] = useState<boolean>(false);
 
   useEffect(() => {
@@ -34,9 +41,11 @@ export const ErrorProvider: React.FC<{ children: React.ReactNode }> = ({
   return (
     <ErrorContext.Provider
       value={{
-        err: (msg: string, err: string | null) => {
+        err: (msg: string, err: unknown) => {
+          if (isIloaderError(err)) msg = `${msg}: ${errorMessage(err)}`;
           setMsg(msg);
-          setError(err);
+          setError(errorDetails(err));
+          setHint(isIloaderError(err) ? errorHint(err) : null);
           setMoreDetailsOpen(false);
           return msg;
         },
@@ -68,6 +77,7 @@ export const ErrorProvider: React.FC<{ children: React.ReactNode }> = ({
             </button>
           </div>
           {simpleError && <pre className="error-inner">{simpleError}</pre>}
+          {hint && <p>{hint}</p>}
           <p style={simpleError ? {} : { marginTop: "0.5rem" }}>
             <Trans
               i18nKey="error.support_message"
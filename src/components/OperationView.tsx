@@ -7,9 +7,11 @@ import {
   FaCircleMinus,
 } from "react-icons/fa6";
 import { useState } from "react";
+import { invoke } from "@tauri-apps/api/core";
 import { toast } from "sonner";
 import { openUrl } from "@tauri-apps/plugin-opener";
 import { Trans, useTranslation } from "react-i18next";
+import { errorHint, errorMessage } from "../errors";
 
 export default ({
   operationState,
@@ -20,14 +22,18 @@ export default ({
 }) => {
   const { t } = useTranslation();
   const operation = operationState.current;
-  const opFailed = operationState.failed.length > 0;
+  const opCancelled = operationState.cancelled.length > 0;
+  const opFailed = operationState.failed.length > 0 || opCancelled;
   const done =
     (opFailed &&
       operationState.started.length ==
-      operationState.completed.length + operationState.failed.length) ||
+      operationState.completed.length +
+      operationState.failed.length +
+      operationState.cancelled.length) ||
     operationState.completed.length == operation.steps.length;
 
   const [moreDetailsOpen, setMoreDetailsOpen] = useState(false);
+  const [cancelling, setCancelling] = useState(false);
 
   return (
     <Modal
@@ -46,24 +52,31 @@ export default ({
         </h2>
         <p>
           {done
-            ? opFailed
-              ? t("operation.failed")
-              : t("operation.completed")
-            : t("operation.please_wait")}
+            ? opCancelled
+              ? t("operation.cancelled")
+              : opFailed
+                ? t("operation.failed")
+                : t("operation.completed")
+            : cancelling
+              ? t("operation.cancelling")
+              : t("operation.please_wait")}
         </p>
       </div>
       <div className="operation-content-container">
         <div className="operation-content">
           {operation.steps.map((step) => {
             let failed = operationState.failed.find((f) => f.stepId == step.id);
+            let cancelled = operationState.cancelled.includes(step.id);
             let completed = operationState.completed.includes(step.id);
             let started = operationState.started.includes(step.id);
-            let notStarted = !failed && !completed && !started;
+            let notStarted = !failed && !cancelled && !completed && !started;
 
+            let details = failed?.extraDetails.details ?? "";
+            let hint = failed ? errorHint(failed.extraDetails) : null;
             // a little bit gross but it gets the job done.
             let lines =
-              failed?.extraDetails
-                ?.split("\n")
+              details
+                .split("\n")
                 .filter((line) => line.includes("●")) ?? [];
             let errorShort =
               lines[lines.length - 1]?.replace(/●\s*/, "").trim() ?? "";
@@ -74,10 +87,13 @@ export default ({
                   {failed && (
                     <FaCircleExclamation className="operation-error" />
                   )}
+                  {cancelled && (
+                    <FaCircleMinus className="operation-skipped" />
+                  )}
                   {!failed && completed && (
                     <FaCircleCheck className="operation-check" />
                   )}
-                  {!failed && !completed && started && (
+                  {!failed && !cancelled && !completed && started && (
                     <div className="loading-icon" />
                   )}
                   {notStarted && !opFailed && <div className="waiting-icon" />}
@@ -88,11 +104,14 @@ export default ({
 
                 <div className="operation-step-internal">
                   <p>{t(step.titleKey)}</p>
+                  {cancelled && <p>{t("operation.cancelled")}</p>}
                   {failed && (
                     <>
+                      <p>{errorMessage(failed.extraDetails)}</p>
                       <pre className="operation-extra-details">
-                        {!errorShort ? failed.extraDetails.replace(/^\n+/, "") : errorShort}
+                        {!errorShort ? details.replace(/^\n+/, "") : errorShort}
                       </pre>
+                      {hint && <p>{hint}</p>}
                       {errorShort !== "" && errorShort !== null && errorShort !== undefined &&
                         <>
                           <p
@@ -105,7 +124,7 @@ export default ({
                           </p>
                           {moreDetailsOpen && (
                             <pre className="operation-extra-details">
-                              {failed.extraDetails.replace(/^\n+/, "")}
+                              {details.replace(/^\n+/, "")}
                             </pre>
                           )}
                         </>
@@ -124,7 +143,7 @@ export default ({
         </p>
       )}
       {done && !(!opFailed && operation.successMessageKey) && <p></p>}
-      {opFailed && done && (
+      {opFailed && !opCancelled && done && (
         <>
           <p style={{ margin: "1.25rem 0 0.5rem 0" }}>
             <Trans
@@ -155,7 +174,7 @@ export default ({
             onClick={() => {
               navigator.clipboard.writeText(
                 "```\n" +
-                (operationState.failed[0]?.extraDetails?.replace(
+                (operationState.failed[0]?.extraDetails?.details?.replace(
                   /^\n+/,
                   "",
                 ) ?? t("common.no_error")) +
@@ -168,6 +187,18 @@ export default ({
           </button>
         </>
       )}
+      {!done && (
+        <button
+          style={{ width: "100%" }}
+          disabled={cancelling}
+          onClick={() => {
+            setCancelling(true);
+            invoke("cancel_operation", { id: operationState.runId });
+          }}
+        >
+          {t("common.cancel")}
+        </button>
+      )}
       {done && (
         <button style={{ width: "100%" }} onClick={closeMenu}>
           {t("common.dismiss")}
@@ -1,3 +1,5 @@
+import { IloaderError } from "../errors";
+
 export type Operation = {
   id: string;
   titleKey: string;
@@ -13,23 +15,26 @@ export type OperationStep = {
 
 export type OperationState = {
   current: Operation;
+  // Identifies this run to cancel_operation
+  runId: string;
   completed: string[];
   started: string[];
+  cancelled: string[];
   failed: {
     stepId: string;
-    extraDetails: string;
+    extraDetails: IloaderError;
   }[];
 };
 
 type OperationInfoUpdate = {
-  updateType: "started" | "finished";
+  updateType: "started" | "finished" | "cancelled";
   stepId: string;
 };
 
 type OperationFailedUpdate = {
   updateType: "failed";
   stepId: string;
-  extraDetails: string;
+  extraDetails: IloaderError;
 };
 
 export type OperationUpdate = OperationInfoUpdate | OperationFailedUpdate;
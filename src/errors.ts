@@ -0,0 +1,31 @@
+import i18n from "./i18next";
+
+export type IloaderError = {
+  code: string;
+  message: string;
+  fields: { [key: string]: unknown };
+  details: string;
+};
+
+export const isIloaderError = (e: unknown): e is IloaderError =>
+  typeof e === "object" && e !== null && "code" in e && "details" in e;
+
+// full error text, suitable for "more details" and copying to the clipboard
+export const errorDetails = (e: unknown): string | null => {
+  if (e === null || e === undefined) return null;
+  if (isIloaderError(e)) return e.details;
+  return String(e);
+};
+
+// localized, user friendly message for an error, falling back to the backend's message
+export const errorMessage = (e: IloaderError): string =>
+  i18n.t(`errors.${e.code}`, { ...e.fields, defaultValue: e.message });
+
+export const errorHint = (e: IloaderError): string | null => {
+  const key = `errors.${e.code}_hint`;
+  return i18n.exists(key) ? i18n.t(key, e.fields) : null;
+};
+
+// short text for places that only have room for a single line, like toasts
+export const errorText = (e: unknown): string =>
+  isIloaderError(e) ? errorMessage(e) : String(e);
@@ -4,6 +4,9 @@ import { useCallback, useEffect, useRef, useState } from "react";
 import { toast } from "sonner";
 import { useError } from "../ErrorContext";
 import { useTranslation } from "react-i18next";
+import { Modal } from "../components/Modal";
+import { useStore } from "../StoreContext";
+import { Dropdown } from "../components/Dropdown";
 
 export type Certificate = {
   name: string;
@@ -11,22 +14,89 @@ export type Certificate = {
   serialNumber: string;
   machineName: string;
   machineId: string;
+  created: string | null;
+  expires: string | null;
+  status: "valid" | "expiringSoon" | "expired" | "unknown";
+  inLocalStorage: boolean;
+  thisMachine: boolean;
 };
 
+type CertificateWarning =
+  | { type: "expiringSoon"; serialNumber: string; expires: string }
+  | { type: "expired"; serialNumber: string }
+  | { type: "revoked" };
+
+type CertificatesResponse = {
+  certificates: Certificate[];
+  localSerialNumber: string | null;
+  machineName: string;
+  warnings: CertificateWarning[];
+};
+
+type RevokeFilterType =
+  | "machineName"
+  | "namePattern"
+  | "createdBefore"
+  | "allExceptLocal";
+
+type CertificateRevocation = {
+  certificate: Certificate;
+  revoked: boolean;
+  error: string | null;
+};
+
+type RevokeCertificatesResponse = {
+  dryRun: boolean;
+  results: CertificateRevocation[];
+};
+
+const revokeFilter = (type: RevokeFilterType, value: string) => {
+  switch (type) {
+    case "machineName":
+      return { type, machineName: value };
+    case "namePattern":
+      return { type, pattern: value };
+    case "createdBefore":
+      return { type, date: value };
+    case "allExceptLocal":
+      return { type };
+  }
+};
+
+const formatDate = (date: string | null) =>
+  date ? new Date(date).toLocaleDateString() : "-";
+
 export const Certificates = () => {
   const { t } = useTranslation();
   const [certificates, setCertificates] = useState<Certificate[]>([]);
+  const [warnings, setWarnings] = useState<CertificateWarning[]>([]);
+  const [machineName, setMachineName] = useState<string | null>(null);
+  const [warnDays] = useStore<number>("certificateWarningDays", 30);
   const [loading, setLoading] = useState<boolean>(false);
   const loadingRef = useRef<boolean>(false);
   const { err } = useError();
+  const [identityAction, setIdentityAction] = useState<
+    "import" | "export" | null
+  >(null);
+  const [identityPassword, setIdentityPassword] = useState<string>("");
+
+  const [bulkOpen, setBulkOpen] = useState<boolean>(false);
+  const [filterType, setFilterType] = useState<RevokeFilterType>("machineName");
+  const [filterValue, setFilterValue] = useState<string>("");
+  const [revocations, setRevocations] =
+    useState<RevokeCertificatesResponse | null>(null);
 
   const loadCertificates = useCallback(async () => {
     if (loadingRef.current) return;
     const promise = async () => {
       loadingRef.current = true;
       setLoading(true);
-      let certs = await invoke<Certificate[]>("get_certificates");
-      setCertificates(certs);
+      let response = await invoke<CertificatesResponse>("get_certificates", {
+        warnDays,
+      });
+      setCertificates(response.certificates);
+      setWarnings(response.warnings);
+      setMachineName(response.machineName);
       setLoading(false);
       loadingRef.current = false;
     };
@@ -35,7 +105,7 @@ export const Certificates = () => {
       success: t("certificates.loaded_success"),
       error: (e) => err(t("certificates.failed_load"), e),
     });
-  }, [setCertificates, t]);
+  }, [setCertificates, warnDays, t]);
 
   const revokeCertificate = useCallback(
     async (serialNumber: string) => {
@@ -52,6 +122,58 @@ export const Certificates = () => {
     [setCertificates, loadCertificates, t],
   );
 
+  const revokeCertificates = useCallback(
+    async (dryRun: boolean) => {
+      const promise = invoke<RevokeCertificatesResponse>(
+        "revoke_certificates",
+        { filter: revokeFilter(filterType, filterValue), dryRun },
+      );
+      promise.then(setRevocations);
+      if (!dryRun) promise.then(loadCertificates);
+      toast.promise(promise, {
+        loading: dryRun
+          ? t("certificates.bulk_previewing")
+          : t("certificates.bulk_revoking"),
+        success: (response) =>
+          dryRun
+            ? t("certificates.bulk_preview_done", {
+                count: response.results.length,
+              })
+            : t("certificates.bulk_revoked", {
+                revoked: response.results.filter((r) => r.revoked).length,
+                failed: response.results.filter((r) => r.error).length,
+              }),
+        error: (e) => err(t("certificates.failed_revoke"), e),
+      });
+    },
+    [filterType, filterValue, loadCertificates, t],
+  );
+
+  const transferIdentity = useCallback(
+    (action: "import" | "export", password: string) => {
+      const promise = invoke<void>(`${action}_certificate`, { password });
+      if (action === "import") promise.then(loadCertificates);
+      toast.promise(promise, {
+        loading:
+          action === "import"
+            ? t("certificates.importing")
+            : t("certificates.exporting"),
+        success:
+          action === "import"
+            ? t("certificates.imported_success")
+            : t("certificates.exported_success"),
+        error: (e) =>
+          err(
+            action === "import"
+              ? t("certificates.failed_import")
+              : t("certificates.failed_export"),
+            e,
+          ),
+      });
+    },
+    [loadCertificates, t],
+  );
+
   useEffect(() => {
     loadCertificates();
   }, []);
@@ -59,6 +181,22 @@ export const Certificates = () => {
   return (
     <>
       <h2>{t("certificates.manage")}</h2>
+      {machineName && (
+        <p className="settings-hint">
+          {t("certificates.machine_name_hint", { machineName })}
+        </p>
+      )}
+      {warnings.map((warning) => (
+        <div key={warning.type} className="certificate-warning">
+          {warning.type === "expiringSoon"
+            ? t("certificates.warning_expiring", {
+                date: formatDate(warning.expires),
+              })
+            : warning.type === "expired"
+              ? t("certificates.warning_expired")
+              : t("certificates.warning_revoked")}
+        </div>
+      ))}
       {certificates.length === 0 ? (
         <div>{loading ? t("certificates.loading") : t("certificates.none_found")}</div>
       ) : (
@@ -71,6 +209,9 @@ export const Certificates = () => {
                   <th className="cert-item-part">{t("certificates.serial_number")}</th>
                   <th className="cert-item-part">{t("certificates.machine_name")}</th>
                   <th className="cert-item-part">{t("certificates.machine_id")}</th>
+                  <th className="cert-item-part">{t("certificates.created")}</th>
+                  <th className="cert-item-part">{t("certificates.expires")}</th>
+                  <th className="cert-item-part">{t("certificates.status")}</th>
                   <th>{t("certificates.revoke")}</th>
                 </tr>
               </thead>
@@ -80,13 +221,34 @@ export const Certificates = () => {
                     key={cert.certificateId}
                     className={
                       "certificate-item" +
-                      (i === certificates.length - 1 ? " cert-item-last" : "")
+                      (i === certificates.length - 1 ? " cert-item-last" : "") +
+                      (cert.inLocalStorage ? " cert-item-local" : "") +
+                      (cert.thisMachine ? " cert-item-this-machine" : "")
                     }
                   >
-                    <td className="cert-item-part">{cert.name}</td>
+                    <td className="cert-item-part">
+                      {cert.name}
+                      {cert.inLocalStorage && (
+                        <span className="cert-local-badge">
+                          {t("certificates.in_use_here")}
+                        </span>
+                      )}
+                    </td>
                     <td className="cert-item-part">{cert.serialNumber}</td>
-                    <td className="cert-item-part">{cert.machineName}</td>
+                    <td className="cert-item-part">
+                      {cert.machineName}
+                      {cert.thisMachine && (
+                        <span className="cert-local-badge">
+                          {t("certificates.this_machine")}
+                        </span>
+                      )}
+                    </td>
                     <td className="cert-item-part">{cert.machineId}</td>
+                    <td className="cert-item-part">{formatDate(cert.created)}</td>
+                    <td className="cert-item-part">{formatDate(cert.expires)}</td>
+                    <td className={"cert-item-part cert-status-" + cert.status}>
+                      {t(`certificates.status_${cert.status}`)}
+                    </td>
                     <td
                       className="cert-item-revoke"
                       role="button"
@@ -109,6 +271,139 @@ export const Certificates = () => {
       >
         {t("common.refresh")}
       </button>
+      <div className="certificate-actions">
+        <button onClick={() => setIdentityAction("import")}>
+          {t("certificates.import")}
+        </button>
+        <button onClick={() => setIdentityAction("export")}>
+          {t("certificates.export")}
+        </button>
+        <button onClick={() => setBulkOpen(true)}>
+          {t("certificates.bulk_revoke")}
+        </button>
+      </div>
+      <Modal
+        isOpen={bulkOpen}
+        close={() => {
+          setBulkOpen(false);
+          setRevocations(null);
+        }}
+      >
+        <h2>{t("certificates.bulk_revoke")}</h2>
+        <Dropdown
+          label={t("certificates.bulk_filter")}
+          labelId="revoke-filter"
+          options={[
+            {
+              value: "machineName",
+              label: t("certificates.bulk_filter_machineName"),
+            },
+            {
+              value: "namePattern",
+              label: t("certificates.bulk_filter_namePattern"),
+            },
+            {
+              value: "createdBefore",
+              label: t("certificates.bulk_filter_createdBefore"),
+            },
+            {
+              value: "allExceptLocal",
+              label: t("certificates.bulk_filter_allExceptLocal"),
+            },
+          ]}
+          value={filterType}
+          onChange={(value) => {
+            setFilterType(value as RevokeFilterType);
+            setFilterValue("");
+            setRevocations(null);
+          }}
+        />
+        {filterType !== "allExceptLocal" && (
+          <input
+            type={filterType === "createdBefore" ? "date" : "text"}
+            placeholder={t(`certificates.bulk_placeholder_${filterType}`)}
+            value={filterValue}
+            onChange={(e) => {
+              setFilterValue(e.target.value);
+              setRevocations(null);
+            }}
+          />
+        )}
+        {revocations && (
+          <ul className="revocation-results">
+            {revocations.results.length === 0 && (
+              <li>{t("certificates.bulk_none")}</li>
+            )}
+            {revocations.results.map((result) => (
+              <li
+                key={result.certificate.serialNumber}
+                className={result.error ? "revocation-failed" : undefined}
+                title={result.error ?? undefined}
+              >
+                {result.certificate.name} - {result.certificate.machineName} (
+                {result.certificate.serialNumber})
+                {result.error && `: ${t("certificates.bulk_failed")}`}
+                {result.revoked && `: ${t("certificates.bulk_done")}`}
+              </li>
+            ))}
+          </ul>
+        )}
+        <div className="certificate-actions">
+          <button
+            disabled={filterType !== "allExceptLocal" && filterValue === ""}
+            onClick={() => revokeCertificates(true)}
+          >
+            {t("certificates.bulk_preview")}
+          </button>
+          <button
+            className="action-button primary"
+            disabled={
+              !revocations?.dryRun || revocations.results.length === 0
+            }
+            onClick={() => revokeCertificates(false)}
+          >
+            {t("certificates.revoke")}
+          </button>
+        </div>
+      </Modal>
+      <Modal
+        isOpen={identityAction !== null}
+        close={() => {
+          setIdentityAction(null);
+          setIdentityPassword("");
+        }}
+      >
+        <h2>
+          {identityAction === "import"
+            ? t("certificates.import")
+            : t("certificates.export")}
+        </h2>
+        <p>
+          {identityAction === "import"
+            ? t("certificates.import_hint")
+            : t("certificates.export_hint")}
+        </p>
+        <input
+          type="password"
+          placeholder={t("certificates.p12_password")}
+          value={identityPassword}
+          onChange={(e) => setIdentityPassword(e.target.value)}
+        />
+        <button
+          className="action-button primary"
+          onClick={() => {
+            if (identityAction) {
+              transferIdentity(identityAction, identityPassword);
+            }
+            setIdentityAction(null);
+            setIdentityPassword("");
+          }}
+        >
+          {identityAction === "import"
+            ? t("certificates.import")
+            : t("certificates.export")}
+        </button>
+      </Modal>
     </>
   );
 };
@@ -17,16 +17,34 @@ type SettingsProps = {
   showHeading?: boolean;
 };
 
-let anisetteServers = [
-  ["ani.sidestore.io", "SideStore (.io)"],
-  ["ani.stikstore.app", "StikStore"],
-  ["ani.sidestore.app", "SideStore (.app)"],
-  ["ani.sidestore.zip", "SideStore (.zip)"],
-  ["ani.846969.xyz", "SideStore (.xyz)"],
-  ["ani.neoarz.xyz", "neoarz"],
-  ["ani.xu30.top", "SteX"],
-  ["anisette.wedotstud.io", "WE. Studio"],
-];
+type AnisetteServer = {
+  server: string;
+  name: string | null;
+};
+
+type AnisetteServerStatus = AnisetteServer & {
+  reachable: boolean;
+  latencyMs: number | null;
+  error: string | null;
+};
+
+type StorageBackend = {
+  backend: "keyring" | "encryptedFile";
+  keySource: "passphrase" | "machineId" | "keyFile" | null;
+  path: string | null;
+};
+
+type MaxCertsPolicy =
+  | { mode: "prompt" | "revokeOldestIloader" | "fail" }
+  | { mode: "revokeMachines"; machines: string[] };
+
+type ProfileSummary = {
+  accounts: string[];
+  entries: number;
+  skipped: number;
+  missingIdentities: string[];
+};
+
 export const Settings = ({ showHeading = true }: SettingsProps) => {
   const { t } = useTranslation();
   const [anisetteServer, setAnisetteServer] = useStore<string>(
@@ -34,15 +52,71 @@ export const Settings = ({ showHeading = true }: SettingsProps) => {
     "ani.sidestore.io",
   );
 
+  const [twoFactorTimeout, setTwoFactorTimeout] = useStore<number>(
+    "twoFactorTimeout",
+    120,
+  );
+  const [promptTimeout, setPromptTimeout] = useStore<number>(
+    "promptTimeout",
+    300,
+  );
+  const [certificateWarningDays, setCertificateWarningDays] = useStore<number>(
+    "certificateWarningDays",
+    30,
+  );
+
+  const [maxCertsPolicy, setMaxCertsPolicy] = useStore<MaxCertsPolicy>(
+    "maxCertsPolicy",
+    { mode: "prompt" },
+  );
+  // Edited as text and parsed on blur, so commas can be typed
+  const [machinesText, setMachinesText] = useState("");
+  useEffect(() => {
+    if (maxCertsPolicy.mode === "revokeMachines") {
+      setMachinesText(maxCertsPolicy.machines.join(", "));
+    }
+  }, [maxCertsPolicy]);
+  const maxCertsOptions = [
+    { value: "prompt", label: t("settings.max_certs_prompt") },
+    {
+      value: "revokeOldestIloader",
+      label: t("settings.max_certs_revoke_oldest"),
+    },
+    { value: "revokeMachines", label: t("settings.max_certs_revoke_machines") },
+    { value: "fail", label: t("settings.max_certs_fail") },
+  ];
+
+  const [anisetteServers, setAnisetteServers] = useState<AnisetteServer[]>(
+    [],
+  );
+  const [serverStatuses, setServerStatuses] = useState<
+    AnisetteServerStatus[] | null
+  >(null);
+  const [checkingServers, setCheckingServers] = useState(false);
+
+  const [storageBackend, setStorageBackend] = useState<StorageBackend | null>(
+    null,
+  );
+
+  useEffect(() => {
+    invoke<AnisetteServer[]>("anisette_servers").then(setAnisetteServers);
+    invoke<StorageBackend>("storage_backend").then(setStorageBackend);
+  }, []);
+
   const [logsOpen, setLogsOpen] = useState(false);
+  const [profileAction, setProfileAction] = useState<
+    "export" | "import" | null
+  >(null);
+  const [profilePassword, setProfilePassword] = useState("");
+  const [profileOverwrite, setProfileOverwrite] = useState(false);
   const [logLevelFilter, setLogLevelFilter] = useState("3");
   const logs = useLogs();
   const { err } = useError();
   const { confirm } = useDialog();
 
-  const anisetteOptions = anisetteServers.map(([value, label]) => ({
-    value,
-    label,
+  const anisetteOptions = anisetteServers.map(({ server, name }) => ({
+    value: server,
+    label: name ?? server,
   }));
   const logLevelOptions = [
     // { value: String(LogLevel.Trace), label: "Trace" },
@@ -78,6 +152,46 @@ export const Settings = ({ showHeading = true }: SettingsProps) => {
           customToggleLabel={t("settings.use_custom_anisette")}
           presetToggleLabel={t("settings.back_preset_servers")}
         />
+        <div>
+          <button
+            disabled={checkingServers}
+            onClick={async () => {
+              setCheckingServers(true);
+              try {
+                setServerStatuses(
+                  await invoke<AnisetteServerStatus[]>(
+                    "check_anisette_servers",
+                    { preferred: anisetteServer },
+                  ),
+                );
+              } finally {
+                setCheckingServers(false);
+              }
+            }}
+          >
+            {checkingServers
+              ? t("settings.checking_anisette_servers")
+              : t("settings.check_anisette_servers")}
+          </button>
+          {serverStatuses && (
+            <ul className="anisette-statuses">
+              {serverStatuses.map((status) => (
+                <li
+                  key={status.server}
+                  title={status.error ?? undefined}
+                  className={status.reachable ? "reachable" : "unreachable"}
+                >
+                  {status.name ?? status.server}:{" "}
+                  {status.reachable
+                    ? t("settings.anisette_latency", {
+                      ms: status.latencyMs,
+                    })
+                    : t("settings.anisette_unreachable")}
+                </li>
+              ))}
+            </ul>
+          )}
+        </div>
         <div>
           <Dropdown
             label={t("app.language")}
@@ -108,6 +222,89 @@ export const Settings = ({ showHeading = true }: SettingsProps) => {
             />
           </p>
         </div>
+        <div>
+          <label className="settings-label">
+            {t("settings.two_factor_timeout")}
+            <input
+              type="number"
+              min={10}
+              value={twoFactorTimeout}
+              onChange={(e) => setTwoFactorTimeout(Number(e.target.value))}
+            />
+          </label>
+          <label className="settings-label">
+            {t("settings.prompt_timeout")}
+            <input
+              type="number"
+              min={10}
+              value={promptTimeout}
+              onChange={(e) => setPromptTimeout(Number(e.target.value))}
+            />
+          </label>
+          <p className="settings-hint" style={{ margin: 0 }}>
+            {t("settings.prompt_timeout_hint")}
+          </p>
+          <label className="settings-label">
+            {t("settings.certificate_warning_days")}
+            <input
+              type="number"
+              min={0}
+              value={certificateWarningDays}
+              onChange={(e) =>
+                setCertificateWarningDays(Number(e.target.value))
+              }
+            />
+          </label>
+        </div>
+        <div>
+          <Dropdown
+            label={t("settings.max_certs_policy")}
+            labelId="max-certs-policy"
+            options={maxCertsOptions}
+            value={maxCertsPolicy.mode}
+            onChange={(mode) =>
+              setMaxCertsPolicy(
+                mode === "revokeMachines"
+                  ? { mode, machines: [] }
+                  : ({ mode } as MaxCertsPolicy),
+              )
+            }
+          />
+          {maxCertsPolicy.mode === "revokeMachines" && (
+            <label className="settings-label">
+              {t("settings.max_certs_machines")}
+              <input
+                type="text"
+                value={machinesText}
+                onChange={(e) => setMachinesText(e.target.value)}
+                onBlur={() =>
+                  setMaxCertsPolicy({
+                    mode: "revokeMachines",
+                    machines: machinesText
+                      .split(",")
+                      .map((machine) => machine.trim())
+                      .filter((machine) => machine !== ""),
+                  })
+                }
+              />
+            </label>
+          )}
+          <p className="settings-hint" style={{ margin: 0 }}>
+            {t("settings.max_certs_policy_hint")}
+          </p>
+        </div>
+        {storageBackend && (
+          <p className="settings-hint" style={{ margin: 0 }}>
+            {storageBackend.backend === "keyring"
+              ? t("settings.storage_keyring")
+              : t("settings.storage_encrypted_file", {
+                  path: storageBackend.path,
+                  keySource: t(
+                    `settings.storage_key_${storageBackend.keySource}`,
+                  ),
+                })}
+          </p>
+        )}
         <div className="settings-buttons">
           <button
             className="action-button danger"
@@ -127,10 +324,129 @@ export const Settings = ({ showHeading = true }: SettingsProps) => {
           >
             {t("settings.reset_anisette_state")}
           </button>
+          <button
+            onClick={() =>
+              toast.promise(
+                invoke<{ moved: string[]; failed: unknown[] }>(
+                  "migrate_storage",
+                ),
+                {
+                  loading: t("settings.migrating_storage"),
+                  success: (migration) =>
+                    migration.failed.length > 0
+                      ? t("settings.storage_migrated_with_failures", {
+                          moved: migration.moved.length,
+                          failed: migration.failed.length,
+                        })
+                      : t("settings.storage_migrated", {
+                          count: migration.moved.length,
+                        }),
+                  error: (e) => err(t("settings.failed_migrate_storage"), e),
+                },
+              )
+            }
+          >
+            {t("settings.migrate_storage")}
+          </button>
+          <button onClick={() => setProfileAction("export")}>
+            {t("settings.export_profile")}
+          </button>
+          <button onClick={() => setProfileAction("import")}>
+            {t("settings.import_profile")}
+          </button>
           <button onClick={() => setLogsOpen(true)}>
             {t("settings.view_logs")}
           </button>
         </div>
+        <Modal
+          isOpen={profileAction !== null}
+          close={() => {
+            setProfileAction(null);
+            setProfilePassword("");
+          }}
+        >
+          <h2>
+            {profileAction === "import"
+              ? t("settings.import_profile")
+              : t("settings.export_profile")}
+          </h2>
+          <p className="settings-hint">
+            {profileAction === "import"
+              ? t("settings.import_profile_hint")
+              : t("settings.export_profile_hint")}
+          </p>
+          <input
+            type="password"
+            placeholder={t("settings.profile_password")}
+            value={profilePassword}
+            onChange={(e) => setProfilePassword(e.target.value)}
+          />
+          {profileAction === "import" && (
+            <label className="settings-label">
+              <input
+                type="checkbox"
+                checked={profileOverwrite}
+                onChange={(e) => setProfileOverwrite(e.target.checked)}
+              />
+              {t("settings.profile_overwrite")}
+            </label>
+          )}
+          <div className="settings-buttons">
+            <button
+              className="action-button primary"
+              disabled={!profilePassword}
+              onClick={() => {
+                const action = profileAction;
+                const password = profilePassword;
+                setProfileAction(null);
+                setProfilePassword("");
+                toast.promise(
+                  action === "import"
+                    ? invoke<ProfileSummary>("import_profile", {
+                        password,
+                        overwrite: profileOverwrite,
+                      })
+                    : invoke<ProfileSummary>("export_profile", { password }),
+                  {
+                    loading:
+                      action === "import"
+                        ? t("settings.importing_profile")
+                        : t("settings.exporting_profile"),
+                    success: (summary) => {
+                      if (summary.missingIdentities.length > 0) {
+                        toast.warning(
+                          t("settings.profile_missing_identities", {
+                            accounts: summary.missingIdentities.join(", "),
+                          }),
+                        );
+                      }
+                      return t(
+                        action === "import"
+                          ? "settings.profile_imported"
+                          : "settings.profile_exported",
+                        {
+                          accounts: summary.accounts.length,
+                          entries: summary.entries,
+                        },
+                      );
+                    },
+                    error: (e) =>
+                      err(
+                        action === "import"
+                          ? t("settings.failed_import_profile")
+                          : t("settings.failed_export_profile"),
+                        e,
+                      ),
+                  },
+                );
+              }}
+            >
+              {profileAction === "import"
+                ? t("settings.import_profile")
+                : t("settings.export_profile")}
+            </button>
+          </div>
+        </Modal>
         <Modal isOpen={logsOpen} close={() => setLogsOpen(false)}>
           <div className="log-outer">
             <div className="log-header">